@@ -0,0 +1,211 @@
+/// Approximate nearest neighbor search for large collections of
+/// embeddings. Similarity search with sqlite-vec compares the query
+/// to every embedding which gets slow as the number of embeddings
+/// grows. This clusters the embeddings in memory and only compares
+/// the query to embeddings in the clusters closest to it, trading
+/// some recall for speed.
+use std::env;
+
+use itertools::Itertools;
+use rusqlite::{Connection, Result};
+
+use super::db::{dequantize_int8, VectorFormat};
+
+/// Settings for approximate nearest neighbor search
+#[derive(Clone, Debug)]
+pub struct AnnConfig {
+    /// Use approximate search once there are at least this many
+    /// embeddings. Disabled if not set.
+    pub min_items: Option<usize>,
+    /// Number of clusters to search. Searching more clusters finds
+    /// more of the true nearest neighbors but is slower.
+    pub probes: usize,
+}
+
+impl Default for AnnConfig {
+    fn default() -> Self {
+        Self {
+            min_items: None,
+            probes: 8,
+        }
+    }
+}
+
+impl AnnConfig {
+    /// Read the config from `INDEXER_ANN_MIN_ITEMS` and
+    /// `INDEXER_ANN_PROBES`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            min_items: env::var("INDEXER_ANN_MIN_ITEMS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            probes: env::var("INDEXER_ANN_PROBES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.probes),
+        }
+    }
+}
+
+/// The ID of a note and the embedding of one of its chunks
+pub type NoteVector = (String, Vec<f32>);
+
+// Number of k-means iterations when building the clusters
+const TRAINING_ITERATIONS: usize = 5;
+// Clusters are trained on a sample of at most this many embeddings
+// per cluster to keep building fast
+const TRAINING_SAMPLES_PER_CLUSTER: usize = 64;
+
+/// Embeddings grouped by their nearest cluster (an inverted file
+/// index)
+pub struct AnnIndex {
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<NoteVector>>,
+    probes: usize,
+}
+
+impl AnnIndex {
+    /// Build the index from the embeddings. The number of clusters is
+    /// the square root of the number of embeddings.
+    pub fn build(items: Vec<NoteVector>, probes: usize) -> Self {
+        let nlist = (items.len() as f64).sqrt().ceil().max(1.0) as usize;
+        let sample_step = (items.len() / (nlist * TRAINING_SAMPLES_PER_CLUSTER)).max(1);
+        let sample: Vec<&Vec<f32>> = items.iter().step_by(sample_step).map(|(_, v)| v).collect();
+
+        // Start from evenly spaced embeddings so building is
+        // deterministic
+        let init_step = (sample.len() / nlist).max(1);
+        let mut centroids: Vec<Vec<f32>> = sample
+            .iter()
+            .step_by(init_step)
+            .take(nlist)
+            .map(|v| v.to_vec())
+            .collect();
+
+        for _ in 0..TRAINING_ITERATIONS {
+            let dims = centroids.first().map(|c| c.len()).unwrap_or_default();
+            let mut sums = vec![vec![0.0f32; dims]; centroids.len()];
+            let mut counts = vec![0usize; centroids.len()];
+            for v in sample.iter() {
+                let i = nearest(&centroids, v);
+                counts[i] += 1;
+                for (s, x) in sums[i].iter_mut().zip(v.iter()) {
+                    *s += x;
+                }
+            }
+            for (i, centroid) in centroids.iter_mut().enumerate() {
+                // Empty clusters keep their previous centroid
+                if counts[i] > 0 {
+                    *centroid = sums[i].iter().map(|s| s / counts[i] as f32).collect();
+                }
+            }
+        }
+
+        let mut lists = vec![Vec::new(); centroids.len()];
+        for (id, v) in items {
+            lists[nearest(&centroids, &v)].push((id, v));
+        }
+        Self {
+            centroids,
+            lists,
+            probes,
+        }
+    }
+
+    /// Number of embeddings in the index
+    pub fn len(&self) -> usize {
+        self.lists.iter().map(|l| l.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns up to `k` of the approximate nearest neighbors as the
+    /// ID and L2 distance ordered by ascending distance, the same as
+    /// sqlite-vec. Notes with more than one embedding are returned
+    /// once with the distance to the closest one.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> {
+        let mut clusters: Vec<(usize, f32)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, distance(c, query)))
+            .collect();
+        clusters.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut hits: Vec<(String, f32)> = clusters
+            .iter()
+            .take(self.probes.max(1))
+            .flat_map(|(i, _)| self.lists[*i].iter())
+            .map(|(id, v)| (id.clone(), distance(v, query)))
+            .collect();
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        hits.into_iter()
+            .unique_by(|(id, _)| id.clone())
+            .take(k)
+            .collect()
+    }
+}
+
+/// Load the embeddings to build the index from if approximate search
+/// is enabled and there are enough embeddings to need it
+pub fn ann_vectors(db: &Connection, config: &AnnConfig) -> Result<Option<Vec<NoteVector>>> {
+    let Some(min_items) = config.min_items else {
+        return Ok(None);
+    };
+    let count: usize = db.query_row("SELECT COUNT(*) FROM vec_items", [], |r| r.get(0))?;
+    if count < min_items {
+        return Ok(None);
+    }
+    load_vectors(db).map(Some)
+}
+
+/// All of the embeddings in the db as floats. Quantized embeddings
+/// are converted back to floats, using the int8 copy of binary
+/// embeddings.
+pub fn load_vectors(db: &Connection) -> Result<Vec<NoteVector>> {
+    let format = VectorFormat::stored(db)?;
+    let table = if format == VectorFormat::Binary {
+        "vec_items_int8"
+    } else {
+        "vec_items"
+    };
+    db.prepare(&format!(
+        "SELECT note_meta_id, embedding FROM {} ORDER BY note_meta_id",
+        table
+    ))?
+    .query_map([], |r| {
+        let bytes: Vec<u8> = r.get(1)?;
+        let vector = if format == VectorFormat::Float {
+            // Stored as little endian `f32` bytes
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        } else {
+            dequantize_int8(&bytes)
+        };
+        Ok((r.get(0)?, vector))
+    })?
+    .collect()
+}
+
+fn nearest(centroids: &[Vec<f32>], v: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, distance(c, v)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+        .unwrap_or_default()
+}
+
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt()
+}
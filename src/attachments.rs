@@ -0,0 +1,209 @@
+/// PDFs linked from notes, e.g. papers saved in an `attachments/`
+/// folder, are indexed as documents of their own using the text in
+/// the PDF so they can be searched with `type:attachment`. Each one
+/// points back to the note that links to it.
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::panic;
+use std::path::Path;
+
+use orgize::ast::Link;
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, Result};
+use tantivy::{doc, IndexWriter, Term};
+
+use super::indexing::word_count;
+use super::schema::note_schema;
+use super::tags::add_tags;
+
+pub const ATTACHMENT_TYPE: &str = "attachment";
+
+/// A PDF linked from a note
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub id: String,
+    /// ID of the note that links to the PDF
+    pub note_id: String,
+    /// Path to the PDF
+    pub path: String,
+    /// File name of the PDF
+    pub title: String,
+    /// Text extracted from the PDF
+    pub body: String,
+}
+
+/// Returns the unique paths of PDFs linked from the document as they
+/// are written in the link e.g. `[[file:attachments/paper.pdf]]`.
+/// Links to web pages are skipped.
+///
+/// ```rust
+/// use indexer::attachments::pdf_links;
+/// use orgize::Org;
+///
+/// let org = Org::parse("See [[file:attachments/paper.pdf][the paper]], [[file:attachments/paper.pdf::3][page 3]], [[./slides.PDF]], [[https://example.com/a.pdf]], and [[file:notes.org]].");
+/// assert_eq!(pdf_links(&org), vec!["./slides.PDF", "attachments/paper.pdf"]);
+/// ```
+pub fn pdf_links(org: &Org) -> Vec<String> {
+    let mut links: Vec<String> = org
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Link::cast)
+        .filter_map(|l| {
+            let path = l.path().to_string();
+            let path = path.strip_prefix("file:").unwrap_or(&path);
+            // Links to a page of the PDF point to the same file
+            let path = path.split("::").next()?.trim();
+            let is_pdf = Path::new(path)
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+            (is_pdf && !path.contains("://")).then(|| path.to_string())
+        })
+        .collect();
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Extract the text of each PDF linked from the note. Relative links
+/// are relative to the note's directory. PDFs that are missing or
+/// can't be read are skipped.
+pub fn attachments(note_id: &str, note_path: &Path, links: &[String]) -> Vec<Attachment> {
+    let dir = note_path.parent().unwrap_or(Path::new(""));
+    links
+        .iter()
+        .filter_map(|link| {
+            let path = dir.join(link);
+            let body = pdf_text(&path)?;
+            let path = path.display().to_string();
+            Some(Attachment {
+                id: attachment_id(note_id, &path),
+                note_id: note_id.to_string(),
+                title: Path::new(link)
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                path,
+                body,
+            })
+        })
+        .collect()
+}
+
+// The same PDF can be linked from more than one note so the ID is
+// made from the note and the path
+fn attachment_id(note_id: &str, path: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    (note_id, path).hash(&mut hasher);
+    format!("attachment-{}", hasher.finish())
+}
+
+// Text of the PDF or `None` if it can't be read
+fn pdf_text(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        tracing::warn!("Attachment {} not found", path.display());
+        return None;
+    }
+    // The PDF parser panics on some malformed files
+    match panic::catch_unwind(|| pdf_extract::extract_text(path)) {
+        Ok(Ok(text)) => Some(text.trim().to_string()),
+        Ok(Err(err)) => {
+            tracing::warn!("Reading attachment {} failed: {}", path.display(), err);
+            None
+        }
+        Err(_) => {
+            tracing::warn!("Reading attachment {} failed", path.display());
+            None
+        }
+    }
+}
+
+/// Replace the attachments of the note. Attachments are stored in
+/// `note_meta` so they show up in search results, with the note's
+/// tags, and the note they are linked from is stored in the
+/// `attachment` table. Returns the IDs of attachments that were
+/// removed so they can be removed from the search index.
+pub fn index_attachments(
+    db: &mut Connection,
+    file_name: &str,
+    collection: &str,
+    tags: Option<&str>,
+    note_id: &str,
+    attachments: &[Attachment],
+) -> Result<Vec<String>> {
+    let tx = db.transaction()?;
+    let stale: Vec<String> = {
+        let ids: HashSet<&str> = attachments.iter().map(|a| a.id.as_str()).collect();
+        let previous: Vec<String> = tx
+            .prepare("SELECT id FROM attachment WHERE note_meta_id = ?1")?
+            .query_map([note_id], |r| r.get(0))?
+            .collect::<Result<_>>()?;
+        previous
+            .into_iter()
+            .filter(|id| !ids.contains(id.as_str()))
+            .collect()
+    };
+    tx.execute(
+        "DELETE FROM note_meta WHERE id IN (SELECT id FROM attachment WHERE note_meta_id = ?1)",
+        [note_id],
+    )?;
+    tx.execute("DELETE FROM attachment WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut meta_stmt = tx.prepare(
+            "REPLACE INTO note_meta(id, type, file_name, title, tags, body, collection, word_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut attachment_stmt =
+            tx.prepare("REPLACE INTO attachment(id, note_meta_id, path) VALUES (?1, ?2, ?3)")?;
+        for a in attachments {
+            meta_stmt.execute(rusqlite::params![
+                a.id,
+                ATTACHMENT_TYPE,
+                file_name,
+                a.title,
+                tags,
+                a.body,
+                collection,
+                word_count(&a.body),
+            ])?;
+            attachment_stmt.execute([&a.id, note_id, &a.path])?;
+        }
+    }
+    tx.commit()?;
+    Ok(stale)
+}
+
+/// Add the attachments to the full text index and remove the `stale`
+/// ones. Changes are committed by the caller.
+pub fn index_attachments_full_text(
+    index_writer: &mut IndexWriter,
+    file_name: &str,
+    collection: &str,
+    tags: Option<&str>,
+    attachments: &[Attachment],
+    stale: &[String],
+) -> tantivy::Result<()> {
+    let schema = note_schema();
+    let id = schema.get_field("id")?;
+    for stale_id in stale {
+        index_writer.delete_term(Term::from_field_text(id, stale_id));
+    }
+    for a in attachments {
+        // Delete the document first to get upsert behavior
+        index_writer.delete_term(Term::from_field_text(id, &a.id));
+        let mut doc = doc!(
+            id => a.id.as_str(),
+            schema.get_field("type")? => ATTACHMENT_TYPE,
+            schema.get_field("title")? => a.title.as_str(),
+            schema.get_field("body")? => a.body.as_str(),
+            schema.get_field("file_name")? => file_name,
+            schema.get_field("collection")? => collection,
+        );
+        if let Some(tags) = tags {
+            add_tags(&mut doc, schema.get_field("tags")?, tags);
+        }
+        index_writer.add_document(doc)?;
+    }
+    Ok(())
+}
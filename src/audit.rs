@@ -0,0 +1,54 @@
+/// Append-only record of changes made to notes through the API so
+/// it's possible to trace what changed, when, and which token was
+/// used to change it
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+/// A change made through the API
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Name of the token used to make the change, if any
+    pub actor: Option<String>,
+    /// Kind of change e.g. `task_status`
+    pub action: String,
+    pub summary: String,
+    pub created_at: String,
+}
+
+/// Record a change. Entries can't be updated or deleted once they
+/// are recorded.
+pub fn record_audit(
+    db: &Connection,
+    actor: Option<&str>,
+    action: &str,
+    summary: &str,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO audit_log(actor, action, summary) VALUES (?1, ?2, ?3)",
+        rusqlite::params![actor, action, summary],
+    )?;
+    Ok(())
+}
+
+/// The most recent changes, newest first
+pub fn audit_log(db: &Connection, limit: usize) -> Result<Vec<AuditEntry>> {
+    db.prepare(
+        r"
+          SELECT id, actor, action, summary, created_at
+          FROM audit_log
+          ORDER BY id DESC
+          LIMIT ?1
+        ",
+    )?
+    .query_map([limit as i64], |r| {
+        Ok(AuditEntry {
+            id: r.get(0)?,
+            actor: r.get(1)?,
+            action: r.get(2)?,
+            summary: r.get(3)?,
+            created_at: r.get(4)?,
+        })
+    })?
+    .collect()
+}
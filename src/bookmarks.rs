@@ -0,0 +1,107 @@
+/// Save web pages as notes so they are archived and searchable even
+/// if the page changes or disappears
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use super::indexing::{escape_org, new_org_id};
+
+/// How long to wait for the page before giving up
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest page that is saved. Anything bigger is probably not an
+/// article and would bloat the notes repo.
+const MAX_PAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// A web page converted to a note
+pub struct Bookmark {
+    pub id: String,
+    pub title: String,
+    pub url: String,
+    pub tags: Vec<String>,
+    /// Page content converted to markdown
+    pub content: String,
+}
+
+impl Bookmark {
+    /// Create a bookmark from the HTML of the page. The title is
+    /// taken from the `<title>` element, falling back to the URL.
+    pub fn from_html(url: &str, html: &str, tags: Vec<String>) -> Self {
+        let title = html_title(html).unwrap_or_else(|| url.to_string());
+        Self {
+            id: new_org_id(),
+            title,
+            url: url.to_string(),
+            tags,
+            content: html2md::parse_html(html).trim().to_string(),
+        }
+    }
+
+    /// File name of the note using the bookmark's ID so it's unique
+    pub fn file_name(&self) -> String {
+        format!("bookmark-{}.org", self.id)
+    }
+
+    /// Render the bookmark as an org-mode note. The page content is
+    /// kept as markdown in a source block.
+    ///
+    /// ```rust
+    /// use indexer::bookmarks::Bookmark;
+    ///
+    /// let html = "<html><head><title>Example</title></head><body><h1>Hello</h1></body></html>";
+    /// let mut bookmark = Bookmark::from_html("https://example.com", html, vec!["reading".to_string()]);
+    /// bookmark.id = "abc".to_string();
+    /// let org = bookmark.to_org();
+    /// assert!(org.starts_with(":PROPERTIES:\n:ID: abc\n:ROAM_REFS: https://example.com\n:END:\n"));
+    /// assert!(org.contains("#+TITLE: Example\n#+FILETAGS: bookmark reading\n"));
+    /// assert!(org.ends_with("Hello\n==========\n#+end_src\n"));
+    /// ```
+    pub fn to_org(&self) -> String {
+        let mut tags = vec!["bookmark".to_string()];
+        tags.extend(self.tags.iter().filter(|t| *t != "bookmark").cloned());
+
+        let content = escape_org(&self.content);
+
+        format!(
+            ":PROPERTIES:\n:ID: {}\n:ROAM_REFS: {}\n:END:\n#+TITLE: {}\n#+FILETAGS: {}\n\n#+begin_src markdown\n{}\n#+end_src\n",
+            self.id,
+            self.url,
+            self.title,
+            tags.join(" "),
+            content
+        )
+    }
+}
+
+/// Fetch the page and convert it to a bookmark
+pub async fn fetch_bookmark(url: &str, tags: Vec<String>) -> Result<Bookmark> {
+    let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+    let mut resp = client.get(url).send().await?.error_for_status()?;
+    // Read the body in chunks so a huge page is rejected without
+    // holding all of it in memory
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > MAX_PAGE_BYTES {
+            return Err(anyhow!("Page is larger than {} bytes", MAX_PAGE_BYTES));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let html = String::from_utf8_lossy(&body);
+    Ok(Bookmark::from_html(url, &html, tags))
+}
+
+fn html_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title>")?;
+    let title = html[start..end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
@@ -0,0 +1,84 @@
+/// Append entries to the capture file in the notes directory. The
+/// capture file is an inbox that isn't indexed until entries are
+/// refiled into notes.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::{Local, NaiveDateTime};
+
+use super::indexing::escape_org;
+
+pub const CAPTURE_FILE_NAME: &str = "capture.org";
+
+/// An entry in the capture file
+pub struct CaptureEntry {
+    pub title: String,
+    pub url: Option<String>,
+    pub body: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl CaptureEntry {
+    /// Render the entry as an org headline. The body is quoted since
+    /// it's usually a selection from somewhere else.
+    ///
+    /// ```rust
+    /// use indexer::capture::CaptureEntry;
+    ///
+    /// let entry = CaptureEntry {
+    ///     title: "Example".to_string(),
+    ///     url: Some("https://example.com".to_string()),
+    ///     body: Some("* Some text".to_string()),
+    ///     tags: vec!["reading".to_string()],
+    /// };
+    /// let org = entry.to_org("[2025-01-06 Mon 10:00]");
+    /// assert_eq!(
+    ///     org,
+    ///     "* [[https://example.com][Example]] :reading:\n:PROPERTIES:\n:CREATED: [2025-01-06 Mon 10:00]\n:END:\n#+begin_quote\n,* Some text\n#+end_quote\n"
+    /// );
+    /// ```
+    pub fn to_org(&self, created: &str) -> String {
+        let mut headline = match &self.url {
+            Some(url) => format!("* [[{}][{}]]", url, self.title),
+            None => format!("* {}", self.title),
+        };
+        let tags: Vec<&str> = self
+            .tags
+            .iter()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if !tags.is_empty() {
+            headline.push_str(&format!(" :{}:", tags.join(":")));
+        }
+
+        let mut output = format!("{}\n:PROPERTIES:\n:CREATED: {}\n:END:\n", headline, created);
+        if let Some(body) = self.body.as_ref().filter(|b| !b.trim().is_empty()) {
+            output.push_str(&format!(
+                "#+begin_quote\n{}\n#+end_quote\n",
+                escape_org(body)
+            ));
+        }
+        output
+    }
+}
+
+/// Append the entry to the end of the capture file, creating it if
+/// it doesn't exist
+pub fn append_capture(notes_path: &str, entry: &CaptureEntry) -> io::Result<()> {
+    append_capture_at(notes_path, entry, Local::now().naive_local())
+}
+
+/// Same as `append_capture` but with the time the entry was created
+/// e.g. when it was captured offline
+pub fn append_capture_at(
+    notes_path: &str,
+    entry: &CaptureEntry,
+    created: NaiveDateTime,
+) -> io::Result<()> {
+    let path = Path::new(notes_path).join(CAPTURE_FILE_NAME);
+    let created = created.format("[%Y-%m-%d %a %H:%M]").to_string();
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(entry.to_org(&created).as_bytes())
+}
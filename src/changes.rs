@@ -0,0 +1,163 @@
+/// Summaries of how notes changed each time they are indexed e.g. to
+/// review what changed in notes this week
+use chrono::NaiveDate;
+use orgize::ast::Headline;
+use orgize::rowan::ast::AstNode;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+use serde_json::json;
+use similar::{ChangeTag, TextDiff};
+
+use super::indexing::{parse_org, TaskKeywords};
+
+/// Words and headings added or removed from a note
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ChangeSummary {
+    pub words_added: usize,
+    pub words_removed: usize,
+    pub headings_added: Vec<String>,
+    pub headings_removed: Vec<String>,
+}
+
+/// Compare the previous content of a note to the current content
+///
+/// ```rust
+/// use indexer::changes::summarize_change;
+/// use indexer::indexing::TaskKeywords;
+///
+/// let keywords = TaskKeywords::default();
+/// let summary = summarize_change(
+///     "* Ideas\nOne two\n",
+///     "* Ideas\nOne three four\n* Questions\n",
+///     &keywords,
+/// );
+/// assert_eq!(summary.words_added, 3);
+/// assert_eq!(summary.words_removed, 1);
+/// assert_eq!(summary.headings_added, vec!["Questions"]);
+/// assert!(summary.headings_removed.is_empty());
+/// ```
+pub fn summarize_change(previous: &str, current: &str, keywords: &TaskKeywords) -> ChangeSummary {
+    let mut summary = ChangeSummary::default();
+    for change in TextDiff::from_words(previous, current).iter_all_changes() {
+        // Whitespace and markup like headline stars aren't words
+        if !change.value().chars().any(char::is_alphanumeric) {
+            continue;
+        }
+        match change.tag() {
+            ChangeTag::Insert => summary.words_added += 1,
+            ChangeTag::Delete => summary.words_removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    let previous_headings = headings(previous, keywords);
+    let current_headings = headings(current, keywords);
+    summary.headings_added = current_headings
+        .iter()
+        .filter(|h| !previous_headings.contains(h))
+        .cloned()
+        .collect();
+    summary.headings_removed = previous_headings
+        .into_iter()
+        .filter(|h| !current_headings.contains(h))
+        .collect();
+    summary
+}
+
+fn headings(content: &str, keywords: &TaskKeywords) -> Vec<String> {
+    parse_org(content, keywords)
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Headline::cast)
+        .map(|h| h.title_raw().trim().to_string())
+        .collect()
+}
+
+/// The body of the note the last time it was indexed
+pub fn previous_body(db: &Connection, note_id: &str) -> Result<Option<String>> {
+    db.query_row(
+        "SELECT body FROM note_meta WHERE id = ?1 AND type = 'note'",
+        [note_id],
+        |r| r.get(0),
+    )
+    .optional()
+}
+
+/// Record how the note changed since it was last indexed. Nothing is
+/// recorded if it didn't change. New notes are compared to an empty
+/// note.
+pub fn record_note_change(
+    db: &Connection,
+    note_id: &str,
+    file_name: &str,
+    title: &str,
+    previous: Option<&str>,
+    current: &str,
+    keywords: &TaskKeywords,
+) -> Result<()> {
+    if previous == Some(current) {
+        return Ok(());
+    }
+    let summary = summarize_change(previous.unwrap_or_default(), current, keywords);
+    db.execute(
+        r"
+          INSERT INTO index_change(note_meta_id, file_name, title, words_added, words_removed, headings_added, headings_removed)
+          VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ",
+        rusqlite::params![
+            note_id,
+            file_name,
+            title,
+            summary.words_added,
+            summary.words_removed,
+            json!(summary.headings_added).to_string(),
+            json!(summary.headings_removed).to_string(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// A change to a note when it was indexed
+#[derive(Debug, Serialize)]
+pub struct NoteChange {
+    pub id: i64,
+    pub note_id: String,
+    pub file_name: String,
+    pub title: String,
+    #[serde(flatten)]
+    pub summary: ChangeSummary,
+    pub created_at: String,
+}
+
+/// Changes to notes indexed on or after `since`, most recent first
+pub fn note_changes(db: &Connection, since: Option<NaiveDate>) -> Result<Vec<NoteChange>> {
+    let since = since.map(|d| d.format("%Y-%m-%d").to_string());
+    db.prepare(
+        r"
+          SELECT id, note_meta_id, file_name, title, words_added, words_removed, headings_added, headings_removed, created_at
+          FROM index_change
+          WHERE ?1 IS NULL OR created_at >= ?1
+          ORDER BY created_at DESC, id DESC
+        ",
+    )?
+    .query_map([since], |r| {
+        let headings = |i: usize| -> Result<Vec<String>> {
+            let value: String = r.get(i)?;
+            Ok(serde_json::from_str(&value).unwrap_or_default())
+        };
+        Ok(NoteChange {
+            id: r.get(0)?,
+            note_id: r.get(1)?,
+            file_name: r.get(2)?,
+            title: r.get(3)?,
+            summary: ChangeSummary {
+                words_added: r.get(4)?,
+                words_removed: r.get(5)?,
+                headings_added: headings(6)?,
+                headings_removed: headings(7)?,
+            },
+            created_at: r.get(8)?,
+        })
+    })?
+    .collect()
+}
@@ -0,0 +1,224 @@
+/// Time tracking using org `CLOCK:` lines e.g. from `org-clock-in`
+/// and reports of the time clocked
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use chrono::{Days, NaiveDate, NaiveDateTime, NaiveTime};
+use orgize::ast::{Clock, Headline, Timestamp};
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, Result};
+use serde::{Deserialize, Serialize};
+
+use super::indexing::task_id;
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// A closed clock entry. Clocks that are still running are skipped
+/// until they are clocked out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockEntry {
+    /// ID of the headline the clock is under, if any
+    pub headline_id: Option<String>,
+    pub headline: Option<String>,
+    /// Tags of the headline and the note
+    pub tags: Vec<String>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl ClockEntry {
+    pub fn minutes(&self) -> i64 {
+        (self.end - self.start).num_minutes()
+    }
+}
+
+fn timestamp_start(ts: &Timestamp) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(
+        ts.year_start()?.parse().ok()?,
+        ts.month_start()?.parse().ok()?,
+        ts.day_start()?.parse().ok()?,
+    )?;
+    let time = NaiveTime::from_hms_opt(
+        ts.hour_start()?.parse().ok()?,
+        ts.minute_start()?.parse().ok()?,
+        0,
+    )?;
+    Some(date.and_time(time))
+}
+
+fn timestamp_end(ts: &Timestamp) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(
+        ts.year_end()?.parse().ok()?,
+        ts.month_end()?.parse().ok()?,
+        ts.day_end()?.parse().ok()?,
+    )?;
+    let time = NaiveTime::from_hms_opt(
+        ts.hour_end()?.parse().ok()?,
+        ts.minute_end()?.parse().ok()?,
+        0,
+    )?;
+    Some(date.and_time(time))
+}
+
+/// Returns all closed clock entries in the document including ones
+/// in `:LOGBOOK:` drawers. Each entry is attributed to the closest
+/// headline it's under.
+///
+/// ```rust
+/// use indexer::clock::clock_entries;
+/// use orgize::Org;
+///
+/// let org = Org::parse("* Write docs :work:\n:LOGBOOK:\nCLOCK: [2025-01-06 Mon 09:00]--[2025-01-06 Mon 10:30] =>  1:30\nCLOCK: [2025-01-07 Tue 09:00]\n:END:\n");
+/// let entries = clock_entries(&org, &[]);
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(entries[0].headline.as_deref(), Some("Write docs"));
+/// assert_eq!(entries[0].tags, vec!["work"]);
+/// assert_eq!(entries[0].minutes(), 90);
+/// ```
+pub fn clock_entries(org: &Org, file_tags: &[String]) -> Vec<ClockEntry> {
+    org.document()
+        .syntax()
+        .descendants()
+        .filter_map(Clock::cast)
+        .filter(|c| c.is_closed())
+        .filter_map(|c| {
+            let ts = c.value()?;
+            let start = timestamp_start(&ts)?;
+            let end = timestamp_end(&ts)?;
+            let headline = c.syntax().ancestors().find_map(Headline::cast);
+            let mut tags: Vec<String> = headline
+                .iter()
+                .flat_map(|h| h.tags().map(|t| t.to_string()))
+                .chain(file_tags.iter().cloned())
+                .collect();
+            tags.sort();
+            tags.dedup();
+            Some(ClockEntry {
+                headline_id: headline.as_ref().map(task_id),
+                headline: headline.as_ref().map(|h| h.title_raw().trim().to_string()),
+                tags,
+                start,
+                end,
+            })
+        })
+        .collect()
+}
+
+/// Replace the clock entries of the note
+pub fn index_clock_entries(
+    db: &mut Connection,
+    note_id: &str,
+    entries: &[ClockEntry],
+) -> Result<()> {
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM clock_entry WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO clock_entry(note_meta_id, headline_id, headline, tags, start, end, minutes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for entry in entries {
+            stmt.execute(rusqlite::params![
+                note_id,
+                entry.headline_id,
+                entry.headline,
+                entry.tags.join(","),
+                entry.start.format(DATETIME_FORMAT).to_string(),
+                entry.end.format(DATETIME_FORMAT).to_string(),
+                entry.minutes(),
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// How to group clocked time in a report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    #[default]
+    File,
+    Tag,
+    Headline,
+}
+
+/// Total time clocked for a group
+#[derive(Debug, Serialize)]
+pub struct TimeReportRow {
+    /// File name, tag, or headline. Time not clocked under a tag or
+    /// headline is grouped under `null`.
+    key: Option<String>,
+    minutes: i64,
+}
+
+/// Total clocked time for clocks started between `from` and `to`
+/// (inclusive) sorted by the most time first. Time for entries with
+/// many tags is counted towards each tag.
+pub fn time_report(
+    db: &Connection,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    group_by: GroupBy,
+) -> Result<Vec<TimeReportRow>> {
+    let from = from.map(|d| d.format("%Y-%m-%d").to_string());
+    // Compare against the start of the next day so the whole day is
+    // included
+    let to = to
+        .and_then(|d| d.checked_add_days(Days::new(1)))
+        .map(|d| d.format("%Y-%m-%d").to_string());
+
+    let rows = db
+        .prepare(
+            r"
+              SELECT
+                note_meta.file_name,
+                clock_entry.tags,
+                clock_entry.headline,
+                clock_entry.minutes
+              FROM clock_entry
+              JOIN note_meta ON note_meta.id = clock_entry.note_meta_id
+              WHERE (?1 IS NULL OR clock_entry.start >= ?1)
+              AND (?2 IS NULL OR clock_entry.start < ?2)
+            ",
+        )?
+        .query_map([from, to], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, Option<String>>(1)?,
+                r.get::<_, Option<String>>(2)?,
+                r.get::<_, i64>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut totals: BTreeMap<Option<String>, i64> = BTreeMap::new();
+    for (file_name, tags, headline, minutes) in rows {
+        let keys: Vec<Option<String>> = match group_by {
+            GroupBy::File => vec![Some(file_name)],
+            GroupBy::Headline => vec![headline],
+            GroupBy::Tag => {
+                let tags: Vec<Option<String>> = tags
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|t| !t.is_empty())
+                    .map(|t| Some(t.to_string()))
+                    .collect();
+                if tags.is_empty() {
+                    vec![None]
+                } else {
+                    tags
+                }
+            }
+        };
+        for key in keys {
+            *totals.entry(key).or_default() += minutes;
+        }
+    }
+
+    let mut report: Vec<TimeReportRow> = totals
+        .into_iter()
+        .map(|(key, minutes)| TimeReportRow { key, minutes })
+        .collect();
+    report.sort_by_key(|r| Reverse(r.minutes));
+    Ok(report)
+}
@@ -0,0 +1,104 @@
+/// Notes in different files that share an org-id. Only one note can
+/// be stored per ID so the file that sorts first by path keeps it and
+/// the others fail to index until their ID is changed.
+use std::fs;
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+
+use super::indexing::{parse_org, TaskKeywords};
+use super::source::read_note;
+
+/// A note that wasn't indexed because another file has its ID
+#[derive(Debug, Serialize)]
+pub struct IdConflict {
+    pub id: String,
+    /// The file that wasn't indexed
+    pub file_name: String,
+    /// The file that has the ID in the index
+    pub kept_file_name: String,
+    pub updated_at: String,
+}
+
+/// The other file that is indexed with the note's ID, if any. Files
+/// that were deleted or no longer have the ID aren't a conflict.
+pub fn conflicting_file(
+    db: &Connection,
+    id: &str,
+    file_name: &str,
+    keywords: &TaskKeywords,
+    age_identity_path: Option<&str>,
+) -> Result<Option<String>> {
+    let indexed: Option<String> = db
+        .query_row(
+            "SELECT file_name FROM note_meta WHERE id = ?1 AND type = 'note' AND file_name != ?2",
+            [id, file_name],
+            |r| r.get(0),
+        )
+        .optional()?;
+    Ok(indexed.filter(|other| {
+        let path = Path::new(other);
+        if fs::metadata(path).is_err() {
+            return false;
+        }
+        // Assume it still has the ID if it can't be read e.g. an
+        // encrypted note without the key
+        read_note(path, age_identity_path).map_or(true, |content| {
+            parse_org(&content, keywords)
+                .document()
+                .properties()
+                .and_then(|p| p.get("ID"))
+                .is_some_and(|other_id| other_id.trim() == id)
+        })
+    }))
+}
+
+/// Record that the note in `file_name` wasn't indexed because
+/// `kept_file_name` has the same ID
+pub fn record_id_conflict(
+    db: &Connection,
+    id: &str,
+    file_name: &str,
+    kept_file_name: &str,
+) -> Result<()> {
+    db.execute(
+        r"
+          INSERT INTO id_conflict(id, file_name, kept_file_name)
+          VALUES (?1, ?2, ?3)
+          ON CONFLICT(file_name) DO UPDATE SET
+            id = excluded.id,
+            kept_file_name = excluded.kept_file_name,
+            updated_at = CURRENT_TIMESTAMP
+        ",
+        [id, file_name, kept_file_name],
+    )?;
+    Ok(())
+}
+
+/// Forget the conflict once the note indexes successfully
+pub fn clear_id_conflict(db: &Connection, file_name: &str) -> Result<()> {
+    db.execute("DELETE FROM id_conflict WHERE file_name = ?1", [file_name])?;
+    Ok(())
+}
+
+/// All notes that weren't indexed because of a duplicate ID, grouped
+/// by ID
+pub fn id_conflicts(db: &Connection) -> Result<Vec<IdConflict>> {
+    db.prepare(
+        r"
+          SELECT id, file_name, kept_file_name, updated_at
+          FROM id_conflict
+          ORDER BY id, file_name
+        ",
+    )?
+    .query_map([], |r| {
+        Ok(IdConflict {
+            id: r.get(0)?,
+            file_name: r.get(1)?,
+            kept_file_name: r.get(2)?,
+            updated_at: r.get(3)?,
+        })
+    })?
+    .collect()
+}
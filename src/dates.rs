@@ -0,0 +1,138 @@
+/// Dates mentioned anywhere in a note e.g. inline `<2025-06-12 Thu>`
+/// timestamps, not just scheduled and deadline dates, so notes can be
+/// found by what's happening on a date e.g. `mentions_date:2025-06-12`
+use chrono::{Days, NaiveDate};
+use orgize::ast::{Clock, Headline, Timestamp};
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+
+use super::recurrence::timestamp_date;
+
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+// Ranges longer than this only mention the first days of the range
+// so a range spanning years doesn't mention every day in it
+const MAX_RANGE_DAYS: u64 = 31;
+
+/// A date mentioned in a note
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteDate {
+    pub date: NaiveDate,
+    /// Headline the timestamp is under, if any
+    pub headline: Option<String>,
+    /// Inactive timestamps e.g. `[2025-06-12 Thu]` usually record
+    /// when something happened rather than something planned
+    pub active: bool,
+}
+
+/// Returns every date mentioned by timestamps in the document. Each
+/// day of a range is mentioned. Clock entries are skipped since they
+/// are a log of time spent rather than a mention of the date.
+///
+/// ```rust
+/// use indexer::dates::note_dates;
+/// use orgize::Org;
+///
+/// let org = Org::parse("* Trip\nFrom <2025-06-12 Thu>--<2025-06-13 Fri>\n* Notes\nWritten [2025-06-01 Sun]\n");
+/// let dates: Vec<String> = note_dates(&org).iter().map(|d| d.date.to_string()).collect();
+/// assert_eq!(dates, vec!["2025-06-12", "2025-06-13", "2025-06-01"]);
+/// ```
+pub fn note_dates(org: &Org) -> Vec<NoteDate> {
+    let mut dates: Vec<NoteDate> = Vec::new();
+    let timestamps = org
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Timestamp::cast)
+        .filter(|ts| !ts.syntax().ancestors().any(|a| Clock::can_cast(a.kind())));
+    for ts in timestamps {
+        let Some(start) = timestamp_date(&ts) else {
+            continue;
+        };
+        let end = timestamp_end_date(&ts).unwrap_or(start).max(start);
+        let headline = ts
+            .syntax()
+            .ancestors()
+            .find_map(Headline::cast)
+            .map(|h| h.title_raw().trim().to_string());
+        let days = (end - start).num_days().min(MAX_RANGE_DAYS as i64 - 1) as u64;
+        for day in 0..=days {
+            let Some(date) = start.checked_add_days(Days::new(day)) else {
+                break;
+            };
+            let note_date = NoteDate {
+                date,
+                headline: headline.clone(),
+                active: ts.is_active(),
+            };
+            if !dates.contains(&note_date) {
+                dates.push(note_date);
+            }
+        }
+    }
+    dates
+}
+
+fn timestamp_end_date(ts: &Timestamp) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(
+        ts.year_end()?.parse().ok()?,
+        ts.month_end()?.parse().ok()?,
+        ts.day_end()?.parse().ok()?,
+    )
+}
+
+/// Replace the dates mentioned in the note
+pub fn index_note_dates(db: &mut Connection, note_id: &str, dates: &[NoteDate]) -> Result<()> {
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM note_date WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO note_date(note_meta_id, date, headline, active) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for d in dates {
+            stmt.execute(rusqlite::params![
+                note_id,
+                d.date.format(DATE_FORMAT).to_string(),
+                d.headline,
+                d.active,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// A note that mentions the date
+#[derive(Debug, Serialize)]
+pub struct DateMention {
+    pub id: String,
+    pub title: String,
+    pub file_name: String,
+    pub headline: Option<String>,
+    pub active: bool,
+}
+
+/// Notes that mention the date ordered by title, active timestamps
+/// first
+pub fn date_mentions(db: &Connection, date: NaiveDate) -> Result<Vec<DateMention>> {
+    db.prepare(
+        r"
+          SELECT note_meta.id, note_meta.title, note_meta.file_name, note_date.headline, note_date.active
+          FROM note_date
+          JOIN note_meta ON note_meta.id = note_date.note_meta_id
+          WHERE note_date.date = ?1
+          ORDER BY note_date.active DESC, note_meta.title, note_date.rowid
+        ",
+    )?
+    .query_map([date.format(DATE_FORMAT).to_string()], |r| {
+        Ok(DateMention {
+            id: r.get(0)?,
+            title: r.get(1)?,
+            file_name: r.get(2)?,
+            headline: r.get(3)?,
+            active: r.get(4)?,
+        })
+    })?
+    .collect()
+}
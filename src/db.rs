@@ -1,7 +1,13 @@
-use rusqlite::{ffi::sqlite3_auto_extension, Connection, Result};
+use std::env;
+use std::path::Path;
+
+use rusqlite::{ffi::sqlite3_auto_extension, Connection, OptionalExtension, Result};
 use sqlite_vec::sqlite3_vec_init;
 
-pub fn migrate_db(db: &Connection) -> Result<()> {
+/// Create the tables and migrate databases created by older
+/// versions. Returns true if a migration removed the embeddings and
+/// every note needs to be embedded again.
+pub fn migrate_db(db: &Connection) -> Result<bool> {
     // Create a metadata table that has a foreign key to the
     // embeddings virtual table. This will be used to coordinate
     // upserts and hydrating the notes
@@ -18,18 +24,30 @@ pub fn migrate_db(db: &Connection) -> Result<()> {
         [],
     )?;
 
-    // Create vector virtual table for similarity search
+    // Create vector virtual table for similarity search with an
+    // embedding for each chunk of a note
     db.execute(
         "CREATE VIRTUAL TABLE IF NOT EXISTS vec_items USING vec0(
-note_meta_id TEXT PRIMARY KEY,
+chunk_id TEXT PRIMARY KEY,
+note_meta_id TEXT,
 embedding float[384]
 );",
         [],
     )?;
 
     // 2024-12-29 Add colums for type and status
-    db.execute(
-        r"BEGIN;
+    //
+    // Only needed for databases created before the columns were
+    // added to the table definition above. This must be run as a
+    // batch, otherwise only the first statement is executed.
+    let has_type_column: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('note_meta') WHERE name = 'type'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !has_type_column {
+        db.execute_batch(
+            r"BEGIN;
 
 CREATE TABLE IF NOT EXISTS note_meta_new (
     id TEXT PRIMARY KEY,
@@ -49,17 +67,486 @@ DROP TABLE note_meta;
 ALTER TABLE note_meta_new RENAME TO note_meta;
 
 COMMIT;",
+        )?;
+    }
+
+    // 2026-10-16 Add table for storing the nearest neighbors of each
+    // note so related notes don't require a similarity search
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS note_related (
+    note_meta_id TEXT,
+    related_id TEXT,
+    distance REAL,
+    PRIMARY KEY (note_meta_id, related_id)
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add column for org properties and keywords stored as
+    // a JSON object
+    add_column_if_missing(db, "note_meta", "props", "TEXT")?;
+
+    // 2026-10-16 Add column for flagging encrypted notes which only
+    // have their title and tags stored
+    add_column_if_missing(db, "note_meta", "encrypted", "INTEGER DEFAULT 0")?;
+
+    // 2026-10-16 Add columns for task dates and recurrence
+    add_column_if_missing(db, "note_meta", "scheduled", "TEXT")?;
+    add_column_if_missing(db, "note_meta", "deadline", "TEXT")?;
+    add_column_if_missing(db, "note_meta", "repeat", "TEXT")?;
+    add_column_if_missing(db, "note_meta", "next_occurrence", "TEXT")?;
+
+    // 2026-10-16 Add table for outgoing links from each note. Links
+    // are resolved when they are read so they don't depend on the
+    // order notes are indexed in.
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS note_link (
+    note_meta_id TEXT,
+    kind TEXT,
+    target TEXT,
+    PRIMARY KEY (note_meta_id, kind, target)
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for public share links so they can be
+    // revoked before they expire
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS note_share (
+    id TEXT PRIMARY KEY,
+    note_meta_id TEXT,
+    expires_at INTEGER
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for storing responses by idempotency key
+    // so clients can safely retry requests
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS idempotency_key (
+    key TEXT PRIMARY KEY,
+    status INTEGER,
+    content_type TEXT,
+    body TEXT,
+    created_at TEXT DEFAULT CURRENT_TIMESTAMP
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for time clocked in notes using org
+    // `CLOCK:` lines. Entries are replaced each time the note is
+    // indexed.
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS clock_entry (
+    note_meta_id TEXT,
+    headline_id TEXT,
+    headline TEXT,
+    tags TEXT,
+    start TEXT,
+    end TEXT,
+    minutes INTEGER
+);",
+        [],
+    )?;
+    db.execute(
+        "CREATE INDEX IF NOT EXISTS clock_entry_note_meta_id ON clock_entry(note_meta_id)",
+        [],
+    )?;
+
+    // 2026-10-16 Flag links that were inferred from mentions of a
+    // note's title rather than linked explicitly
+    add_column_if_missing(db, "note_link", "inferred", "INTEGER DEFAULT 0")?;
+
+    // 2026-10-16 Add table for the content hash of each chunk that
+    // was embedded so unchanged chunks aren't embedded again
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS vec_chunk (
+    note_meta_id TEXT,
+    chunk INTEGER,
+    hash TEXT,
+    PRIMARY KEY (note_meta_id, chunk)
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for notes that failed to index
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS index_error (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_name TEXT NOT NULL UNIQUE,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);",
         [],
     )?;
 
+    // 2026-10-16 Add collection column so results show which
+    // collection of notes they came from
+    add_column_if_missing(db, "note_meta", "collection", "TEXT")?;
+
+    // 2026-10-16 Add table for the format embeddings are stored in
+    // so the embeddings can be rebuilt when it changes
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS vector_format (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    format TEXT NOT NULL
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add note statistics so clients can show reading
+    // time and sort by size without fetching the body
+    add_column_if_missing(db, "note_meta", "word_count", "INTEGER")?;
+    add_column_if_missing(db, "note_meta", "heading_count", "INTEGER")?;
+    add_column_if_missing(db, "note_meta", "modified_at", "TEXT")?;
+
+    // 2026-10-16 Add table for the source of quotes in notes which
+    // are stored in note_meta as highlights
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS highlight (
+    id TEXT PRIMARY KEY,
+    note_meta_id TEXT NOT NULL,
+    source TEXT NOT NULL,
+    headline TEXT
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for favorite notes
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS note_favorite (
+    note_meta_id TEXT PRIMARY KEY,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add an append-only log of changes made through the
+    // API. The triggers stop entries from being changed or removed.
+    db.execute_batch(
+        r"CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor TEXT,
+    action TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+  SELECT RAISE(ABORT, 'audit_log is append-only');
+END;",
+    )?;
+
+    // 2026-10-16 Add table for dates mentioned in notes
+    db.execute_batch(
+        r"CREATE TABLE IF NOT EXISTS note_date (
+    note_meta_id TEXT NOT NULL,
+    date TEXT NOT NULL,
+    headline TEXT,
+    active BOOLEAN NOT NULL
+);
+CREATE INDEX IF NOT EXISTS note_date_date ON note_date(date);
+CREATE INDEX IF NOT EXISTS note_date_note_meta_id ON note_date(note_meta_id);",
+    )?;
+
+    // 2026-10-16 Add table for summaries of changes to notes when
+    // they are indexed
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS index_change (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    note_meta_id TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    title TEXT NOT NULL,
+    words_added INTEGER NOT NULL,
+    words_removed INTEGER NOT NULL,
+    headings_added TEXT NOT NULL,
+    headings_removed TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for notes that weren't indexed because
+    // another file has the same ID
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS id_conflict (
+    id TEXT NOT NULL,
+    file_name TEXT NOT NULL UNIQUE,
+    kept_file_name TEXT NOT NULL,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for the read-later queue
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS reading (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT,
+    note_meta_id TEXT,
+    progress INTEGER NOT NULL DEFAULT 0,
+    status TEXT NOT NULL DEFAULT 'queued',
+    added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for headlines with their own ID which are
+    // stored in note_meta as headings
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS heading (
+    id TEXT PRIMARY KEY,
+    note_meta_id TEXT NOT NULL
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add table for runs of indexing notes
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS index_run (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    trigger TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued',
+    indexed INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    removed INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    queued_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TEXT,
+    duration_ms INTEGER
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add the modified time and hash of each note's file so
+    // files that haven't changed are skipped when indexing
+    add_column_if_missing(db, "note_meta", "file_mtime", "INTEGER")?;
+    add_column_if_missing(db, "note_meta", "content_hash", "TEXT")?;
+    add_column_if_missing(db, "index_run", "unchanged", "INTEGER NOT NULL DEFAULT 0")?;
+
+    // 2026-10-16 Add table for PDFs linked from notes which are stored
+    // in note_meta as attachments
+    db.execute(
+        r"CREATE TABLE IF NOT EXISTS attachment (
+    id TEXT PRIMARY KEY,
+    note_meta_id TEXT NOT NULL,
+    path TEXT NOT NULL
+);",
+        [],
+    )?;
+
+    // 2026-10-16 Add the hash of the request body for each
+    // idempotency key so reusing a key for a different request is
+    // rejected
+    add_column_if_missing(db, "idempotency_key", "request_hash", "TEXT")?;
+
+    // 2026-10-16 Move the link of feed items out of `file_name` into
+    // their props since they aren't files
+    db.execute(
+        "UPDATE note_meta SET props = json_set(COALESCE(props, '{}'), '$.url', file_name), file_name = NULL WHERE type = 'feed_item' AND file_name IS NOT NULL",
+        [],
+    )?;
+
+    // 2026-10-16 Store an embedding for each chunk of a note instead
+    // of only the last one. The embeddings tables are recreated so
+    // the caller needs to embed every note again.
+    let has_chunk_id: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('vec_items') WHERE name = 'chunk_id'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !has_chunk_id {
+        create_vector_tables(db, VectorFormat::stored(db)?)?;
+    }
+
+    Ok(!has_chunk_id)
+}
+
+/// Number of dimensions of the embeddings
+pub const EMBEDDING_DIMENSIONS: usize = 384;
+
+/// Quantized int8 values are in [-128, 127] for floats in [-1, 1]
+const INT8_SCALE: f32 = 127.5;
+
+/// How embeddings are stored. Quantized embeddings take up much less
+/// space and are faster to compare at the cost of some precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VectorFormat {
+    /// Full precision 32-bit floats
+    #[default]
+    Float,
+    /// One byte per dimension
+    Int8,
+    /// One bit per dimension. An int8 copy of each embedding is also
+    /// stored in `vec_items_int8` to rescore the nearest candidates.
+    Binary,
+}
+
+impl VectorFormat {
+    /// Read the format from `INDEXER_VECTOR_FORMAT` which is one of
+    /// `float`, `int8`, or `binary`. Defaults to `float`.
+    pub fn from_env() -> Self {
+        let format = env::var("INDEXER_VECTOR_FORMAT").unwrap_or_default();
+        Self::parse(&format).unwrap_or_else(|| {
+            if !format.is_empty() {
+                tracing::warn!("Ignoring invalid vector format {}", format);
+            }
+            Self::default()
+        })
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "float" => Some(Self::Float),
+            "int8" => Some(Self::Int8),
+            "binary" => Some(Self::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Float => "float",
+            Self::Int8 => "int8",
+            Self::Binary => "binary",
+        }
+    }
+
+    /// SQL to convert the float vector in the parameter e.g. `?1` to
+    /// the stored format
+    pub fn quantize(&self, param: &str) -> String {
+        match self {
+            Self::Float => param.to_string(),
+            Self::Int8 => format!("vec_quantize_int8({}, 'unit')", param),
+            Self::Binary => format!("vec_quantize_binary({})", param),
+        }
+    }
+
+    /// Multiplier to make distances between int8 embeddings close to
+    /// the distance between the full precision embeddings. Binary
+    /// embeddings are rescored using the int8 copy.
+    pub fn distance_scale(&self) -> f32 {
+        match self {
+            Self::Float => 1.0,
+            Self::Int8 | Self::Binary => 1.0 / INT8_SCALE,
+        }
+    }
+
+    /// The format embeddings are currently stored in
+    pub fn stored(db: &Connection) -> Result<Self> {
+        let format: Option<String> = db
+            .query_row("SELECT format FROM vector_format WHERE id = 1", [], |r| {
+                r.get(0)
+            })
+            .optional()?;
+        Ok(format.and_then(|f| Self::parse(&f)).unwrap_or_default())
+    }
+}
+
+/// Convert a stored int8 embedding back to floats
+pub fn dequantize_int8(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .iter()
+        .map(|b| (*b as i8 as f32 + 128.0) / INT8_SCALE - 1.0)
+        .collect()
+}
+
+/// Store embeddings in the format, recreating the embeddings tables
+/// if it changed. Returns true if they were recreated and every note
+/// needs to be embedded again.
+pub fn set_vector_format(db: &Connection, format: VectorFormat) -> Result<bool> {
+    let stored = VectorFormat::stored(db)?;
+    let has_format: bool =
+        db.query_row("SELECT COUNT(*) > 0 FROM vector_format", [], |r| r.get(0))?;
+    if has_format && stored == format {
+        return Ok(false);
+    }
+    // Embeddings stored before the format was tracked are floats
+    if !has_format && format == VectorFormat::Float {
+        db.execute(
+            "INSERT INTO vector_format(id, format) VALUES (1, 'float')",
+            [],
+        )?;
+        return Ok(false);
+    }
+
+    tracing::warn!(
+        "Vector format changed from {} to {}, all notes will be embedded again",
+        stored.as_str(),
+        format.as_str()
+    );
+    create_vector_tables(db, format)?;
+    db.execute(
+        "REPLACE INTO vector_format(id, format) VALUES (1, ?1)",
+        [format.as_str()],
+    )?;
+    Ok(true)
+}
+
+// Recreate the embeddings tables for the format. This removes every
+// embedding so all notes need to be embedded again.
+fn create_vector_tables(db: &Connection, format: VectorFormat) -> Result<()> {
+    let column_type = match format {
+        VectorFormat::Float => "float",
+        VectorFormat::Int8 => "int8",
+        VectorFormat::Binary => "bit",
+    };
+    db.execute_batch(&format!(
+        r"
+          DROP TABLE IF EXISTS vec_items;
+          DROP TABLE IF EXISTS vec_items_int8;
+          CREATE VIRTUAL TABLE vec_items USING vec0(
+            chunk_id TEXT PRIMARY KEY,
+            note_meta_id TEXT,
+            embedding {column_type}[{EMBEDDING_DIMENSIONS}]
+          );
+          DELETE FROM vec_chunk;
+        "
+    ))?;
+    if format == VectorFormat::Binary {
+        db.execute_batch(&format!(
+            r"
+              CREATE VIRTUAL TABLE vec_items_int8 USING vec0(
+                chunk_id TEXT PRIMARY KEY,
+                note_meta_id TEXT,
+                embedding int8[{EMBEDDING_DIMENSIONS}]
+              );
+            "
+        ))?;
+    }
+    Ok(())
+}
+
+/// Add a column to the table unless it already exists. SQLite doesn't
+/// support `ADD COLUMN IF NOT EXISTS` so migrations need to check
+/// first to be safe to run more than once.
+fn add_column_if_missing(db: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+        [table, column],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        db.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
     Ok(())
 }
 
 pub fn vector_db(path_to_db_file: &str) -> Result<Connection> {
+    #[allow(clippy::missing_transmute_annotations)]
     unsafe {
         sqlite3_auto_extension(Some(std::mem::transmute(sqlite3_vec_init as *const ())));
     }
-    let db = Connection::open(format!("{}/vector.db", path_to_db_file))?;
+    let db = Connection::open(Path::new(path_to_db_file).join("vector.db"))?;
 
     Ok(db)
 }
@@ -0,0 +1,60 @@
+/// Compare the indexed body of a note to the note on disk or in an
+/// earlier git revision to find notes where search results are stale
+use similar::TextDiff;
+
+/// What to compare the indexed body to
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffAgainst {
+    /// The note file as it is now
+    Disk,
+    /// The note file at a git revision e.g. `HEAD~1`
+    Revision(String),
+}
+
+impl DiffAgainst {
+    /// Defaults to the note on disk, anything else is a git revision
+    ///
+    /// ```rust
+    /// use indexer::diff::DiffAgainst;
+    ///
+    /// assert_eq!(DiffAgainst::parse(None), DiffAgainst::Disk);
+    /// assert_eq!(DiffAgainst::parse(Some("disk")), DiffAgainst::Disk);
+    /// assert_eq!(
+    ///     DiffAgainst::parse(Some("HEAD~1")),
+    ///     DiffAgainst::Revision("HEAD~1".to_string())
+    /// );
+    /// ```
+    pub fn parse(against: Option<&str>) -> Self {
+        match against.map(|s| s.trim()) {
+            None | Some("") | Some("disk") => Self::Disk,
+            Some(revision) => Self::Revision(revision.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Disk => "disk",
+            Self::Revision(revision) => revision,
+        }
+    }
+}
+
+/// Unified diff from the indexed body to the other body. Returns an
+/// empty string if they are the same.
+///
+/// ```rust
+/// use indexer::diff::unified_diff;
+///
+/// let diff = unified_diff("a\nb\n", "a\nc\n", "disk");
+/// assert_eq!(diff, "--- index\n+++ disk\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+/// assert_eq!(unified_diff("a\n", "a\n", "disk"), "");
+/// ```
+pub fn unified_diff(indexed: &str, other: &str, other_name: &str) -> String {
+    if indexed == other {
+        return String::new();
+    }
+    TextDiff::from_lines(indexed, other)
+        .unified_diff()
+        .header("index", other_name)
+        .to_string()
+}
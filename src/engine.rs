@@ -0,0 +1,106 @@
+/// The indexer as a library so other programs, e.g. a desktop app or
+/// an editor plugin, can index and search notes without running the
+/// server
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use rusqlite::Connection;
+use tantivy::IndexWriter;
+
+use super::db::{migrate_db, vector_db};
+use super::indexing::{index_all, index_writer, IndexConfig};
+use super::search::{
+    related_notes, search_notes, SearchExclusions, SearchFilters, SearchIndex, SearchResult,
+};
+use super::source::OrgDirectory;
+
+/// Indexes and searches the notes in a directory
+pub struct Indexer {
+    db: Connection,
+    notes_path: String,
+    // The only writer to the full text index while the indexer is
+    // open
+    index_writer: IndexWriter,
+    search_index: SearchIndex,
+    /// Settings that control how notes are indexed
+    pub index_config: IndexConfig,
+    /// Notes that are removed from search results
+    pub search_exclusions: SearchExclusions,
+}
+
+impl Indexer {
+    /// Open the indexer for the notes in `notes_path`, creating the
+    /// full text index in `index_path` and the db in `db_path` if they
+    /// don't exist yet. Config is read from the same environment
+    /// variables as the server.
+    pub fn open(notes_path: &str, index_path: &str, db_path: &str) -> Result<Self> {
+        fs::create_dir_all(index_path)?;
+        fs::create_dir_all(db_path)?;
+        let db = vector_db(db_path)?;
+        if migrate_db(&db)? {
+            tracing::warn!("Embeddings were cleared, index with vector set to embed notes again");
+        }
+        Ok(Self {
+            db,
+            notes_path: notes_path.to_string(),
+            index_writer: index_writer(index_path),
+            search_index: SearchIndex::open(index_path),
+            index_config: IndexConfig::from_env(),
+            search_exclusions: SearchExclusions::from_env(),
+        })
+    }
+
+    /// Index the notes at `paths` or all notes if `None`. Embeddings
+    /// are only created if `vector` is set since loading the model is
+    /// slow.
+    pub fn index(
+        &mut self,
+        paths: Option<Vec<PathBuf>>,
+        full_text: bool,
+        vector: bool,
+    ) -> Result<()> {
+        let source = OrgDirectory::new(
+            &self.notes_path,
+            self.index_config.age_identity_path.clone(),
+        )
+        .with_walk(self.index_config.walk.clone());
+        let source = match paths {
+            Some(paths) => source.with_paths(paths),
+            None => source,
+        };
+        index_all(
+            &mut self.db,
+            &mut self.index_writer,
+            &source,
+            full_text,
+            vector,
+            &self.index_config,
+        )?;
+        self.search_index.reload();
+        Ok(())
+    }
+
+    /// Search notes using the full text query syntax e.g.
+    /// `tags:work type:task`, including similar notes if
+    /// `include_similarity` is set
+    pub fn search(&self, query: &str, include_similarity: bool) -> Vec<SearchResult> {
+        search_notes(
+            &self.search_index,
+            &self.db,
+            include_similarity,
+            query,
+            20,
+            None,
+            None,
+            &self.search_exclusions,
+            &SearchFilters::default(),
+        )
+    }
+
+    /// Notes related to the note with `id` that were found when it was
+    /// indexed
+    pub fn related(&self, id: &str) -> Result<Vec<SearchResult>> {
+        Ok(related_notes(&self.db, id)?)
+    }
+}
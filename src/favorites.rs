@@ -0,0 +1,24 @@
+/// Notes marked as favorites so they're easy to get back to
+use rusqlite::{Connection, Result};
+
+/// Add or remove the note from favorites. Returns false if the note
+/// doesn't exist.
+pub fn set_favorite(db: &Connection, id: &str, favorite: bool) -> Result<bool> {
+    let exists: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM note_meta WHERE id = ?1",
+        [id],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        return Ok(false);
+    }
+    if favorite {
+        db.execute(
+            "INSERT OR IGNORE INTO note_favorite(note_meta_id) VALUES (?1)",
+            [id],
+        )?;
+    } else {
+        db.execute("DELETE FROM note_favorite WHERE note_meta_id = ?1", [id])?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+/// Ingest items from RSS and Atom feeds so they are searchable
+/// alongside notes e.g. `type:feed_item read:false`
+use std::env;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fastembed::TextEmbedding;
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
+use tantivy::{doc, IndexWriter, Term};
+
+use super::hash::sha256_hex;
+use super::indexing::{index_text_vector, text_splitter};
+use super::schema::note_schema;
+
+const FEED_ITEM_TYPE: &str = "feed_item";
+
+/// Feeds to poll and how often
+#[derive(Clone, Debug, Default)]
+pub struct FeedConfig {
+    pub urls: Vec<String>,
+    pub poll_interval: Duration,
+}
+
+impl FeedConfig {
+    /// Read the config from environment variables. Feeds are a comma
+    /// separated list of URLs in `INDEXER_FEEDS` and they are polled
+    /// every `INDEXER_FEEDS_POLL_MINUTES` (defaults to 60).
+    pub fn from_env() -> Self {
+        let urls = env::var("INDEXER_FEEDS")
+            .map(|s| {
+                s.split(',')
+                    .map(|i| i.trim().to_string())
+                    .filter(|i| !i.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let minutes = env::var("INDEXER_FEEDS_POLL_MINUTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60);
+        Self {
+            urls,
+            poll_interval: Duration::from_secs(minutes * 60),
+        }
+    }
+}
+
+/// An entry from a feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedItem {
+    pub id: String,
+    pub feed_url: String,
+    pub title: String,
+    pub link: String,
+    pub summary: String,
+    pub published: Option<String>,
+}
+
+/// Parse the entries of an RSS or Atom feed. Item IDs are a hash of
+/// the feed URL and the entry ID so they are stable between polls.
+pub fn parse_feed(feed_url: &str, content: &[u8]) -> Result<Vec<FeedItem>> {
+    let feed = feed_rs::parser::parse(content)?;
+    let items = feed
+        .entries
+        .into_iter()
+        .map(|entry| {
+            // Separate the parts so different URL and entry ID pairs
+            // can't have the same ID. The hash is shortened because
+            // tokens over 40 characters aren't indexed so the document
+            // couldn't be deleted by ID.
+            let mut id = sha256_hex(format!("{}\n{}", feed_url, entry.id).as_bytes());
+            id.truncate(32);
+
+            let link = entry
+                .links
+                .first()
+                .map(|l| l.href.clone())
+                .unwrap_or_default();
+            let summary = entry
+                .summary
+                .map(|s| s.content)
+                .or_else(|| entry.content.and_then(|c| c.body))
+                .unwrap_or_default();
+            FeedItem {
+                id,
+                feed_url: feed_url.to_string(),
+                title: entry.title.map(|t| t.content).unwrap_or(link.clone()),
+                link,
+                summary,
+                published: entry
+                    .published
+                    .or(entry.updated)
+                    .map(|d| d.format("%Y-%m-%d").to_string()),
+            }
+        })
+        .collect();
+    Ok(items)
+}
+
+/// Fetch and parse the feed
+pub async fn fetch_feed(url: &str) -> Result<Vec<FeedItem>> {
+    let resp = reqwest::get(url).await?.error_for_status()?;
+    let content = resp.bytes().await?;
+    parse_feed(url, &content)
+}
+
+fn add_feed_item_doc(
+    index_writer: &mut IndexWriter,
+    item_id: &str,
+    title: &str,
+    body: &str,
+    read: bool,
+) -> tantivy::Result<()> {
+    let schema = note_schema();
+    let id = schema.get_field("id")?;
+    // Delete the document first to get upsert behavior
+    index_writer.delete_term(Term::from_field_text(id, item_id));
+    index_writer.add_document(doc!(
+        id => item_id,
+        schema.get_field("type")? => FEED_ITEM_TYPE,
+        schema.get_field("title")? => title,
+        schema.get_field("body")? => body,
+        schema.get_field("status")? => if read { "read" } else { "unread" },
+        schema.get_field("read")? => if read { "true" } else { "false" },
+    ))?;
+    Ok(())
+}
+
+/// Store new feed items in the db and full text index, generating
+/// embeddings if a model is given. Items that were already ingested
+/// are skipped so their read state is kept. Returns the number of new
+/// items.
+pub fn index_feed_items(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    items: &[FeedItem],
+    embeddings_model: Option<&TextEmbedding>,
+) -> Result<usize> {
+    let splitter = text_splitter();
+    let mut count = 0;
+    for item in items {
+        let inserted = db.execute(
+            "INSERT OR IGNORE INTO note_meta(id, type, title, body, status, props) VALUES (?, ?, ?, ?, 'unread', ?)",
+            rusqlite::params![
+                item.id,
+                FEED_ITEM_TYPE,
+                item.title,
+                item.summary,
+                json!({"feed": item.feed_url, "url": item.link, "published": item.published})
+                    .to_string(),
+            ],
+        )?;
+        if inserted == 0 {
+            continue;
+        }
+        count += 1;
+        add_feed_item_doc(index_writer, &item.id, &item.title, &item.summary, false)?;
+        if let Some(model) = embeddings_model {
+            index_text_vector(db, model, &splitter, &item.id, &item.summary)?;
+        }
+    }
+    index_writer.commit()?;
+    Ok(count)
+}
+
+/// Mark the feed item as read so it no longer matches `read:false`.
+/// Returns an error if the item doesn't exist.
+pub fn mark_feed_item_read(
+    db: &Connection,
+    index_writer: &mut IndexWriter,
+    id: &str,
+) -> Result<()> {
+    let item: Option<(String, String)> = db
+        .query_row(
+            "SELECT title, body FROM note_meta WHERE id = ?1 AND type = ?2",
+            [id, FEED_ITEM_TYPE],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()?;
+    let Some((title, body)) = item else {
+        return Err(anyhow!("Feed item {} not found", id));
+    };
+
+    db.execute("UPDATE note_meta SET status = 'read' WHERE id = ?1", [id])?;
+    add_feed_item_doc(index_writer, id, &title, &body, true)?;
+    index_writer.commit()?;
+    Ok(())
+}
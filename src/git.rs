@@ -1,55 +1,223 @@
-use std::process::Command;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process::{Command, Output};
 
-/// Clone a repo if it doesn't already exist
-pub fn maybe_clone_repo(deploy_key_path: &str, url: &str, storage_path: &str) {
-    let git_clone = Command::new("sh")
-        .arg("-c")
-        .arg(format!(
-            "GIT_SSH_COMMAND='ssh -i {} -o IdentitiesOnly=yes' git clone {} {}",
-            deploy_key_path, url, storage_path
-        ))
+// Git credential helper that answers with the token passed in the
+// environment
+const CREDENTIAL_HELPER: &str = r#"!f() { test "$1" = get && echo "username=$INDEXER_GIT_USERNAME" && echo "password=$INDEXER_GIT_TOKEN"; }; f"#;
+
+/// Credentials for the remote of a notes repo. SSH remotes use a
+/// deploy key and HTTPS remotes use a personal access token.
+#[derive(Clone, Debug, Default)]
+pub struct GitAuth {
+    pub deploy_key_path: Option<String>,
+    pub token: Option<String>,
+    /// Username sent with the token. Most hosts ignore it but some
+    /// require a specific one e.g. `oauth2` for GitLab.
+    pub username: Option<String>,
+}
+
+impl GitAuth {
+    /// Read the credentials from `INDEXER_NOTES_DEPLOY_KEY_PATH`,
+    /// `INDEXER_NOTES_GIT_TOKEN`, and `INDEXER_NOTES_GIT_USERNAME`
+    pub fn from_env() -> Self {
+        Self {
+            deploy_key_path: env::var("INDEXER_NOTES_DEPLOY_KEY_PATH").ok(),
+            token: env::var("INDEXER_NOTES_GIT_TOKEN").ok(),
+            username: env::var("INDEXER_NOTES_GIT_USERNAME").ok(),
+        }
+    }
+
+    /// Returns true if there are any credentials to access the remote
+    pub fn is_configured(&self) -> bool {
+        self.deploy_key_path.is_some() || self.token.is_some()
+    }
+}
+
+// Git runs the ssh command with a shell so the key path is quoted in
+// case it has spaces
+fn ssh_command(deploy_key_path: &str) -> String {
+    format!(
+        "ssh -i '{}' -o IdentitiesOnly=yes",
+        deploy_key_path.replace('\'', r"'\''")
+    )
+}
+
+/// Git command run in the directory that uses the credentials for
+/// any requests to the remote. Arguments are passed to git directly
+/// rather than through a shell so paths and messages don't need
+/// escaping and it works the same on every platform.
+pub fn git_command(auth: &GitAuth, dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command
+        .current_dir(dir)
+        // Fail rather than wait for a password that will never come
+        .env("GIT_TERMINAL_PROMPT", "0");
+    if let Some(deploy_key_path) = &auth.deploy_key_path {
+        command.env("GIT_SSH_COMMAND", ssh_command(deploy_key_path));
+    }
+    // The token is set with environment variables rather than
+    // arguments so it isn't visible in the process list. The empty
+    // helper clears any others configured on the machine.
+    if let Some(token) = &auth.token {
+        command
+            .env("GIT_CONFIG_COUNT", "2")
+            .env("GIT_CONFIG_KEY_0", "credential.helper")
+            .env("GIT_CONFIG_VALUE_0", "")
+            .env("GIT_CONFIG_KEY_1", "credential.helper")
+            .env("GIT_CONFIG_VALUE_1", CREDENTIAL_HELPER)
+            .env(
+                "INDEXER_GIT_USERNAME",
+                auth.username.as_deref().unwrap_or("x-access-token"),
+            )
+            .env("INDEXER_GIT_TOKEN", token);
+    }
+    command
+}
+
+fn git(auth: &GitAuth, dir: &Path, args: &[&str]) -> Output {
+    git_command(auth, dir)
+        .args(args)
         .output()
-        .expect("failed to execute process");
+        .expect("Failed to execute process")
+}
 
-    let stdout = std::str::from_utf8(&git_clone.stdout).expect("Failed to parse stdout");
-    let stderr = std::str::from_utf8(&git_clone.stderr).expect("Failed to parse stderr");
+fn output_str(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+/// Clone a repo if it doesn't already exist
+pub fn maybe_clone_repo(auth: &GitAuth, url: &str, storage_path: &str) {
+    let git_clone = git(auth, Path::new("."), &["clone", url, storage_path]);
+
+    let stdout = output_str(&git_clone.stdout);
+    let stderr = output_str(&git_clone.stderr);
     println!("stdout: {}\nstderr: {}", stdout, stderr);
 }
 
 /// Pull and reset to origin main branch
-pub fn maybe_pull_and_reset_repo(deploy_key_path: &str, path: &str) {
-    let git_clone = Command::new("sh")
-        .arg("-c")
-        .arg(format!("cd {} && GIT_SSH_COMMAND='ssh -i {} -o IdentitiesOnly=yes' git fetch origin && git reset --hard origin/main", path, deploy_key_path))
-        .output()
-        .expect("Failed to execute process");
-
-    let stdout = std::str::from_utf8(&git_clone.stdout).expect("Failed to parse stdout");
-    let stderr = std::str::from_utf8(&git_clone.stderr).expect("Failed to parse stderr");
-    tracing::debug!("stdout: {}\nstderr: {}", stdout, stderr);
+pub fn maybe_pull_and_reset_repo(auth: &GitAuth, path: &str) {
+    let path = Path::new(path);
+    for args in [
+        &["fetch", "origin"][..],
+        &["reset", "--hard", "origin/main"][..],
+    ] {
+        let output = git(auth, path, args);
+        let stdout = output_str(&output.stdout);
+        let stderr = output_str(&output.stderr);
+        tracing::debug!("stdout: {}\nstderr: {}", stdout, stderr);
+        if !output.status.success() {
+            break;
+        }
+    }
 }
 
 /// Return a list of files that have changed between the last two
 /// commits.  Run `maybe_pull_and_reset_repo` before hand if you want
 /// to get a list of files that changed on origin.
-pub fn diff_last_commit_files(deploy_key_path: &str, path: &str) -> Vec<String> {
+pub fn diff_last_commit_files(auth: &GitAuth, path: &str) -> Vec<String> {
     // Run git diff
-    let command = Command::new("sh")
-        .arg("-c")
-        .arg(format!(
-            "cd {} && GIT_SSH_COMMAND='ssh -i {} -o IdentitiesOnly=yes' git --no-pager diff --name-only HEAD^ HEAD",
-            path,
-            deploy_key_path
-        ))
-        .output()
-        .expect("Failed to execute process");
+    let command = git(
+        auth,
+        Path::new(path),
+        // Separating with NUL stops git from quoting file names with
+        // special characters
+        &["--no-pager", "diff", "--name-only", "-z", "HEAD^", "HEAD"],
+    );
 
-    let stdout = std::str::from_utf8(&command.stdout).expect("Failed to parse stdout");
-    let stderr = std::str::from_utf8(&command.stderr).expect("Failed to parse stderr");
+    let stdout = output_str(&command.stdout);
+    let stderr = output_str(&command.stderr);
 
     if !stderr.is_empty() {
         tracing::error!("Git diff failed: {}", stderr);
     }
 
-    stdout.split("\n").map(|s| s.to_string()).collect()
+    stdout
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Contents of the file at the git revision e.g. `HEAD~1`. Returns
+/// `None` if the revision is invalid or the file isn't in it.
+pub fn file_at_revision(auth: &GitAuth, path: &Path, revision: &str) -> Option<String> {
+    // Revisions that look like options would be passed to git as one
+    if revision.starts_with('-') {
+        return None;
+    }
+    let dir = path.parent()?;
+    let file_name = path.file_name()?.to_string_lossy();
+    // `./` makes the path relative to the note's directory rather
+    // than the root of the repo
+    let object = format!("{}:./{}", revision, file_name);
+    let output = git(auth, dir, &["show", &object]);
+    if !output.status.success() {
+        tracing::debug!("Git show failed: {}", output_str(&output.stderr));
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Error when local commits couldn't be pushed to origin. The
+/// commits are kept locally but will be lost the next time the repo
+/// is reset to origin so callers should report it.
+#[derive(Debug)]
+pub struct PushError(pub String);
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to push notes: {}", self.0)
+    }
+}
+
+impl std::error::Error for PushError {}
+
+// Run the git command and return stderr as the error if it fails
+fn run_git(auth: &GitAuth, dir: &Path, args: &[&str]) -> Result<Output, PushError> {
+    let output = git(auth, dir, args);
+    let stdout = output_str(&output.stdout);
+    let stderr = output_str(&output.stderr);
+    tracing::debug!("stdout: {}\nstderr: {}", stdout, stderr);
+    if !output.status.success() {
+        return Err(PushError(format!(
+            "git {} failed: {}",
+            args[0],
+            stderr.trim()
+        )));
+    }
+    Ok(output)
+}
+
+// Replay local commits on top of origin main and push them
+fn rebase_and_push(auth: &GitAuth, dir: &Path) -> Result<(), PushError> {
+    run_git(auth, dir, &["fetch", "origin"])?;
+    if let Err(err) = run_git(auth, dir, &["rebase", "origin/main"]) {
+        // Leave the commits as they were rather than part way
+        // through a rebase with conflicts
+        git(auth, dir, &["rebase", "--abort"]);
+        return Err(err);
+    }
+    run_git(auth, dir, &["push", "origin", "HEAD:main"])?;
+    Ok(())
+}
+
+/// Commit all changes and push to origin main so local edits aren't
+/// lost the next time the repo is reset to origin. Local commits are
+/// rebased onto origin first in case it changed since the last pull
+/// and the push is tried once more if it's rejected.
+pub fn commit_and_push_repo(auth: &GitAuth, path: &str, message: &str) -> Result<(), PushError> {
+    let path = Path::new(path);
+    run_git(auth, path, &["add", "-A"])?;
+    // Nothing to commit isn't an error since earlier commits may
+    // still need pushing
+    let staged = git(auth, path, &["diff", "--cached", "--quiet"]);
+    if !staged.status.success() {
+        run_git(auth, path, &["commit", "-m", message])?;
+    }
+    rebase_and_push(auth, path).or_else(|err| {
+        tracing::warn!("Retrying push after error: {}", err);
+        rebase_and_push(auth, path)
+    })
 }
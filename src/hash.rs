@@ -0,0 +1,21 @@
+/// Hashes that are stored in the db or used in IDs. These need to be
+/// the same across Rust releases and platforms so `DefaultHasher`
+/// can't be used.
+use sha2::{Digest, Sha256};
+
+/// Returns the SHA-256 hash of the content as lowercase hex
+///
+/// ```rust
+/// use indexer::hash::sha256_hex;
+///
+/// assert_eq!(
+///     sha256_hex(b"hello"),
+///     "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+/// );
+/// ```
+pub fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
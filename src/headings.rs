@@ -0,0 +1,176 @@
+/// Headlines in a note that have their own org-id are indexed as
+/// documents of their own so search results for long notes point to
+/// the section that matched e.g. `type:heading`.
+use std::collections::HashSet;
+
+use orgize::ast::Headline;
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, Result};
+use tantivy::{doc, IndexWriter, Term};
+
+use super::indexing::word_count;
+use super::schema::note_schema;
+use super::tags::add_tags;
+
+pub const HEADING_TYPE: &str = "heading";
+
+/// A section of a note with its own ID
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub id: String,
+    /// ID of the note the headline is in
+    pub note_id: String,
+    pub title: String,
+    /// The headline and everything under it
+    pub body: String,
+    /// Tags of the note, any parent headlines, and the headline
+    pub tags: Vec<String>,
+}
+
+impl Heading {
+    /// Tags as a comma separated string like other documents
+    pub fn tag_string(&self) -> Option<String> {
+        (!self.tags.is_empty()).then(|| self.tags.join(","))
+    }
+}
+
+/// Returns every headline in the document with an `:ID:` property,
+/// including ones nested under other headlines. Tasks are indexed on
+/// their own already so they're skipped.
+///
+/// ```rust
+/// use indexer::headings::headings;
+/// use orgize::Org;
+///
+/// let org = Org::parse("* Garden :home:\n:PROPERTIES:\n:ID: garden\n:END:\nPlants.\n** Tomatoes :summer:\n:PROPERTIES:\n:ID: tomatoes\n:END:\nWater daily.\n* TODO Buy seeds\n:PROPERTIES:\n:ID: seeds\n:END:\n* No ID\n");
+/// let found = headings(&org, "note", &["plants".to_string()]);
+/// assert_eq!(found.len(), 2);
+/// assert_eq!(found[0].id, "garden");
+/// assert_eq!(found[0].note_id, "note");
+/// assert!(found[0].body.contains("Water daily."));
+/// assert_eq!(found[1].title, "Tomatoes");
+/// assert_eq!(found[1].tags, vec!["plants", "home", "summer"]);
+/// assert!(found[1].body.starts_with("** Tomatoes"));
+/// ```
+pub fn headings(org: &Org, note_id: &str, file_tags: &[String]) -> Vec<Heading> {
+    org.document()
+        .syntax()
+        .descendants()
+        .filter_map(Headline::cast)
+        .filter(|h| h.todo_keyword().is_none())
+        .filter_map(|h| {
+            let id = h.properties()?.get("ID")?.to_string().trim().to_string();
+            if id.is_empty() || id == note_id {
+                return None;
+            }
+
+            // Tags are inherited from the file and parent headlines
+            // like they are in org-mode
+            let mut ancestors: Vec<Headline> =
+                h.syntax().ancestors().filter_map(Headline::cast).collect();
+            ancestors.reverse();
+            let mut tags: Vec<String> = file_tags.to_vec();
+            for tag in ancestors.iter().flat_map(|a| a.tags()) {
+                let tag = tag.to_string();
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+
+            Some(Heading {
+                id,
+                note_id: note_id.to_string(),
+                title: h.title_raw().trim().to_string(),
+                body: h.raw(),
+                tags,
+            })
+        })
+        .collect()
+}
+
+/// Replace the headings of the note. Headings are stored in
+/// `note_meta` so they show up in search results and the note they
+/// are in is stored in the `heading` table. Returns the IDs of
+/// headings that were removed so they can be removed from the search
+/// index.
+pub fn index_headings(
+    db: &mut Connection,
+    file_name: &str,
+    collection: &str,
+    note_id: &str,
+    headings: &[Heading],
+) -> Result<Vec<String>> {
+    let tx = db.transaction()?;
+    let stale: Vec<String> = {
+        let ids: HashSet<&str> = headings.iter().map(|h| h.id.as_str()).collect();
+        let previous: Vec<String> = tx
+            .prepare("SELECT id FROM heading WHERE note_meta_id = ?1")?
+            .query_map([note_id], |r| r.get(0))?
+            .collect::<Result<_>>()?;
+        previous
+            .into_iter()
+            .filter(|id| !ids.contains(id.as_str()))
+            .collect()
+    };
+    tx.execute(
+        "DELETE FROM note_meta WHERE id IN (SELECT id FROM heading WHERE note_meta_id = ?1)",
+        [note_id],
+    )?;
+    tx.execute("DELETE FROM heading WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut meta_stmt = tx.prepare(
+            "REPLACE INTO note_meta(id, type, file_name, title, tags, body, collection, word_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut heading_stmt =
+            tx.prepare("REPLACE INTO heading(id, note_meta_id) VALUES (?1, ?2)")?;
+        for h in headings {
+            meta_stmt.execute(rusqlite::params![
+                h.id,
+                HEADING_TYPE,
+                file_name,
+                h.title,
+                h.tag_string(),
+                h.body,
+                collection,
+                word_count(&h.body),
+            ])?;
+            heading_stmt.execute([&h.id, note_id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(stale)
+}
+
+/// Add the headings to the full text index and remove the `stale`
+/// ones. Changes are committed by the caller.
+pub fn index_headings_full_text(
+    index_writer: &mut IndexWriter,
+    file_name: &str,
+    collection: &str,
+    headings: &[Heading],
+    stale: &[String],
+) -> tantivy::Result<()> {
+    let schema = note_schema();
+    let id = schema.get_field("id")?;
+    for stale_id in stale {
+        index_writer.delete_term(Term::from_field_text(id, stale_id));
+    }
+    for h in headings {
+        // Delete the document first to get upsert behavior
+        index_writer.delete_term(Term::from_field_text(id, &h.id));
+        let mut doc = doc!(
+            id => h.id.as_str(),
+            schema.get_field("type")? => HEADING_TYPE,
+            schema.get_field("title")? => h.title.as_str(),
+            schema.get_field("body")? => h.body.as_str(),
+            schema.get_field("file_name")? => file_name,
+            schema.get_field("collection")? => collection,
+        );
+        if let Some(tags) = h.tag_string() {
+            add_tags(&mut doc, schema.get_field("tags")?, &tags);
+        }
+        index_writer.add_document(doc)?;
+    }
+    Ok(())
+}
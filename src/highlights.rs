@@ -0,0 +1,229 @@
+/// Quotes from books and articles kept in notes e.g. highlights
+/// exported from a reader. Quote blocks with a source are indexed on
+/// their own so they can be searched separately from the rest of the
+/// notes e.g. `type:highlight`.
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use orgize::ast::{Headline, Keyword, QuoteBlock};
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use tantivy::{doc, IndexWriter, Term};
+
+use super::schema::note_schema;
+use super::tags::add_tags;
+
+pub const HIGHLIGHT_TYPE: &str = "highlight";
+
+// Property of a headline or note with the source of the quotes in it
+const SOURCE_PROP: &str = "SOURCE";
+
+/// A quote and where it's from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Highlight {
+    pub id: String,
+    /// ID of the note the quote is in
+    pub note_id: String,
+    pub text: String,
+    /// Book, article, or other work the quote is from
+    pub source: String,
+    /// Headline the quote is under, if any
+    pub headline: Option<String>,
+}
+
+/// Returns the quote blocks in the document that have a source. The
+/// source is the `#+CAPTION:` of the block, otherwise the `SOURCE`
+/// property of the closest headline or `note_source`. Quotes without
+/// a source are part of the note like any other text.
+///
+/// ```rust
+/// use indexer::highlights::highlights;
+/// use orgize::Org;
+///
+/// let org = Org::parse("* Chapter 1\n:PROPERTIES:\n:SOURCE: The Book\n:END:\n#+begin_quote\nA good line.\n#+end_quote\n\n#+caption: Another Book\n#+begin_quote\nAnother line.\n#+end_quote\n");
+/// let found = highlights(&org, "note", None);
+/// assert_eq!(found.len(), 2);
+/// assert_eq!(found[0].text, "A good line.");
+/// assert_eq!(found[0].source, "The Book");
+/// assert_eq!(found[0].headline.as_deref(), Some("Chapter 1"));
+/// assert_eq!(found[1].source, "Another Book");
+/// ```
+pub fn highlights(org: &Org, note_id: &str, note_source: Option<&str>) -> Vec<Highlight> {
+    org.document()
+        .syntax()
+        .descendants()
+        .filter_map(QuoteBlock::cast)
+        .filter_map(|q| {
+            let headline = q.syntax().ancestors().find_map(Headline::cast);
+            let source = caption(&q)
+                .filter(|v| !v.is_empty())
+                .or_else(|| {
+                    q.syntax()
+                        .ancestors()
+                        .filter_map(Headline::cast)
+                        .find_map(|h| h.properties()?.get(SOURCE_PROP))
+                        .map(|v| v.trim().to_string())
+                })
+                .or_else(|| note_source.map(|s| s.to_string()))?;
+            let start = usize::from(q.start());
+            let range =
+                usize::from(q.content_start()) - start..usize::from(q.content_end()) - start;
+            let text = q.raw()[range].trim().to_string();
+            if text.is_empty() {
+                return None;
+            }
+
+            // Based on the content so unchanged quotes keep their ID
+            let mut hasher = DefaultHasher::new();
+            (&source, &text).hash(&mut hasher);
+            Some(Highlight {
+                id: format!("{}-{}", note_id, hasher.finish()),
+                note_id: note_id.to_string(),
+                text,
+                source,
+                headline: headline.map(|h| h.title_raw().trim().to_string()),
+            })
+        })
+        .collect()
+}
+
+// The caption is sometimes parsed as a keyword before the block
+// rather than part of it e.g. `#+caption:` in lowercase
+fn caption(quote: &QuoteBlock) -> Option<String> {
+    quote
+        .caption()
+        .and_then(|c| c.value())
+        .map(|v| v.to_string())
+        .or_else(|| {
+            quote
+                .syntax()
+                .prev_sibling()
+                .and_then(Keyword::cast)
+                .filter(|k| k.key().eq_ignore_ascii_case("CAPTION"))
+                .map(|k| k.value().to_string())
+        })
+        .map(|v| v.trim().to_string())
+}
+
+/// Replace the highlights of the note. Highlights are stored in
+/// `note_meta` so they show up in search results along with their
+/// source in the `highlight` table. Returns the IDs of highlights
+/// that were removed so they can be removed from the search index.
+pub fn index_highlights(
+    db: &mut Connection,
+    file_name: &str,
+    tags: Option<&str>,
+    collection: &str,
+    note_id: &str,
+    highlights: &[Highlight],
+) -> Result<Vec<String>> {
+    let tx = db.transaction()?;
+    let stale: Vec<String> = {
+        let ids: HashSet<&str> = highlights.iter().map(|h| h.id.as_str()).collect();
+        let previous: Vec<String> = tx
+            .prepare("SELECT id FROM highlight WHERE note_meta_id = ?1")?
+            .query_map([note_id], |r| r.get(0))?
+            .collect::<Result<_>>()?;
+        previous
+            .into_iter()
+            .filter(|id| !ids.contains(id.as_str()))
+            .collect()
+    };
+    tx.execute(
+        "DELETE FROM note_meta WHERE id IN (SELECT id FROM highlight WHERE note_meta_id = ?1)",
+        [note_id],
+    )?;
+    tx.execute("DELETE FROM highlight WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut meta_stmt = tx.prepare(
+            "INSERT INTO note_meta(id, type, file_name, title, tags, body, collection) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let mut highlight_stmt = tx.prepare(
+            "INSERT INTO highlight(id, note_meta_id, source, headline) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for h in highlights {
+            meta_stmt.execute(rusqlite::params![
+                h.id,
+                HIGHLIGHT_TYPE,
+                file_name,
+                h.source,
+                tags,
+                h.text,
+                collection,
+            ])?;
+            highlight_stmt.execute(rusqlite::params![h.id, note_id, h.source, h.headline])?;
+        }
+    }
+    tx.commit()?;
+    Ok(stale)
+}
+
+/// Add the highlights to the full text index and remove the `stale`
+/// ones. Changes are committed by the caller.
+pub fn index_highlights_full_text(
+    index_writer: &mut IndexWriter,
+    file_name: &str,
+    tags: Option<&str>,
+    collection: &str,
+    highlights: &[Highlight],
+    stale: &[String],
+) -> tantivy::Result<()> {
+    let schema = note_schema();
+    let id = schema.get_field("id")?;
+    for stale_id in stale {
+        index_writer.delete_term(Term::from_field_text(id, stale_id));
+    }
+    for h in highlights {
+        // Delete the document first to get upsert behavior
+        index_writer.delete_term(Term::from_field_text(id, &h.id));
+        let mut doc = doc!(
+            id => h.id.as_str(),
+            schema.get_field("type")? => HIGHLIGHT_TYPE,
+            schema.get_field("title")? => h.source.as_str(),
+            schema.get_field("body")? => h.text.as_str(),
+            schema.get_field("file_name")? => file_name,
+            schema.get_field("collection")? => collection,
+        );
+        if let Some(tags) = tags {
+            add_tags(&mut doc, schema.get_field("tags")?, tags);
+        }
+        index_writer.add_document(doc)?;
+    }
+    Ok(())
+}
+
+/// Look up highlights by ID in the same order, optionally only ones
+/// from sources containing `source`
+pub fn highlights_by_id(
+    db: &Connection,
+    ids: &[String],
+    source: Option<&str>,
+) -> Result<Vec<Highlight>> {
+    let mut stmt = db.prepare(
+        r"
+          SELECT highlight.id, highlight.note_meta_id, note_meta.body, highlight.source, highlight.headline
+          FROM highlight
+          JOIN note_meta ON note_meta.id = highlight.id
+          WHERE highlight.id = ?1
+          AND (?2 IS NULL OR instr(lower(highlight.source), lower(?2)) > 0)
+        ",
+    )?;
+    let mut found = Vec::new();
+    for id in ids {
+        let rows = stmt.query_map(rusqlite::params![id, source], |r| {
+            Ok(Highlight {
+                id: r.get(0)?,
+                note_id: r.get(1)?,
+                text: r.get(2)?,
+                source: r.get(3)?,
+                headline: r.get(4)?,
+            })
+        })?;
+        for row in rows {
+            found.push(row?);
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,110 @@
+/// Render tasks with scheduled and deadline dates as an iCalendar
+/// feed so they can be subscribed to from a calendar app
+use chrono::Utc;
+use rusqlite::{Connection, Result};
+
+/// A task with at least one date to show on the calendar
+pub struct CalendarTask {
+    pub id: String,
+    pub title: String,
+    pub scheduled: Option<String>,
+    pub deadline: Option<String>,
+    pub repeat: Option<String>,
+}
+
+/// Returns all tasks with a scheduled or deadline date that are not
+/// done. Completed recurring tasks are included since they will
+/// happen again. If `ids` is set, only those tasks are returned.
+pub fn calendar_tasks(db: &Connection, ids: Option<Vec<String>>) -> Result<Vec<CalendarTask>> {
+    let ids_filter = ids.map(|i| serde_json::json!(i).to_string());
+    db.prepare(
+        r"
+          SELECT
+            id,
+            title,
+            scheduled,
+            deadline,
+            repeat
+          FROM note_meta
+          WHERE type = 'task'
+          AND (scheduled IS NOT NULL OR deadline IS NOT NULL)
+          AND (status NOT IN ('DONE', 'CANCELED') OR repeat IS NOT NULL)
+          AND (?1 IS NULL OR id IN (SELECT value FROM json_each(?1)))
+          ORDER BY id
+        ",
+    )?
+    .query_map([ids_filter], |r| {
+        Ok(CalendarTask {
+            id: r.get(0)?,
+            title: r.get(1)?,
+            scheduled: r.get(2)?,
+            deadline: r.get(3)?,
+            repeat: r.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// Render the tasks as an iCalendar document with an all day event
+/// for each scheduled and deadline date
+pub fn render_calendar(tasks: &[CalendarTask]) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//indexer//tasks//EN".to_string(),
+        "X-WR-CALNAME:Tasks".to_string(),
+    ];
+
+    for task in tasks {
+        let events = [
+            ("scheduled", "Scheduled", &task.scheduled),
+            ("deadline", "Deadline", &task.deadline),
+        ];
+        for (kind, label, date) in events {
+            let Some(date) = date else {
+                continue;
+            };
+            lines.push("BEGIN:VEVENT".to_string());
+            lines.push(format!("UID:{}-{}@indexer", task.id, kind));
+            lines.push(format!("DTSTAMP:{}", stamp));
+            lines.push(format!("DTSTART;VALUE=DATE:{}", date.replace('-', "")));
+            lines.push(format!("SUMMARY:{}: {}", label, escape_text(&task.title)));
+            if let Some(rule) = task.repeat.as_deref().and_then(repeat_to_rrule) {
+                lines.push(format!("RRULE:{}", rule));
+            }
+            lines.push("END:VEVENT".to_string());
+        }
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    // iCalendar requires CRLF line endings
+    let mut output = lines.join("\r\n");
+    output.push_str("\r\n");
+    output
+}
+
+/// Convert an org repeater like `+1w` into an iCalendar recurrence
+/// rule. The different org repeater types can't be represented so
+/// they are all treated as a fixed interval.
+fn repeat_to_rrule(repeat: &str) -> Option<String> {
+    let cookie = repeat.trim_start_matches(['.', '+']);
+    let unit = cookie.chars().last()?;
+    let interval: u32 = cookie[..cookie.len() - 1].parse().ok()?;
+    let freq = match unit {
+        'h' => "HOURLY",
+        'd' => "DAILY",
+        'w' => "WEEKLY",
+        'm' => "MONTHLY",
+        'y' => "YEARLY",
+        _ => return None,
+    };
+    Some(format!("FREQ={};INTERVAL={}", freq, interval))
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
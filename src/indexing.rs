@@ -1,21 +1,275 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
 use std::hash::{Hash, Hasher};
-use std::path::PathBuf;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
-use super::schema::note_schema;
-use super::source::{note_filter, notes};
+use super::attachments::{
+    attachments, index_attachments, index_attachments_full_text, pdf_links, Attachment,
+};
+use super::changes::{previous_body, record_note_change};
+use super::clock::{clock_entries, index_clock_entries, ClockEntry};
+use super::conflicts::{clear_id_conflict, conflicting_file, record_id_conflict};
+use super::dates::{index_note_dates, note_dates, NoteDate, DATE_FORMAT};
+use super::db::VectorFormat;
+use super::hash::sha256_hex;
+use super::headings::{headings, index_headings, index_headings_full_text, Heading};
+use super::highlights::{highlights, index_highlights, index_highlights_full_text, Highlight};
+use super::links::{index_inferred_links, index_note_links, note_links, LinkTarget};
+use super::quarantine::{clear_index_error, is_quarantined, record_index_error};
+use super::recurrence::{timestamp_date, Repeater};
+use super::runs::IndexRun;
+use super::schema::{note_schema, open_index, title_prefixes};
+use super::source::{Document, NoteWalk, SourceProvider};
+use super::tags::add_tags;
 use crate::export::MarkdownExport;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use orgize::ast::Headline;
 use orgize::rowan::ast::AstNode;
-use orgize::ParseConfig;
-use rusqlite::{Connection, Result};
-use std::fs;
+use orgize::{Org, ParseConfig};
+use rayon::prelude::*;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+use serde_json::json;
 use std::hash::DefaultHasher;
 use tantivy::schema::*;
-use tantivy::{doc, Index, IndexWriter};
+use tantivy::{doc, IndexWriter};
 use text_splitter::{ChunkConfig, TextSplitter};
 use tiktoken_rs::{cl100k_base, CoreBPE};
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard};
 use zerocopy::AsBytes;
 
+/// Settings that control how notes are indexed
+#[derive(Clone, Debug, Default)]
+pub struct IndexConfig {
+    /// Org properties and keywords (lowercased) that are added to the
+    /// full text index so they can be queried like `prop.client:acme`.
+    /// All properties are stored in `note_meta` regardless.
+    pub indexed_properties: Vec<String>,
+    /// Path to the age identity file used to decrypt `.org.age`
+    /// notes. Notes encrypted with gpg use the default keyring.
+    pub age_identity_path: Option<String>,
+    /// Task keywords used when parsing notes
+    pub keywords: TaskKeywords,
+    /// Rules for assigning a type to notes
+    pub note_types: Vec<NoteType>,
+    /// Detect mentions of note titles in other notes and store them
+    /// as inferred links
+    pub infer_links: bool,
+    /// Directories of notes other than the main notes directory
+    pub collections: Vec<Collection>,
+    /// Which sub directories of the notes directories to index
+    pub walk: NoteWalk,
+    /// Index the text of PDFs linked from notes
+    pub pdf_attachments: bool,
+    /// Index notes even if their file hasn't changed since it was last
+    /// indexed e.g. after changing task keywords or note types
+    pub force: bool,
+}
+
+impl IndexConfig {
+    /// Read the config from environment variables. Indexed properties
+    /// are a comma separated list in `INDEXER_INDEXED_PROPERTIES` and
+    /// the age identity is read from `INDEXER_AGE_IDENTITY_PATH`.
+    /// Inferred links are enabled with `INDEXER_INFER_LINKS=true`.
+    /// Collections are read from `INDEXER_COLLECTIONS` and which sub
+    /// directories are indexed from `INDEXER_NOTES_MAX_DEPTH` and
+    /// `INDEXER_NOTES_IGNORE`. PDF attachments are indexed with
+    /// `INDEXER_PDF_ATTACHMENTS=true`.
+    pub fn from_env() -> Self {
+        let indexed_properties = env::var("INDEXER_INDEXED_PROPERTIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        Self {
+            indexed_properties,
+            age_identity_path: env::var("INDEXER_AGE_IDENTITY_PATH").ok(),
+            keywords: TaskKeywords::from_env(),
+            note_types: NoteType::from_env(),
+            infer_links: env::var("INDEXER_INFER_LINKS").is_ok_and(|v| v == "true" || v == "1"),
+            collections: Collection::from_env(),
+            walk: NoteWalk::from_env(),
+            pdf_attachments: env::var("INDEXER_PDF_ATTACHMENTS")
+                .is_ok_and(|v| v == "true" || v == "1"),
+            force: false,
+        }
+    }
+
+    /// Name of the collection the note at the path belongs to
+    pub fn collection_for(&self, path: &str) -> &str {
+        self.collections
+            .iter()
+            .find(|c| Path::new(path).starts_with(&c.path))
+            .map(|c| c.name.as_str())
+            .unwrap_or(DEFAULT_COLLECTION)
+    }
+}
+
+/// Collection of the notes in the main notes directory
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// A directory of notes kept apart from the main notes directory,
+/// usually in its own git repo e.g. work notes separate from
+/// personal ones. Notes are tagged with the collection name so
+/// searches can be limited to it e.g. `collection:work`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Collection {
+    pub name: String,
+    pub path: String,
+    /// Git remote to clone the notes from
+    pub remote: Option<String>,
+}
+
+impl Collection {
+    /// Read the collections from `INDEXER_COLLECTIONS` which is a
+    /// comma separated list of collections where each collection is a
+    /// name and `|` separated settings e.g.
+    /// `work:path=/data/work|remote=git@github.com:me/work.git`.
+    pub fn from_env() -> Vec<Self> {
+        env::var("INDEXER_COLLECTIONS")
+            .map(|s| Self::parse(&s))
+            .unwrap_or_default()
+    }
+
+    fn parse(s: &str) -> Vec<Self> {
+        s.split(',')
+            .filter_map(|c| {
+                let (name, settings) = c.split_once(':')?;
+                let mut path = None;
+                let mut remote = None;
+                for setting in settings.split('|') {
+                    match setting.trim().split_once('=') {
+                        Some(("path", v)) => path = Some(v.trim().to_string()),
+                        Some(("remote", v)) => remote = Some(v.trim().to_string()),
+                        _ => tracing::warn!("Ignoring invalid collection setting {}", setting),
+                    }
+                }
+                let Some(path) = path else {
+                    tracing::warn!("Ignoring collection {} without a path", name);
+                    return None;
+                };
+                Some(Self {
+                    name: name.trim().to_string(),
+                    path,
+                    remote,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Task keywords for open and closed tasks, the same as
+/// `org-todo-keywords`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TaskKeywords {
+    pub todo: Vec<String>,
+    pub done: Vec<String>,
+}
+
+impl Default for TaskKeywords {
+    fn default() -> Self {
+        Self {
+            todo: TODO_KEYWORDS.iter().map(|k| k.to_string()).collect(),
+            done: DONE_KEYWORDS.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+}
+
+impl TaskKeywords {
+    /// Read the keywords from the comma separated lists in
+    /// `INDEXER_TODO_KEYWORDS` and `INDEXER_DONE_KEYWORDS`. Each
+    /// defaults to the built in keywords if not set.
+    pub fn from_env() -> Self {
+        let parse = |var: &str| -> Option<Vec<String>> {
+            let keywords: Vec<String> = env::var(var)
+                .ok()?
+                .split(',')
+                .map(|s| s.trim().to_uppercase())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if keywords.is_empty() {
+                None
+            } else {
+                Some(keywords)
+            }
+        };
+        let defaults = Self::default();
+        Self {
+            todo: parse("INDEXER_TODO_KEYWORDS").unwrap_or(defaults.todo),
+            done: parse("INDEXER_DONE_KEYWORDS").unwrap_or(defaults.done),
+        }
+    }
+
+    /// Returns true if the keyword is a todo or done keyword
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.todo
+            .iter()
+            .chain(self.done.iter())
+            .any(|k| k == keyword)
+    }
+}
+
+/// Property that holds the type of a note e.g. `prop.type:journal`
+pub const NOTE_TYPE_PROP: &str = "type";
+
+/// A type of note and the rules for which notes have it. Notes can
+/// also set the type explicitly with a `#+TYPE:` keyword or `:TYPE:`
+/// property.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NoteType {
+    pub name: String,
+    /// File name prefixes of notes with this type
+    pub file_prefixes: Vec<String>,
+    /// File tags of notes with this type
+    pub tags: Vec<String>,
+}
+
+impl NoteType {
+    /// Read the note types from `INDEXER_NOTE_TYPES` which is a comma
+    /// separated list of types where each type is a name and `|`
+    /// separated rules e.g. `journal:file=journal-|tag=daily,person:tag=person`.
+    pub fn from_env() -> Vec<Self> {
+        env::var("INDEXER_NOTE_TYPES")
+            .map(|s| Self::parse(&s))
+            .unwrap_or_default()
+    }
+
+    fn parse(s: &str) -> Vec<Self> {
+        s.split(',')
+            .filter_map(|t| {
+                let (name, rules) = t.split_once(':')?;
+                let mut note_type = Self {
+                    name: name.trim().to_string(),
+                    file_prefixes: vec![],
+                    tags: vec![],
+                };
+                for rule in rules.split('|') {
+                    match rule.trim().split_once('=') {
+                        Some(("file", v)) => note_type.file_prefixes.push(v.trim().to_string()),
+                        Some(("tag", v)) => note_type.tags.push(v.trim().to_string()),
+                        _ => tracing::warn!("Ignoring invalid note type rule {}", rule),
+                    }
+                }
+                Some(note_type)
+            })
+            .collect()
+    }
+
+    fn matches(&self, file_name: &str, tags: &[&str]) -> bool {
+        self.file_prefixes.iter().any(|p| file_name.starts_with(p))
+            || self.tags.iter().any(|t| tags.contains(&t.as_str()))
+    }
+}
+
+/// Org properties and keywords keyed by the lowercased name
+type Props = BTreeMap<String, String>;
+
 #[derive(Debug)]
 struct Task {
     id: String,
@@ -23,10 +277,13 @@ struct Task {
     body: String,
     status: String,
     tags: Option<String>,
-    #[allow(dead_code)]
+    props: Props,
     scheduled: Option<String>,
-    #[allow(dead_code)]
     deadline: Option<String>,
+    // Repeater cookie of the scheduled or deadline timestamp e.g. `+1w`
+    repeat: Option<String>,
+    // When a repeating task is done, the date it's due again
+    next_occurrence: Option<String>,
 }
 
 struct Note {
@@ -34,30 +291,190 @@ struct Note {
     title: String,
     body: String,
     tags: Option<String>,
+    props: Props,
     tasks: Vec<Task>,
+    // Links to other notes by ID or title
+    links: Vec<LinkTarget>,
+    // Time clocked in the note
+    clocks: Vec<ClockEntry>,
+    // Quotes with a source
+    highlights: Vec<Highlight>,
+    // Headlines with their own ID
+    headings: Vec<Heading>,
+    // Links to PDFs and the text of each one, if they're indexed
+    pdf_links: Vec<String>,
+    attachments: Vec<Attachment>,
+    // Dates mentioned by timestamps
+    dates: Vec<NoteDate>,
+    // Name of the collection the note is in
+    collection: String,
+    word_count: usize,
+    heading_count: usize,
+    // When the note file was last modified
+    modified_at: Option<String>,
+    // Modified time in nanoseconds and hash of the file, used to skip
+    // the file if it hasn't changed the next time it's indexed
+    file_mtime: Option<i64>,
+    content_hash: Option<String>,
+    // Encrypted notes are sensitive and the body should never be
+    // persisted anywhere in plain text
+    encrypted: bool,
 }
 
-/// Parse the content into a `Note`
-fn parse_note(content: &str) -> Note {
+impl Note {
+    /// Set the type property using the first matching rule unless the
+    /// note already sets it
+    fn apply_note_type(&mut self, note_types: &[NoteType], path: &str) {
+        if self.props.contains_key(NOTE_TYPE_PROP) {
+            return;
+        }
+        let file_name = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let tags: Vec<&str> = self
+            .tags
+            .as_deref()
+            .map(|t| t.split(',').collect())
+            .unwrap_or_default();
+        if let Some(t) = note_types.iter().find(|t| t.matches(file_name, &tags)) {
+            self.props
+                .insert(NOTE_TYPE_PROP.to_string(), t.name.to_string());
+        }
+    }
+
+    /// Remove everything from the note except the title and tags so
+    /// nothing else is stored
+    fn redact(&mut self) {
+        self.body = String::new();
+        self.props = Props::new();
+        self.tasks = Vec::new();
+        self.links = Vec::new();
+        self.clocks = Vec::new();
+        self.highlights = Vec::new();
+        self.headings = Vec::new();
+        self.pdf_links = Vec::new();
+        self.attachments = Vec::new();
+        self.dates = Vec::new();
+        self.word_count = 0;
+        self.heading_count = 0;
+    }
+}
+
+// These are already handled as their own fields
+const RESERVED_KEYS: [&str; 3] = ["id", "title", "filetags"];
+
+/// Collect properties into a map with lowercased keys, skipping any
+/// that have dedicated fields
+fn collect_props(entries: impl Iterator<Item = (String, String)>) -> Props {
+    entries
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .filter(|(k, _)| !RESERVED_KEYS.contains(&k.as_str()))
+        .collect()
+}
+
+/// Default task keywords for tasks that are still open
+pub const TODO_KEYWORDS: [&str; 2] = ["TODO", "WAITING"];
+
+/// Default task keywords for tasks that are closed
+pub const DONE_KEYWORDS: [&str; 3] = ["DONE", "CANCELED", "SOMEDAY"];
+
+/// Parse org content using the task keywords of the notes
+pub fn parse_org(content: &str, keywords: &TaskKeywords) -> Org {
     let config = ParseConfig {
-        todo_keywords: (
-            vec!["TODO".to_string(), "WAITING".to_string()],
-            vec![
-                "DONE".to_string(),
-                "CANCELED".to_string(),
-                "SOMEDAY".to_string(),
-            ],
-        ),
+        todo_keywords: (keywords.todo.clone(), keywords.done.clone()),
         ..Default::default()
     };
-    let p = config.parse(content);
+    config.parse(content)
+}
+
+/// Escape lines that look like org syntax with a comma so they can be
+/// put inside of a block without ending it or starting a headline
+///
+/// ```rust
+/// use indexer::indexing::escape_org;
+///
+/// assert_eq!(escape_org("* Heading\n#+end_src\nText"), ",* Heading\n,#+end_src\nText");
+/// ```
+pub fn escape_org(content: &str) -> String {
+    content
+        .lines()
+        .map(|l| {
+            if l.starts_with('*') || l.starts_with("#+") {
+                format!(",{}", l)
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Returns the ID of a task headline. Tasks sometimes don't have an
+/// org-id so a hash of the title is used instead.
+pub fn task_id(headline: &Headline) -> String {
+    let task_title = headline.title_raw().trim().to_string();
+    let mut hasher = DefaultHasher::new();
+    task_title.hash(&mut hasher);
+    let default_id = hasher.finish().to_string();
+
+    // Note: Can't use a question mark operator as that will cause an
+    // early return rather than handling the case where properties
+    // don't exist
+    if let Some(task_props) = headline.properties() {
+        // Properties might exist but the ID might be missing
+        task_props
+            .get("ID")
+            .map(|j| j.to_string())
+            .unwrap_or(default_id)
+    } else {
+        default_id
+    }
+}
 
-    let props = p.document().properties().expect(
-        "Missing property
-drawer",
+/// Generate a new org-id formatted like a UUID to match the IDs
+/// org-mode creates
+pub fn new_org_id() -> String {
+    let bytes: [u8; 16] = rand::random();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Parse the content into a `Note`
+// Parse the note from org-mode content. Returns an error if the note
+// doesn't have a property drawer with an ID or a title so the file
+// can be quarantined rather than aborting indexing.
+fn parse_note(content: &str, keywords: &TaskKeywords) -> anyhow::Result<Note> {
+    let p = parse_org(content, keywords);
+
+    let props = p
+        .document()
+        .properties()
+        .ok_or_else(|| anyhow::anyhow!("Missing property drawer"))?;
+    let id = props
+        .get("ID")
+        .ok_or_else(|| anyhow::anyhow!("Missing org-id"))?
+        .to_string();
+    let title = p.title().ok_or_else(|| anyhow::anyhow!("No title found"))?;
+
+    // Properties from the property drawer and custom keywords like
+    // `#+AREA:` are both treated as note properties
+    let note_props = collect_props(
+        props
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .chain(
+                p.keywords()
+                    .map(|k| (k.key().to_string(), k.value().to_string())),
+            ),
     );
-    let id = props.get("ID").expect("Missing org-id").to_string();
-    let title = p.title().expect("No title found");
 
     // TODO: Remove the title and the tasks when indexing the body so it's
     // not duplicated
@@ -95,31 +512,19 @@ drawer",
         .filter_map(|i| -> Option<Task> {
             if let Some(status) = i.todo_keyword().map(|j| j.to_string()) {
                 let task_title = i.title_raw().trim().to_string();
+                let id = task_id(&i);
+                let props = i
+                    .properties()
+                    .map(|task_props| {
+                        collect_props(
+                            task_props
+                                .iter()
+                                .map(|(k, v)| (k.to_string(), v.to_string())),
+                        )
+                    })
+                    .unwrap_or_default();
 
-                // Tasks sometimes don't have an org-id. These tasks are ignored.
-                let mut hasher = DefaultHasher::new();
-                task_title.hash(&mut hasher);
-                let default_id = hasher.finish().to_string();
-
-                // Note: Can't use a question mark operator as that
-                // will cause an early return rather than handling the
-                // case where properties don't exist
-                let task_properties = i.properties();
-                let id = if let Some(task_props) = task_properties {
-                    // Properties might exist but the ID might be missing
-                    task_props
-                        .get("ID")
-                        .map(|j| j.to_string())
-                        .unwrap_or(default_id)
-                } else {
-                    default_id
-                };
-
-                // Extract note body into markdown format This is
-                // useful since LLMs are typically tune for markdown
-                let mut plain_text = MarkdownExport::default();
-                plain_text.render(i.syntax());
-                let task_body = plain_text.finish();
+                let task_body = task_body(&i);
 
                 let tag_string = i
                     .tags()
@@ -134,33 +539,53 @@ drawer",
 
                 let mut scheduled = None;
                 let mut deadline = None;
+                let mut repeat = None;
+                let mut next_occurrence = None;
                 if let Some(planning) = i.planning() {
-                    scheduled = planning.scheduled().map(|t| {
-                        format!(
-                            "{}-{}-{}",
-                            t.year_start().unwrap(),
-                            t.month_start().unwrap(),
-                            t.day_start().unwrap()
-                        )
-                    });
-                    deadline = planning.deadline().map(|t| {
-                        format!(
-                            "{}-{}-{}",
-                            t.year_start().unwrap(),
-                            t.month_start().unwrap(),
-                            t.day_start().unwrap()
-                        )
-                    });
+                    let scheduled_ts = planning.scheduled();
+                    let deadline_ts = planning.deadline();
+                    scheduled = scheduled_ts.as_ref().and_then(timestamp_date);
+                    deadline = deadline_ts.as_ref().and_then(timestamp_date);
+
+                    // Prefer the scheduled date for recurrence since
+                    // that's when the task is worked on
+                    let repeating = scheduled_ts
+                        .as_ref()
+                        .and_then(|t| Some((Repeater::from_timestamp(t)?, timestamp_date(t)?)))
+                        .or_else(|| {
+                            deadline_ts.as_ref().and_then(|t| {
+                                Some((Repeater::from_timestamp(t)?, timestamp_date(t)?))
+                            })
+                        });
+                    if let Some((repeater, date)) = repeating {
+                        repeat = Some(repeater.to_string());
+                        // Completing a repeating task means it's due
+                        // again on the next occurrence
+                        if i.is_done() {
+                            let today = Local::now().date_naive();
+                            next_occurrence = Some(
+                                repeater
+                                    .next_occurrence(date, today)
+                                    .format("%Y-%m-%d")
+                                    .to_string(),
+                            );
+                        }
+                    }
                 }
+                let scheduled = scheduled.map(|d| d.format("%Y-%m-%d").to_string());
+                let deadline = deadline.map(|d| d.format("%Y-%m-%d").to_string());
 
                 let task = Task {
                     id,
                     title: task_title,
                     body: task_body,
                     tags,
+                    props,
                     status,
                     scheduled,
                     deadline,
+                    repeat,
+                    next_occurrence,
                 };
                 return Some(task);
             }
@@ -168,13 +593,121 @@ drawer",
         })
         .collect();
 
-    Note {
+    let note_highlights = highlights(&p, &id, note_props.get("source").map(|s| s.as_str()));
+    let note_headings = headings(
+        &p,
+        &id,
+        filetags.first().map(|t| t.as_slice()).unwrap_or_default(),
+    );
+
+    Ok(Note {
         id,
         title,
         body,
         tags,
+        props: note_props,
         tasks,
+        links: note_links(&p),
+        clocks: clock_entries(
+            &p,
+            filetags.first().map(|t| t.as_slice()).unwrap_or_default(),
+        ),
+        highlights: note_highlights,
+        headings: note_headings,
+        pdf_links: pdf_links(&p),
+        attachments: Vec::new(),
+        dates: note_dates(&p),
+        collection: DEFAULT_COLLECTION.to_string(),
+        word_count: word_count(content),
+        heading_count: p
+            .document()
+            .syntax()
+            .descendants()
+            .filter_map(Headline::cast)
+            .count(),
+        modified_at: None,
+        file_mtime: None,
+        content_hash: None,
+        encrypted: false,
+    })
+}
+
+// Extract the task body into markdown format. This is useful since
+// LLMs are typically tune for markdown.
+fn task_body(headline: &Headline) -> String {
+    let mut plain_text = MarkdownExport::default();
+    plain_text.render(headline.syntax());
+    plain_text.finish()
+}
+
+/// The body of the note or task with the ID as it's indexed from the
+/// content. Returns `None` if the note or task isn't in the content.
+///
+/// ```rust
+/// use indexer::indexing::{indexed_body, TaskKeywords};
+///
+/// let content = ":PROPERTIES:\n:ID: abc\n:END:\n#+TITLE: Hello\n\n* TODO Call mom\n";
+/// let keywords = TaskKeywords::default();
+/// assert_eq!(indexed_body(content, &keywords, "abc").as_deref(), Some(content));
+/// assert!(indexed_body(content, &keywords, "missing").is_none());
+/// ```
+pub fn indexed_body(content: &str, keywords: &TaskKeywords, id: &str) -> Option<String> {
+    let p = parse_org(content, keywords);
+    let is_note = p
+        .document()
+        .properties()
+        .and_then(|props| props.get("ID"))
+        .is_some_and(|note_id| note_id.trim() == id);
+    if is_note {
+        return Some(p.document().raw());
     }
+    p.document()
+        .syntax()
+        .descendants()
+        .filter_map(Headline::cast)
+        .find(|h| h.todo_keyword().is_some() && task_id(h) == id)
+        .map(|h| task_body(&h))
+}
+
+// Average words read per minute used to estimate reading time
+const WORDS_PER_MINUTE: i64 = 200;
+
+/// Number of words in the org content, not counting keywords like
+/// `#+TITLE:` or drawers like `:PROPERTIES:`
+///
+/// ```rust
+/// use indexer::indexing::word_count;
+///
+/// let content = ":PROPERTIES:\n:ID: abc\n:END:\n#+TITLE: Hello\n\n* A heading\nSome words here.\n";
+/// assert_eq!(word_count(content), 5);
+/// ```
+pub fn word_count(content: &str) -> usize {
+    let mut in_drawer = false;
+    content
+        .lines()
+        .filter(|l| {
+            let l = l.trim();
+            let is_drawer_line = l.len() > 1 && l.starts_with(':') && l.ends_with(':');
+            if is_drawer_line {
+                in_drawer = l != ":END:";
+            }
+            !(is_drawer_line || in_drawer || l.starts_with("#+"))
+        })
+        .flat_map(|l| l.split_whitespace())
+        .filter(|w| w.chars().any(|c| c.is_alphanumeric()))
+        .count()
+}
+
+/// Estimated minutes to read the words, rounded up
+///
+/// ```rust
+/// use indexer::indexing::reading_time_minutes;
+///
+/// assert_eq!(reading_time_minutes(0), 0);
+/// assert_eq!(reading_time_minutes(450), 3);
+/// ```
+pub fn reading_time_minutes(word_count: i64) -> i64 {
+    (word_count + WORDS_PER_MINUTE - 1) / WORDS_PER_MINUTE
 }
 
 enum DocType {
@@ -191,10 +724,21 @@ impl DocType {
     }
 }
 
+fn add_title_prefixes(doc: &mut TantivyDocument, field: Field, title: &str, aliases: Option<&str>) {
+    let prefixes: BTreeSet<String> = title_prefixes(title)
+        .into_iter()
+        .chain(aliases.map(title_prefixes).unwrap_or_default())
+        .collect();
+    for prefix in prefixes {
+        doc.add_text(field, prefix);
+    }
+}
+
 // Deletes and then writes the document to the index
 fn index_note_full_text(
     index_writer: &mut IndexWriter,
     schema: &Schema,
+    config: &IndexConfig,
     file_name_value: &str,
     note: &Note,
 ) -> tantivy::Result<()> {
@@ -211,103 +755,332 @@ fn index_note_full_text(
     let tags = schema.get_field("tags")?;
     let status = schema.get_field("status")?;
     let file_name = schema.get_field("file_name")?;
+    let prop = schema.get_field("prop")?;
+    let encrypted = schema.get_field("encrypted")?;
+    let title_prefix = schema.get_field("title_prefix")?;
+    let collection = schema.get_field("collection")?;
+    let mentions_date = schema.get_field("mentions_date")?;
 
-    // Parse the file from the path
-    let content = &note.body;
-    let note_type = DocType::Note.to_str();
-    let Note {
-        id: note_id,
-        title: note_title,
-        body: note_body,
-        tags: note_tags,
-        tasks: note_tasks,
-    } = parse_note(content);
+    // Only the configured properties are added to the index
+    let indexed_props = |props: &Props| -> BTreeMap<String, OwnedValue> {
+        props
+            .iter()
+            // The note type is always indexed so notes can be
+            // filtered by it
+            .filter(|(k, _)| config.indexed_properties.contains(k) || k.as_str() == NOTE_TYPE_PROP)
+            .map(|(k, v)| (k.to_owned(), OwnedValue::Str(v.to_owned())))
+            .collect()
+    };
+    let is_encrypted = if note.encrypted { "true" } else { "false" };
 
+    let note_type = DocType::Note.to_str();
     let mut doc = doc!(
-        id => note_id,
+        id => note.id.as_str(),
         r#type => note_type,
-        title => note_title,
-        body => note_body,
+        title => note.title.as_str(),
+        body => note.body.as_str(),
         file_name => file_name_value,
+        encrypted => is_encrypted,
+        collection => note.collection.as_str(),
     );
 
     // This needs to be done outside of the `doc!` macro
-    if let Some(tag_list) = note_tags {
-        doc.add_text(tags, tag_list);
+    if let Some(tag_list) = &note.tags {
+        add_tags(&mut doc, tags, tag_list);
+    }
+    doc.add_object(prop, indexed_props(&note.props));
+    // Org-roam aliases are quoted but quotes are ignored when
+    // splitting into words anyway
+    let aliases = note.props.get("roam_aliases").map(|a| a.as_str());
+    add_title_prefixes(&mut doc, title_prefix, &note.title, aliases);
+    let dates: BTreeSet<String> = note
+        .dates
+        .iter()
+        .map(|d| d.date.format(DATE_FORMAT).to_string())
+        .collect();
+    for date in dates {
+        doc.add_text(mentions_date, date);
     }
     index_writer.add_document(doc)?;
 
     // Index each task
-    for t in note_tasks.into_iter() {
+    for t in note.tasks.iter() {
         // Delete first to get upsert behavior
         let task_term_id = Term::from_field_text(id, &t.id);
         index_writer.delete_term(task_term_id);
 
         let task_type = DocType::Task.to_str();
         let mut doc = doc!(
-            id => t.id,
+            id => t.id.as_str(),
             r#type => task_type,
-            title => t.title,
-            body => t.body,
-            status => t.status,
+            title => t.title.as_str(),
+            body => t.body.as_str(),
+            status => t.status.as_str(),
             file_name => file_name_value,
+            encrypted => is_encrypted,
+            collection => note.collection.as_str(),
         );
-        if let Some(tag_list) = t.tags {
-            doc.add_text(tags, tag_list);
+        if let Some(tag_list) = &t.tags {
+            add_tags(&mut doc, tags, tag_list);
         }
+        doc.add_object(prop, indexed_props(&t.props));
+        add_title_prefixes(&mut doc, title_prefix, &t.title, None);
         index_writer.add_document(doc)?;
     }
 
     Ok(())
 }
 
-/// Index the embeddings for the note
+// Hash of a chunk's content to detect when it changed
+fn chunk_hash(chunk: &str) -> String {
+    sha256_hex(chunk.as_bytes())
+}
+
+// ID of the embedding of a chunk of a document. Chunks are identified
+// by their hash so the embedding of a chunk is kept when other chunks
+// are added or removed.
+fn chunk_id(id: &str, hash: &str) -> String {
+    format!("{}:{}", id, hash)
+}
+
+/// Text of a document that needs to be embedded along with the hash
+/// of each chunk of it
+struct PendingEmbedding {
+    id: String,
+    path: String,
+    /// The hash and text of each chunk to embed that changed
+    chunks: Vec<(String, String)>,
+    hashes: Vec<String>,
+}
+
+/// Find the chunks of the text that need to be embedded
 /// Target model has N tokens or roughly a M sized context window
 ///
 /// Algorithm:
 /// 1. If the note text is less than N tokens, embed the whole thing
 /// 2. Otherwise, split the text into N tokens
-/// 3. Calculate the embeddings for each chunk
-/// 4. Store the embedding vector in the sqlite database
+/// 3. Calculate the embeddings for each chunk that changed
+/// 4. Store the embedding vector of each chunk in the sqlite database
 /// 5. Include metadata about the source of the chunk for further
 ///    retrieval and to avoid duplicating rows
-fn index_note_vector(
+fn pending_embedding(
+    db: &Connection,
+    splitter: &TextSplitter<CoreBPE>,
+    id: &str,
+    path: &str,
+    text: &str,
+) -> Result<PendingEmbedding> {
+    let chunks: Vec<&str> = splitter.chunks(text).collect();
+    let hashes: Vec<String> = chunks.iter().map(|c| chunk_hash(c)).collect();
+    let mut embedded: BTreeSet<String> = db
+        .prepare("SELECT hash FROM vec_chunk WHERE note_meta_id = ?")?
+        .query_map([id], |r| r.get(0))?
+        .collect::<Result<_>>()?;
+
+    // Chunks with the same text only need to be embedded once
+    let chunks = hashes
+        .iter()
+        .zip(chunks)
+        .filter(|(hash, _)| embedded.insert(hash.to_string()))
+        .map(|(hash, chunk)| (hash.clone(), chunk.to_string()))
+        .collect();
+    Ok(PendingEmbedding {
+        id: id.to_string(),
+        path: path.to_string(),
+        chunks,
+        hashes,
+    })
+}
+
+// Store the embedding of each chunk that changed, remove the
+// embeddings of chunks that no longer exist, and remember the hashes
+// of the chunks. Run this in a transaction.
+fn store_embedding(
+    db: &Connection,
+    pending: &PendingEmbedding,
+    embeddings: &[Vec<f32>],
+) -> Result<()> {
+    let id = pending.id.as_str();
+    let format = VectorFormat::stored(db)?;
+    // Binary embeddings are rescored with the int8 copy
+    let mut tables = vec![("vec_items", format)];
+    if format == VectorFormat::Binary {
+        tables.push(("vec_items_int8", VectorFormat::Int8));
+    }
+    let chunk_ids: Vec<String> = pending.hashes.iter().map(|h| chunk_id(id, h)).collect();
+    for (table, format) in tables {
+        let quantize = format.quantize("?");
+        for ((hash, _), embedding) in pending.chunks.iter().zip(embeddings) {
+            upsert_embedding(db, table, &quantize, &chunk_id(id, hash), id, embedding);
+        }
+        // Forget the embeddings of chunks that were removed, which is
+        // all of them if the text is now empty
+        db.execute(
+            &format!(
+                "DELETE FROM {} WHERE note_meta_id = ?1 AND chunk_id NOT IN (SELECT value FROM json_each(?2))",
+                table
+            ),
+            rusqlite::params![id, json!(chunk_ids).to_string()],
+        )?;
+    }
+
+    db.execute(
+        "DELETE FROM vec_chunk WHERE note_meta_id = ? AND chunk >= ?",
+        rusqlite::params![id, pending.hashes.len() as i64],
+    )?;
+    let mut stmt =
+        db.prepare("REPLACE INTO vec_chunk(note_meta_id, chunk, hash) VALUES (?, ?, ?)")?;
+    for (i, hash) in pending.hashes.iter().enumerate() {
+        stmt.execute(rusqlite::params![id, i as i64, hash])?;
+    }
+    Ok(())
+}
+
+// Embed the pending chunks in one batch, which is much faster than
+// one at a time, and store them in one transaction
+fn embed_pending(
     db: &mut Connection,
     embeddings_model: &TextEmbedding,
-    splitter: &TextSplitter<CoreBPE>,
-    file_name: &str,
-    note: &Note,
+    pending: &[PendingEmbedding],
+    progress: &dyn Fn(IndexEvent),
 ) -> Result<()> {
-    tracing::debug!("Vector indexing note: {}", file_name);
-
-    // Generate embeddings and store it in the DB
-    let mut embedding_stmt =
-        db.prepare("INSERT OR REPLACE INTO vec_items(note_meta_id, embedding) VALUES (?, ?)")?;
-    let mut embedding_update_stmt =
-        db.prepare("UPDATE vec_items set embedding = ? WHERE note_meta_id = ?")?;
-
-    let embeddings: Vec<Vec<Vec<f32>>> = splitter
-        .chunks(&note.body)
-        .map(|chunk| {
-            embeddings_model
-                .embed(vec![chunk], None)
-                .expect("Failed to generate embeddings")
-        })
+    let chunks: Vec<&str> = pending
+        .iter()
+        .flat_map(|p| p.chunks.iter().map(|(_, chunk)| chunk.as_str()))
         .collect();
-
-    for embedding in embeddings.concat() {
-        // Upserts are not currently supported by sqlite for
-        // virtual tables like the vector embeddings table so this
-        // attempts to insert a new row and then falls back to an
-        // update statement.
-        embedding_stmt
-            .execute(rusqlite::params![note.id, embedding.as_bytes()])
-            .unwrap_or_else(|_| {
-                embedding_update_stmt
-                    .execute(rusqlite::params![embedding.as_bytes(), note.id])
-                    .expect("Update failed")
+    let embeddings = if chunks.is_empty() {
+        Vec::new()
+    } else {
+        for p in pending.iter().filter(|p| !p.chunks.is_empty()) {
+            progress(IndexEvent::Embedding {
+                path: p.path.clone(),
             });
+        }
+        embeddings_model
+            .embed(chunks, None)
+            .expect("Failed to generate embeddings")
+    };
+
+    let tx = db.transaction()?;
+    let mut offset = 0;
+    for p in pending {
+        let end = offset + p.chunks.len();
+        store_embedding(&tx, p, &embeddings[offset..end])?;
+        offset = end;
+    }
+    tx.commit()
+}
+
+/// Generate embeddings for the text and store them in the DB for the
+/// document with the given ID. The hash of each chunk is stored so
+/// embeddings are only generated for chunks that changed.
+pub fn index_text_vector(
+    db: &mut Connection,
+    embeddings_model: &TextEmbedding,
+    splitter: &TextSplitter<CoreBPE>,
+    id: &str,
+    text: &str,
+) -> Result<()> {
+    let pending = pending_embedding(db, splitter, id, id, text)?;
+    embed_pending(db, embeddings_model, &[pending], &|_| {})
+}
+
+// Upserts are not currently supported by sqlite for virtual tables
+// like the vector embeddings table so this attempts to insert a new
+// row and then falls back to an update statement.
+fn upsert_embedding(
+    db: &Connection,
+    table: &str,
+    quantize: &str,
+    chunk_id: &str,
+    id: &str,
+    embedding: &[f32],
+) {
+    db.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {}(chunk_id, note_meta_id, embedding) VALUES (?, ?, {})",
+            table, quantize
+        ),
+        rusqlite::params![chunk_id, id, embedding.as_bytes()],
+    )
+    .unwrap_or_else(|_| {
+        db.execute(
+            &format!(
+                "UPDATE {} set embedding = {} WHERE chunk_id = ?",
+                table, quantize
+            ),
+            rusqlite::params![embedding.as_bytes(), chunk_id],
+        )
+        .expect("Update failed")
+    });
+}
+
+/// Store the nearest neighbors of the note in the `note_related`
+/// table. This needs to be run after the embeddings for all notes
+/// have been stored so the neighbors are up to date.
+fn index_note_related(db: &mut Connection, note_id: &str, limit: usize) -> Result<()> {
+    // Binary embeddings are too imprecise to rank related notes so
+    // the int8 copy is used instead
+    let format = VectorFormat::stored(db)?;
+    let table = if format == VectorFormat::Binary {
+        "vec_items_int8"
+    } else {
+        "vec_items"
+    };
+    let chunk_ids: Vec<String> = db
+        .prepare(&format!(
+            "SELECT chunk_id FROM {table} WHERE note_meta_id = ?"
+        ))?
+        .query_map([note_id], |r| r.get(0))?
+        .collect::<Result<_>>()?;
+    // The note's own chunks are the closest matches and other notes
+    // can have more than one chunk in the neighbors so extra
+    // neighbors are included
+    let k = (limit + chunk_ids.len()) * 2;
+    // Notes are as close as their closest chunks
+    let mut closest: BTreeMap<String, f32> = BTreeMap::new();
+    {
+        let mut stmt = db.prepare(&format!(
+            r"
+          SELECT
+            note_meta_id,
+            distance
+          FROM {table}
+          WHERE embedding MATCH (SELECT embedding FROM {table} WHERE chunk_id = ?)
+          AND k = ?
+          ORDER BY distance
+        ",
+        ))?;
+        for chunk_id in chunk_ids.iter() {
+            let neighbors = stmt.query_map(rusqlite::params![chunk_id, k as i64], |r| {
+                Ok((
+                    r.get::<_, String>(0)?,
+                    r.get::<_, f32>(1)? * format.distance_scale(),
+                ))
+            })?;
+            for neighbor in neighbors {
+                let (id, distance) = neighbor?;
+                if id != note_id {
+                    let d = closest.entry(id).or_insert(distance);
+                    *d = d.min(distance);
+                }
+            }
+        }
+    }
+    let mut related: Vec<(String, f32)> = closest.into_iter().collect();
+    related.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM note_related WHERE note_meta_id = ?", [note_id])?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO note_related(note_meta_id, related_id, distance) VALUES (?, ?, ?)",
+        )?;
+        for (related_id, distance) in related.into_iter().take(limit) {
+            stmt.execute(rusqlite::params![note_id, related_id, distance])?;
+        }
     }
+    tx.commit()?;
 
     Ok(())
 }
@@ -318,25 +1091,53 @@ fn index_note_vector(
 /// note(s) by ID.
 fn index_note_meta(db: &mut Connection, file_name: &str, note: &Note) -> Result<()> {
     let mut note_meta_stmt = db.prepare(
-        "REPLACE INTO note_meta(id, type, file_name, title, tags, body) VALUES (?, ?, ?, ?, ?, ?)",
+        "REPLACE INTO note_meta(id, type, file_name, title, tags, body, props, encrypted, collection, word_count, heading_count, modified_at, file_mtime, content_hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )?;
 
     // Update the note meta table
     note_meta_stmt
         // TODO: Don't hardcode the note path, save the file name instead
         .execute(rusqlite::params![
-            note.id, "note", file_name, note.title, note.tags, note.body
+            note.id,
+            "note",
+            file_name,
+            note.title,
+            note.tags,
+            note.body,
+            json!(note.props).to_string(),
+            note.encrypted,
+            note.collection,
+            note.word_count,
+            note.heading_count,
+            note.modified_at,
+            note.file_mtime,
+            note.content_hash
         ])
         .expect("Note meta upsert failed");
 
     let mut task_meta_stmt = db.prepare(
-        "REPLACE INTO note_meta(id, type, file_name, title, tags, body, status) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        "REPLACE INTO note_meta(id, type, file_name, title, tags, body, status, props, encrypted, scheduled, deadline, repeat, next_occurrence, collection, word_count, modified_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )?;
 
     for t in note.tasks.iter() {
         task_meta_stmt
             .execute(rusqlite::params![
-                t.id, "task", file_name, t.title, t.tags, t.body, t.status
+                t.id,
+                "task",
+                file_name,
+                t.title,
+                t.tags,
+                t.body,
+                t.status,
+                json!(t.props).to_string(),
+                note.encrypted,
+                t.scheduled,
+                t.deadline,
+                t.repeat,
+                t.next_occurrence,
+                note.collection,
+                word_count(&t.body),
+                note.modified_at
             ])
             .expect("Note meta upsert failed for task");
     }
@@ -344,65 +1145,604 @@ fn index_note_meta(db: &mut Connection, file_name: &str, note: &Note) -> Result<
     Ok(())
 }
 
+/// Remove the note, tasks, highlights, and headings in the file from
+/// `note_meta`, the full text index, embeddings, and every table that
+/// refers to them by ID. The index writer still needs to be
+/// committed.
+pub fn remove_note_file(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    file_name: &str,
+) -> Result<()> {
+    let ids: Vec<String> = db
+        .prepare("SELECT id FROM note_meta WHERE file_name = ?1")?
+        .query_map([file_name], |r| r.get(0))?
+        .collect::<Result<_>>()?;
+    // Only binary embeddings have an int8 copy
+    let has_int8: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'vec_items_int8'",
+        [],
+        |r| r.get(0),
+    )?;
+    let id_field = note_schema()
+        .get_field("id")
+        .expect("Schema is missing the id field");
+
+    let tx = db.transaction()?;
+    for id in ids.iter() {
+        for table in [
+            "vec_items",
+            "vec_chunk",
+            "note_related",
+            "note_link",
+            "clock_entry",
+            "note_date",
+            "highlight",
+            "heading",
+            "attachment",
+            "note_favorite",
+        ] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE note_meta_id = ?1", table),
+                [id],
+            )?;
+        }
+        if has_int8 {
+            tx.execute("DELETE FROM vec_items_int8 WHERE note_meta_id = ?1", [id])?;
+        }
+        // Other notes shouldn't list it as related either
+        tx.execute("DELETE FROM note_related WHERE related_id = ?1", [id])?;
+        index_writer.delete_term(Term::from_field_text(id_field, id));
+    }
+    tx.execute("DELETE FROM note_meta WHERE file_name = ?1", [file_name])?;
+    tx.execute("DELETE FROM index_error WHERE file_name = ?1", [file_name])?;
+    tx.execute("DELETE FROM id_conflict WHERE file_name = ?1", [file_name])?;
+    tx.commit()
+}
+
+/// Model used to generate embeddings for the index
+pub const EMBEDDING_MODEL: EmbeddingModel = EmbeddingModel::BGESmallENV15;
+
+// Set when the model failed to load so searches don't keep trying
+// to download it
+static EMBEDDINGS_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// Load the model used to generate embeddings. Returns `None` if the
+/// model files aren't available e.g. they can't be downloaded on a
+/// server without internet access, in which case embeddings and
+/// similarity search are skipped.
+pub fn embeddings_model() -> Option<TextEmbedding> {
+    match TextEmbedding::try_new(
+        InitOptions::new(EMBEDDING_MODEL).with_show_download_progress(true),
+    ) {
+        Ok(model) => {
+            EMBEDDINGS_UNAVAILABLE.store(false, Ordering::Relaxed);
+            Some(model)
+        }
+        Err(err) => {
+            tracing::warn!(
+                "Embeddings model {:?} is unavailable, skipping embeddings and similarity search: {}",
+                EMBEDDING_MODEL,
+                err
+            );
+            EMBEDDINGS_UNAVAILABLE.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Returns false if the embeddings model failed to load the last time
+/// it was loaded. It's loaded again the next time notes are indexed.
+pub fn embeddings_available() -> bool {
+    !EMBEDDINGS_UNAVAILABLE.load(Ordering::Relaxed)
+}
+
+/// Splitter for chunking text before generating embeddings
+pub fn text_splitter() -> TextSplitter<CoreBPE> {
+    let tokenizer = cl100k_base().unwrap();
+    // Targeting Llama 3.2 with a context window of 128k tokens means
+    // we can stuff around 100 documents
+    let max_tokens = 1280;
+    TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer))
+}
+
+/// Open the full text search index for writing, creating it if it
+/// doesn't exist yet
+pub fn index_writer(index_dir_path: &str) -> IndexWriter {
+    let idx = open_index(index_dir_path);
+    idx.writer(50_000_000)
+        .expect("Index writer failed to initialize")
+}
+
+/// Progress of indexing for each note
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum IndexEvent {
+    /// Started indexing this many notes
+    Started {
+        total: usize,
+    },
+    /// Generating embeddings for the note which is the slowest part
+    Embedding {
+        path: String,
+    },
+    Indexed {
+        path: String,
+    },
+    Failed {
+        path: String,
+        error: String,
+    },
+    /// The note's file was deleted so it was removed from the index
+    Removed {
+        path: String,
+    },
+    /// The note's file hasn't changed since it was last indexed so it
+    /// was skipped
+    Unchanged {
+        path: String,
+    },
+    /// Finished indexing the notes. Indexing more than one collection
+    /// starts and finishes once for each collection.
+    Finished {
+        indexed: usize,
+        unchanged: usize,
+        failed: usize,
+    },
+}
+
+// Progress events that haven't been received yet are dropped for
+// subscribers that fall this far behind
+const INDEX_EVENTS_CAPACITY: usize = 256;
+
+/// Owns the only writer for the full text search index. Tantivy
+/// locks the index directory while a writer is open so opening a
+/// second one fails. Everything in the server that writes to the
+/// index shares this writer instead.
+#[derive(Clone)]
+pub struct IndexService {
+    writer: Arc<Mutex<IndexWriter>>,
+    events: broadcast::Sender<IndexEvent>,
+    // The run that's queued or in progress, if any
+    run: Arc<std::sync::Mutex<Option<IndexRun>>>,
+}
+
+impl IndexService {
+    pub fn open(index_dir_path: &str) -> Self {
+        let (events, _) = broadcast::channel(INDEX_EVENTS_CAPACITY);
+        Self {
+            writer: Arc::new(Mutex::new(index_writer(index_dir_path))),
+            events,
+            run: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Receive progress events for indexing that starts after
+    /// subscribing
+    pub fn subscribe(&self) -> broadcast::Receiver<IndexEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send progress to any subscribers for `index_all_with_progress`
+    /// and count it in the current run
+    pub fn progress(&self) -> impl Fn(IndexEvent) + '_ {
+        |event| {
+            if let Some(run) = self.run.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+                run.record(&event);
+            }
+            // Nobody is listening which is fine
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// The run that's queued or in progress, if any
+    pub fn current_run(&self) -> Option<IndexRun> {
+        self.run.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Set the run that's queued or in progress, returning the
+    /// previous one e.g. to save it once it's finished
+    pub fn set_run(&self, run: Option<IndexRun>) -> Option<IndexRun> {
+        std::mem::replace(&mut self.run.lock().unwrap_or_else(|e| e.into_inner()), run)
+    }
+
+    /// Wait for the writer, queueing behind any indexing that is
+    /// already running
+    pub async fn writer(&self) -> OwnedMutexGuard<IndexWriter> {
+        Arc::clone(&self.writer).lock_owned().await
+    }
+
+    /// Take the writer only if nothing else is indexing
+    pub fn try_writer(&self) -> Option<OwnedMutexGuard<IndexWriter>> {
+        Arc::clone(&self.writer).try_lock_owned().ok()
+    }
+}
+
+// Notes are read and parsed in parallel in batches of this many
+const INDEX_BATCH_SIZE: usize = 64;
+
+// Modified time and hash of a note's file when it was last indexed
+struct IndexedFile {
+    file_mtime: Option<i64>,
+    content_hash: String,
+    has_embedding: bool,
+}
+
+// Look up the file as it was the last time it was indexed. Files
+// that were only ever indexed for embeddings don't have a hash.
+fn indexed_file(db: &Connection, file_name: &str) -> Result<Option<IndexedFile>> {
+    db.query_row(
+        r"
+          SELECT file_mtime, content_hash,
+            EXISTS(SELECT 1 FROM vec_chunk WHERE note_meta_id = note_meta.id)
+          FROM note_meta
+          WHERE file_name = ?1 AND type = 'note' AND content_hash IS NOT NULL
+        ",
+        [file_name],
+        |r| {
+            Ok(IndexedFile {
+                file_mtime: r.get(0)?,
+                content_hash: r.get(1)?,
+                has_embedding: r.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
+
+// Hash of a file's content to detect when it changed. This is
+// stored so it needs to be stable across Rust releases. Hashes from
+// before it was stable don't match so those files are indexed again
+// once.
+fn content_hash(content: &[u8]) -> String {
+    sha256_hex(content)
+}
+
+enum ReadNote {
+    Changed(Box<Note>),
+    // The file is the same as when it was last indexed
+    Unchanged { file_mtime: Option<i64> },
+}
+
+// Modified time of the file in nanoseconds
+fn file_mtime(path: &Path) -> Option<i64> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64)
+}
+
+// Read and parse the document unless the file is the same as the
+// `unchanged_since` version
+fn read_changed_note(
+    source: &dyn SourceProvider,
+    doc: &Document,
+    config: &IndexConfig,
+    unchanged_since: Option<&IndexedFile>,
+) -> anyhow::Result<ReadNote> {
+    // Checking the modified time first avoids reading the file at all.
+    // The file is hashed as it is on disk so encrypted notes don't
+    // need to be decrypted to tell if they changed.
+    if let Some(indexed) = unchanged_since {
+        let file_mtime = file_mtime(&doc.path);
+        if file_mtime.is_some() && file_mtime == indexed.file_mtime
+            || content_hash(&fs::read(&doc.path)?) == indexed.content_hash
+        {
+            return Ok(ReadNote::Unchanged { file_mtime });
+        }
+    }
+    let note = read_and_parse_note(source, doc, config)?;
+    Ok(ReadNote::Changed(Box::new(note)))
+}
+
+// Read the document and parse it into a note with the settings that
+// come from the config and the file
+fn read_and_parse_note(
+    source: &dyn SourceProvider,
+    doc: &Document,
+    config: &IndexConfig,
+) -> anyhow::Result<Note> {
+    let file_name = doc.path.to_str().unwrap();
+    let content = source.read(doc)?;
+    let mut note = parse_note(&content, &config.keywords)?;
+    // Never store the plain text of encrypted notes. This is done
+    // before the type is set so it can still come from the file name
+    // or tags.
+    if doc.encrypted {
+        note.encrypted = true;
+        note.redact();
+    }
+    note.apply_note_type(&config.note_types, file_name);
+    note.collection = config.collection_for(file_name).to_string();
+    // Extracting the text of PDFs is slow so it's done here while
+    // notes are being read in parallel
+    if config.pdf_attachments && !doc.encrypted {
+        note.attachments = attachments(&note.id, &doc.path, &note.pdf_links);
+    }
+    note.modified_at = fs::metadata(&doc.path)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Secs, true));
+    note.file_mtime = file_mtime(&doc.path);
+    note.content_hash = Some(content_hash(&fs::read(&doc.path)?));
+    Ok(note)
+}
+
 /// This is the primary function to call for indexing. Coordinates
 /// saving notes in the db, full text search index, and vector
 /// storage. This needs to be done in one to avoid parsing org mode
-/// notes many times for each index.
+/// notes many times for each index. Documents to index come from the
+/// `source` e.g. an `OrgDirectory` of notes.
 pub fn index_all(
     db: &mut Connection,
-    index_dir_path: &str,
-    notes_dir_path: &str,
+    index_writer: &mut IndexWriter,
+    source: &dyn SourceProvider,
     index_full_text: bool,
     index_vector: bool,
-    paths: Option<Vec<PathBuf>>,
+    config: &IndexConfig,
 ) -> Result<()> {
-    let embeddings_model = TextEmbedding::try_new(
-        InitOptions::new(EmbeddingModel::BGESmallENV15).with_show_download_progress(true),
+    index_all_with_progress(
+        db,
+        index_writer,
+        source,
+        index_full_text,
+        index_vector,
+        config,
+        &|_| {},
     )
-    .unwrap();
-
-    let tokenizer = cl100k_base().unwrap();
-    // Targeting Llama 3.2 with a context window of 128k tokens means
-    // we can stuff around 100 documents
-    let max_tokens = 1280;
-    let splitter = TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer));
+}
 
-    let note_paths: Vec<PathBuf> = if let Some(path_bufs) = paths {
-        // Only index the specified notes
-        note_filter(notes_dir_path, path_bufs)
+/// Same as `index_all` and calls `progress` as each note is indexed
+pub fn index_all_with_progress(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    source: &dyn SourceProvider,
+    index_full_text: bool,
+    index_vector: bool,
+    config: &IndexConfig,
+    progress: &dyn Fn(IndexEvent),
+) -> Result<()> {
+    // Loading the model is slow so only do it when it's needed
+    let embeddings_model = if index_vector {
+        embeddings_model()
     } else {
-        notes(notes_dir_path)
+        None
     };
+    let splitter = text_splitter();
 
-    let index_path =
-        tantivy::directory::MmapDirectory::open(index_dir_path).expect("Index not found");
     let schema = note_schema();
-    let idx =
-        Index::open_or_create(index_path, schema.clone()).expect("Unable to open or create index");
-    let mut index_writer: IndexWriter = idx
-        .writer(50_000_000)
-        .expect("Index writer failed to initialize");
-
-    for p in note_paths.iter() {
-        let file_name = p.to_str().unwrap();
-        let content = fs::read_to_string(file_name).unwrap();
-        let note = parse_note(&content);
-
-        // Always update the meta DB otherwise it's possible for the
-        // other indices to diverge which will eventually break search
-        index_note_meta(db, file_name, &note).expect("Upserting note meta failed");
-        if index_vector {
-            index_note_vector(db, &embeddings_model, &splitter, file_name, &note)
-                .expect("Upserting note vector failed");
+
+    let mut embedded_note_ids: Vec<String> = Vec::new();
+    // Sorted so the same file keeps an ID that's used by more than
+    // one note no matter what order the files are listed in
+    let mut documents = source.documents();
+    documents.sort_by(|a, b| a.path.cmp(&b.path));
+    let (mut indexed, mut unchanged, mut failed) = (0, 0, 0);
+    progress(IndexEvent::Started {
+        total: documents.len(),
+    });
+    for batch in documents.chunks(INDEX_BATCH_SIZE) {
+        // Notes that keep failing are skipped until they are retried
+        let mut batch_docs: Vec<(&Document, Option<IndexedFile>)> = Vec::new();
+        for doc in batch {
+            let file_name = doc.path.to_str().unwrap();
+            if is_quarantined(db, file_name)? {
+                tracing::warn!("Skipping quarantined note {}", file_name);
+                continue;
+            }
+            // Notes that haven't changed are skipped unless they need
+            // embedding e.g. after the vector format changed
+            let needs_embedding = embeddings_model.is_some() && !doc.encrypted;
+            let indexed_file = indexed_file(db, file_name)?
+                .filter(|f| !config.force && (!needs_embedding || f.has_embedding));
+            batch_docs.push((doc, indexed_file));
         }
-        if index_full_text {
-            index_note_full_text(&mut index_writer, &schema, file_name, &note)
-                .expect("Updating full text search failed");
+        // Reading and parsing are done in parallel since decrypting
+        // and parsing large notes is slow. Everything that writes to
+        // the db or index is done in order after.
+        let notes: Vec<anyhow::Result<ReadNote>> = batch_docs
+            .par_iter()
+            .map(|(doc, indexed_file)| {
+                read_changed_note(source, doc, config, indexed_file.as_ref())
+            })
+            .collect();
+
+        let mut pending: Vec<PendingEmbedding> = Vec::new();
+        for ((doc, _), note) in batch_docs.iter().zip(notes) {
+            let file_name = doc.path.to_str().unwrap();
+            let note = match note {
+                Ok(ReadNote::Unchanged { file_mtime }) => {
+                    // Remember the new modified time so the file isn't
+                    // read again next time
+                    let updated = db.execute(
+                        "UPDATE note_meta SET file_mtime = ?1 WHERE file_name = ?2 AND type = 'note'",
+                        rusqlite::params![file_mtime, file_name],
+                    )?;
+                    // Another file in the batch may have taken the
+                    // note's ID in which case it's indexed after all
+                    if updated == 0 {
+                        read_and_parse_note(source, doc, config)
+                    } else {
+                        clear_index_error(db, file_name)?;
+                        unchanged += 1;
+                        progress(IndexEvent::Unchanged {
+                            path: file_name.to_string(),
+                        });
+                        continue;
+                    }
+                }
+                Ok(ReadNote::Changed(note)) => Ok(*note),
+                Err(err) => Err(err),
+            };
+            let result = note.and_then(|mut note| {
+                // Notes with the same ID would replace each other so
+                // the first file by path keeps it
+                if let Some(other) = conflicting_file(
+                    db,
+                    &note.id,
+                    file_name,
+                    &config.keywords,
+                    config.age_identity_path.as_deref(),
+                )? {
+                    if other.as_str() < file_name {
+                        record_id_conflict(db, &note.id, file_name, &other)?;
+                        return Err(anyhow::anyhow!(
+                            "Duplicate ID {} is also used by {}",
+                            note.id,
+                            other
+                        ));
+                    }
+                    record_id_conflict(db, &note.id, &other, file_name)?;
+                }
+
+                // The file is only remembered as unchanged once the
+                // full text is indexed, otherwise the next run would
+                // skip it
+                if !index_full_text {
+                    let previous = indexed_file(db, file_name)?;
+                    note.file_mtime = previous.as_ref().and_then(|f| f.file_mtime);
+                    note.content_hash = previous.map(|f| f.content_hash);
+                }
+
+                // Always update the meta DB otherwise it's possible for
+                // the other indices to diverge which will eventually
+                // break search
+                let previous = previous_body(db, &note.id)?;
+                index_note_meta(db, file_name, &note)?;
+                // Encrypted notes don't have a body to compare
+                if !note.encrypted {
+                    record_note_change(
+                        db,
+                        &note.id,
+                        file_name,
+                        &note.title,
+                        previous.as_deref(),
+                        &note.body,
+                        &config.keywords,
+                    )?;
+                }
+                index_note_links(db, &note.id, &note.links)?;
+                index_clock_entries(db, &note.id, &note.clocks)?;
+                index_note_dates(db, &note.id, &note.dates)?;
+                let stale_highlights = index_highlights(
+                    db,
+                    file_name,
+                    note.tags.as_deref(),
+                    &note.collection,
+                    &note.id,
+                    &note.highlights,
+                )?;
+                let stale_headings =
+                    index_headings(db, file_name, &note.collection, &note.id, &note.headings)?;
+                let stale_attachments = index_attachments(
+                    db,
+                    file_name,
+                    &note.collection,
+                    note.tags.as_deref(),
+                    &note.id,
+                    &note.attachments,
+                )?;
+                // Encrypted notes don't have a body to embed.
+                // Embeddings are generated for the whole batch at
+                // once.
+                let embedded = embeddings_model.is_some() && !note.encrypted;
+                if embedded {
+                    pending.push(pending_embedding(
+                        db, &splitter, &note.id, file_name, &note.body,
+                    )?);
+                } else if !note.encrypted {
+                    // Forget the chunks that were embedded so the note
+                    // isn't skipped as unchanged the next time
+                    // embeddings are generated
+                    db.execute("DELETE FROM vec_chunk WHERE note_meta_id = ?1", [&note.id])?;
+                }
+                if index_full_text {
+                    index_note_full_text(index_writer, &schema, config, file_name, &note)?;
+                    index_highlights_full_text(
+                        index_writer,
+                        file_name,
+                        note.tags.as_deref(),
+                        &note.collection,
+                        &note.highlights,
+                        &stale_highlights,
+                    )?;
+                    index_headings_full_text(
+                        index_writer,
+                        file_name,
+                        &note.collection,
+                        &note.headings,
+                        &stale_headings,
+                    )?;
+                    index_attachments_full_text(
+                        index_writer,
+                        file_name,
+                        &note.collection,
+                        note.tags.as_deref(),
+                        &note.attachments,
+                        &stale_attachments,
+                    )?;
+                }
+                Ok(embedded.then_some(note.id))
+            });
+            match result {
+                Ok(embedded_id) => {
+                    clear_index_error(db, file_name)?;
+                    clear_id_conflict(db, file_name)?;
+                    embedded_note_ids.extend(embedded_id);
+                    indexed += 1;
+                    progress(IndexEvent::Indexed {
+                        path: file_name.to_string(),
+                    });
+                }
+                Err(err) => {
+                    tracing::warn!("Indexing note {} failed: {}", file_name, err);
+                    record_index_error(db, file_name, &err.to_string())?;
+                    failed += 1;
+                    progress(IndexEvent::Failed {
+                        path: file_name.to_string(),
+                        error: err.to_string(),
+                    });
+                }
+            }
         }
+        if let Some(model) = &embeddings_model {
+            embed_pending(db, model, &pending, progress)?;
+        }
+    }
+    // Notes that were deleted would otherwise show up in search
+    // results forever
+    let indexed_files: Vec<String> = db
+        .prepare("SELECT DISTINCT file_name FROM note_meta WHERE type IN ('note', 'task')")?
+        .query_map([], |r| r.get(0))?
+        .collect::<Result<_>>()?;
+    for file_name in source.deleted(&indexed_files) {
+        remove_note_file(db, index_writer, &file_name)?;
+        progress(IndexEvent::Removed { path: file_name });
     }
+
     index_writer
         .commit()
         .expect("Full text search index failed to commit");
 
+    // Mentions can point to any note so this looks at all of them
+    // rather than just the ones that changed
+    if config.infer_links {
+        index_inferred_links(db)?;
+    }
+
+    // Refresh related notes for just the notes that changed now that
+    // all of the embeddings are stored
+    if index_vector {
+        for note_id in embedded_note_ids.iter() {
+            index_note_related(db, note_id, 5).expect("Updating related notes failed");
+        }
+    }
+    progress(IndexEvent::Finished {
+        indexed,
+        unchanged,
+        failed,
+    });
+
     Ok(())
 }
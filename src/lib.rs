@@ -6,3 +6,35 @@ pub mod git;
 pub mod db;
 pub mod source;
 pub mod export;
+pub mod feeds;
+pub mod recurrence;
+pub mod bookmarks;
+pub mod capture;
+pub mod sync;
+pub mod ical;
+pub mod links;
+pub mod tasks;
+pub mod share;
+pub mod engine;
+pub mod refile;
+pub mod clock;
+pub mod quarantine;
+pub mod ann;
+pub mod highlights;
+pub mod favorites;
+pub mod audit;
+pub mod diff;
+pub mod dates;
+pub mod changes;
+pub mod lint;
+pub mod conflicts;
+pub mod reading;
+pub mod meetings;
+pub mod markdown;
+pub mod headings;
+pub mod runs;
+pub mod attachments;
+pub mod tags;
+pub mod hash;
+
+pub use engine::Indexer;
@@ -0,0 +1,365 @@
+/// Links between notes using org-id links like `[[id:abc][Note]]` or
+/// links by title like `[[Some note]]`
+use std::collections::{BTreeSet, HashMap};
+
+use orgize::ast::Link;
+use orgize::export::{from_fn_with_ctx, Container, Event, HtmlEscape, HtmlExport, Traverser};
+use orgize::rowan::ast::AstNode;
+use orgize::Org;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+
+/// The note a link points to
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkTarget {
+    Id(String),
+    Title(String),
+}
+
+impl LinkTarget {
+    /// Returns the target of a link path if it links to another
+    /// note. Links with a protocol other than `id:` (`https:`,
+    /// `file:`, etc.) and links to headings in the same file are
+    /// ignored.
+    ///
+    /// ```rust
+    /// use indexer::links::LinkTarget;
+    ///
+    /// assert_eq!(LinkTarget::from_path("id:abc"), Some(LinkTarget::Id("abc".to_string())));
+    /// assert_eq!(LinkTarget::from_path("My note"), Some(LinkTarget::Title("My note".to_string())));
+    /// assert_eq!(LinkTarget::from_path("https://example.com"), None);
+    /// assert_eq!(LinkTarget::from_path("*Heading"), None);
+    /// ```
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.trim();
+        if let Some(id) = path.strip_prefix("id:") {
+            return Some(Self::Id(id.trim().to_string()));
+        }
+        if path.is_empty() || path.contains(':') || path.starts_with(['*', '#', '/', '.', '~', '('])
+        {
+            return None;
+        }
+        Some(Self::Title(path.to_string()))
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Id(_) => "id",
+            Self::Title(_) => "title",
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            Self::Id(v) | Self::Title(v) => v,
+        }
+    }
+
+    /// Returns the ID of the note or task the link points to if it
+    /// exists. Title links match notes case insensitively.
+    pub fn resolve(&self, db: &Connection) -> Result<Option<String>> {
+        let sql = match self {
+            Self::Id(_) => "SELECT id FROM note_meta WHERE id = ?1",
+            Self::Title(_) => {
+                "SELECT id FROM note_meta WHERE type = 'note' AND lower(title) = lower(?1) LIMIT 1"
+            }
+        };
+        db.query_row(sql, [self.value()], |r| r.get(0)).optional()
+    }
+}
+
+/// Returns the unique links to other notes in the document
+pub fn note_links(org: &Org) -> Vec<LinkTarget> {
+    let mut links: Vec<LinkTarget> = org
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Link::cast)
+        .filter_map(|l| LinkTarget::from_path(&l.path()))
+        .collect();
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Replace the outgoing links of the note. Inferred links are removed
+/// too until mentions are detected again.
+pub fn index_note_links(db: &mut Connection, note_id: &str, links: &[LinkTarget]) -> Result<()> {
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM note_link WHERE note_meta_id = ?1", [note_id])?;
+    {
+        let mut stmt =
+            tx.prepare("INSERT INTO note_link(note_meta_id, kind, target) VALUES (?1, ?2, ?3)")?;
+        for link in links {
+            stmt.execute([note_id, link.kind(), link.value()])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Render the org document as HTML with links to other notes
+/// replaced by links to the note viewer. Links that can't be resolved
+/// are rendered as is.
+pub fn render_html(db: &Connection, org: &Org) -> String {
+    let mut html = HtmlExport::default();
+    let mut handler = from_fn_with_ctx(|event, ctx| {
+        if let Event::Enter(Container::Link(link)) = &event {
+            let resolved = LinkTarget::from_path(&link.path())
+                .and_then(|target| target.resolve(db).ok().flatten());
+            if let Some(id) = resolved {
+                html.push_str(format!(r#"<a href="/notes/{}/view">"#, HtmlEscape(&id)));
+                if link.has_description() {
+                    for elem in link.description() {
+                        html.element(elem, ctx);
+                    }
+                } else {
+                    let path = link.path();
+                    html.push_str(format!("{}", HtmlEscape(path.trim_start_matches("id:"))));
+                }
+                html.push_str("</a>");
+                return ctx.skip();
+            }
+        }
+        html.event(event, ctx);
+    });
+    org.traverse(&mut handler);
+    html.finish()
+}
+
+/// A link to a note that doesn't exist
+#[derive(Serialize)]
+pub struct BrokenLink {
+    id: String,
+    title: String,
+    kind: String,
+    target: String,
+}
+
+/// Returns all links that don't point to an indexed note or task.
+/// Links to headings that have an ID but are not tasks are reported
+/// as broken since they aren't indexed.
+pub fn broken_links(db: &Connection) -> Result<Vec<BrokenLink>> {
+    db.prepare(
+        r"
+          SELECT
+            l.note_meta_id,
+            n.title,
+            l.kind,
+            l.target
+          FROM note_link l
+          JOIN note_meta n ON n.id = l.note_meta_id
+          WHERE NOT EXISTS (
+            SELECT 1 FROM note_meta m
+            WHERE (l.kind = 'id' AND m.id = l.target)
+            OR (l.kind = 'title' AND m.type = 'note' AND lower(m.title) = lower(l.target))
+          )
+          ORDER BY n.title, l.target
+        ",
+    )?
+    .query_map([], |r| {
+        Ok(BrokenLink {
+            id: r.get(0)?,
+            title: r.get(1)?,
+            kind: r.get(2)?,
+            target: r.get(3)?,
+        })
+    })?
+    .collect()
+}
+
+/// A note without any links to or from other notes
+#[derive(Serialize)]
+pub struct OrphanNote {
+    id: String,
+    title: String,
+    file_name: String,
+}
+
+/// Returns notes that don't link to anything and aren't linked to
+/// from any other note
+pub fn orphan_notes(db: &Connection) -> Result<Vec<OrphanNote>> {
+    db.prepare(
+        r"
+          SELECT
+            n.id,
+            n.title,
+            n.file_name
+          FROM note_meta n
+          WHERE n.type = 'note'
+          AND NOT EXISTS (SELECT 1 FROM note_link l WHERE l.note_meta_id = n.id)
+          AND NOT EXISTS (
+            SELECT 1 FROM note_link l
+            WHERE l.note_meta_id != n.id
+            AND (
+              (l.kind = 'id' AND l.target = n.id)
+              OR (l.kind = 'title' AND lower(l.target) = lower(n.title))
+            )
+          )
+          ORDER BY n.title
+        ",
+    )?
+    .query_map([], |r| {
+        Ok(OrphanNote {
+            id: r.get(0)?,
+            title: r.get(1)?,
+            file_name: r.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// Titles shorter than this aren't detected as mentions since they
+/// tend to match common words
+const MIN_MENTION_LEN: usize = 4;
+
+/// Split org-roam aliases which are quoted if they contain spaces
+/// e.g. `"Go lang" Golang`
+///
+/// ```rust
+/// use indexer::links::parse_aliases;
+///
+/// assert_eq!(parse_aliases(r#""Go lang" Golang"#), vec!["Go lang", "Golang"]);
+/// ```
+pub fn parse_aliases(aliases: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = aliases.trim();
+    while !rest.is_empty() {
+        let (alias, remaining) = if let Some(quoted) = rest.strip_prefix('"') {
+            quoted.split_once('"').unwrap_or((quoted, ""))
+        } else {
+            rest.split_once(char::is_whitespace).unwrap_or((rest, ""))
+        };
+        if !alias.trim().is_empty() {
+            result.push(alias.trim().to_string());
+        }
+        rest = remaining.trim_start();
+    }
+    result
+}
+
+// True if the needle appears in the text as whole words
+fn contains_words(text: &str, needle: &str) -> bool {
+    text.match_indices(needle).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + needle.len()..].chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric()) && !after.is_some_and(|c| c.is_alphanumeric())
+    })
+}
+
+/// Detect mentions of other notes' titles and aliases in the body of
+/// every note and store them as inferred links. Notes that are
+/// already explicitly linked are skipped. Replaces all previously
+/// inferred links and returns the number of links found.
+pub fn index_inferred_links(db: &mut Connection) -> Result<usize> {
+    let notes: Vec<(String, String, String, Option<String>)> = db
+        .prepare("SELECT id, title, body, props FROM note_meta WHERE type = 'note'")?
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))?
+        .collect::<Result<_>>()?;
+
+    // Every name a note can be mentioned by
+    let mut names: Vec<(String, String)> = Vec::new();
+    let mut ids_by_title: HashMap<String, String> = HashMap::new();
+    for (id, title, _, props) in notes.iter() {
+        ids_by_title
+            .entry(title.to_lowercase())
+            .or_insert(id.clone());
+        let aliases = props
+            .as_deref()
+            .and_then(|p| serde_json::from_str::<serde_json::Value>(p).ok())
+            .and_then(|p| p["roam_aliases"].as_str().map(parse_aliases))
+            .unwrap_or_default();
+        for name in std::iter::once(title.clone()).chain(aliases) {
+            let name = name.trim().to_lowercase();
+            if name.chars().count() >= MIN_MENTION_LEN {
+                names.push((id.clone(), name));
+            }
+        }
+    }
+
+    let explicit: BTreeSet<(String, String)> = db
+        .prepare("SELECT note_meta_id, kind, target FROM note_link WHERE inferred = 0")?
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                r.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter_map(|(note_id, kind, target)| match kind.as_str() {
+            "title" => ids_by_title
+                .get(&target.to_lowercase())
+                .map(|id| (note_id, id.clone())),
+            _ => Some((note_id, target)),
+        })
+        .collect();
+
+    let tx = db.transaction()?;
+    tx.execute("DELETE FROM note_link WHERE inferred = 1", [])?;
+    let mut count = 0;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO note_link(note_meta_id, kind, target, inferred) VALUES (?1, 'id', ?2, 1)",
+        )?;
+        for (id, _, body, _) in notes.iter() {
+            let body = body.to_lowercase();
+            let mentioned: BTreeSet<&String> = names
+                .iter()
+                .filter(|(target, name)| {
+                    target != id
+                        && !explicit.contains(&(id.clone(), target.clone()))
+                        && contains_words(&body, name)
+                })
+                .map(|(target, _)| target)
+                .collect();
+            for target in mentioned {
+                count += stmt.execute([id, target])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// A note or task that links to another note
+#[derive(Serialize)]
+pub struct Backlink {
+    id: String,
+    title: String,
+    file_name: String,
+    /// Either `explicit` or `inferred` from a mention of the title
+    r#type: String,
+}
+
+/// Returns the notes and tasks that link to the note
+pub fn backlinks(db: &Connection, id: &str) -> Result<Vec<Backlink>> {
+    db.prepare(
+        r"
+          SELECT
+            n.id,
+            n.title,
+            n.file_name,
+            CASE WHEN max(l.inferred) = 1 AND min(l.inferred) = 1 THEN 'inferred' ELSE 'explicit' END
+          FROM note_link l
+          JOIN note_meta n ON n.id = l.note_meta_id
+          JOIN note_meta t ON t.id = ?1
+          WHERE l.note_meta_id != t.id
+          AND (
+            (l.kind = 'id' AND l.target = t.id)
+            OR (l.kind = 'title' AND t.type = 'note' AND lower(l.target) = lower(t.title))
+          )
+          GROUP BY n.id
+          ORDER BY n.title
+        ",
+    )?
+    .query_map([id], |r| {
+        Ok(Backlink {
+            id: r.get(0)?,
+            title: r.get(1)?,
+            file_name: r.get(2)?,
+            r#type: r.get(3)?,
+        })
+    })?
+    .collect()
+}
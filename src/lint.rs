@@ -0,0 +1,210 @@
+/// Checks notes for problems that make them fail to index or show up
+/// wrong in search results e.g. two notes with the same ID where one
+/// silently replaces the other.
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use super::indexing::{parse_org, word_count, TaskKeywords};
+use super::source::SourceProvider;
+
+/// Notes without tags that are longer than this many words are hard
+/// to find and probably need to be split up
+pub const LONG_NOTE_WORDS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    MissingId,
+    MissingTitle,
+    EmptyBody,
+    MalformedPropertyDrawer,
+    DuplicateId,
+    LongUntagged,
+}
+
+/// A problem with a note
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LintIssue {
+    pub kind: LintKind,
+    pub message: String,
+    /// Line of the note the problem is on starting from 1, if any
+    pub line: Option<usize>,
+}
+
+impl LintIssue {
+    fn new(kind: LintKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            line: None,
+        }
+    }
+}
+
+/// Problems found in a note
+#[derive(Debug, Serialize)]
+pub struct NoteLint {
+    pub file_name: String,
+    /// The org-id of the note if it has one
+    pub id: Option<String>,
+    pub issues: Vec<LintIssue>,
+}
+
+/// Check a single note. Duplicate IDs can only be found by checking
+/// all of the notes with `lint_sources`.
+///
+/// ```rust
+/// use indexer::indexing::TaskKeywords;
+/// use indexer::lint::{lint_note, LintKind};
+///
+/// let lint = lint_note("a.org", "#+TITLE: Hello\n\nSome words.\n", &TaskKeywords::default());
+/// assert_eq!(lint.id, None);
+/// assert_eq!(lint.issues[0].kind, LintKind::MissingId);
+///
+/// let lint = lint_note("b.org", ":PROPERTIES:\n:ID: b\n#+TITLE: Hello\n", &TaskKeywords::default());
+/// let kinds: Vec<LintKind> = lint.issues.iter().map(|i| i.kind).collect();
+/// assert_eq!(kinds, vec![LintKind::MalformedPropertyDrawer]);
+/// assert_eq!(lint.issues[0].line, Some(1));
+/// ```
+pub fn lint_note(file_name: &str, content: &str, keywords: &TaskKeywords) -> NoteLint {
+    let org = parse_org(content, keywords);
+    let mut issues = property_drawer_issues(content);
+
+    let id = org
+        .document()
+        .properties()
+        .and_then(|p| p.get("ID"))
+        .map(|id| id.to_string().trim().to_string())
+        .filter(|id| !id.is_empty());
+    // A malformed drawer is the reason the ID is missing, and the rest
+    // of the note is read as part of the drawer, so it's only reported
+    // once
+    let malformed = !issues.is_empty();
+    if id.is_none() && !malformed {
+        issues.push(LintIssue::new(
+            LintKind::MissingId,
+            "Missing :ID: in the property drawer at the top of the note",
+        ));
+    }
+
+    if org.title().is_none_or(|t| t.trim().is_empty()) {
+        issues.push(LintIssue::new(LintKind::MissingTitle, "Missing #+TITLE:"));
+    }
+
+    let words = word_count(content);
+    if words == 0 && !malformed {
+        issues.push(LintIssue::new(LintKind::EmptyBody, "Note has no content"));
+    }
+
+    let has_tags = org
+        .keywords()
+        .any(|k| k.key().eq_ignore_ascii_case("FILETAGS") && !k.value().trim().is_empty());
+    if words > LONG_NOTE_WORDS && !has_tags {
+        issues.push(LintIssue::new(
+            LintKind::LongUntagged,
+            format!(
+                "Note has {} words and no #+FILETAGS: which makes it hard to find",
+                words
+            ),
+        ));
+    }
+
+    NoteLint {
+        file_name: file_name.to_string(),
+        id,
+        issues,
+    }
+}
+
+// Property drawers that aren't closed with `:END:` before a line that
+// isn't a property. Org-mode ignores the whole drawer when that
+// happens.
+fn property_drawer_issues(content: &str) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    // Line the open drawer starts on
+    let mut open: Option<usize> = None;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        match open {
+            None if line.eq_ignore_ascii_case(":PROPERTIES:") => open = Some(i + 1),
+            Some(_) if line.eq_ignore_ascii_case(":END:") => open = None,
+            Some(start) if !is_property(line) => {
+                issues.push(unclosed_drawer(start, Some(i + 1)));
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = open {
+        issues.push(unclosed_drawer(start, None));
+    }
+    issues
+}
+
+fn unclosed_drawer(start: usize, end: Option<usize>) -> LintIssue {
+    let message = match end {
+        Some(end) => format!("Property drawer is missing :END: before line {}", end),
+        None => "Property drawer is missing :END:".to_string(),
+    };
+    LintIssue {
+        kind: LintKind::MalformedPropertyDrawer,
+        message,
+        line: Some(start),
+    }
+}
+
+// A line like `:KEY: value` or `:KEY+: value`
+fn is_property(line: &str) -> bool {
+    line.strip_prefix(':')
+        .and_then(|rest| rest.split_once(':'))
+        .is_some_and(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+}
+
+/// Check every note from the sources including notes that share an
+/// ID, even in different sources. Only notes with problems are
+/// returned ordered by file name. Notes that can't be read are
+/// skipped.
+pub fn lint_sources(sources: &[&dyn SourceProvider], keywords: &TaskKeywords) -> Vec<NoteLint> {
+    let mut lints: Vec<NoteLint> = sources
+        .iter()
+        .flat_map(|source| source.documents().into_iter().map(move |doc| (source, doc)))
+        .filter_map(|(source, doc)| {
+            let content = source
+                .read(&doc)
+                .map_err(|e| tracing::warn!("Skipping lint of {}: {}", doc.path.display(), e))
+                .ok()?;
+            Some(lint_note(&doc.path.to_string_lossy(), &content, keywords))
+        })
+        .collect();
+    lints.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+    let mut files_by_id: HashMap<String, Vec<String>> = HashMap::new();
+    for lint in lints.iter() {
+        if let Some(id) = &lint.id {
+            files_by_id
+                .entry(id.clone())
+                .or_default()
+                .push(lint.file_name.clone());
+        }
+    }
+    for lint in lints.iter_mut() {
+        let Some(files) = lint.id.as_ref().and_then(|id| files_by_id.get(id)) else {
+            continue;
+        };
+        if files.len() > 1 {
+            let others: Vec<&str> = files
+                .iter()
+                .filter(|f| **f != lint.file_name)
+                .map(|f| f.as_str())
+                .collect();
+            lint.issues.push(LintIssue::new(
+                LintKind::DuplicateId,
+                format!("ID is also used by {}", others.join(", ")),
+            ));
+        }
+    }
+
+    lints.retain(|lint| !lint.issues.is_empty());
+    lints
+}
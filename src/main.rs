@@ -1,15 +1,21 @@
+use std::cell::Cell;
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
 
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
 use serde_json::json;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use indexer::search::search_notes;
-use indexer::indexing::index_all;
-use indexer::git::{maybe_clone_repo, maybe_pull_and_reset_repo};
-use indexer::db::{migrate_db, vector_db};
+use indexer::search::{search_notes, SearchExclusions, SearchFilters, SearchIndex};
+use indexer::indexing::{
+    index_all, index_all_with_progress, index_writer, Collection, IndexConfig, IndexEvent,
+};
+use indexer::source::OrgDirectory;
+use indexer::git::{maybe_clone_repo, maybe_pull_and_reset_repo, GitAuth};
+use indexer::db::{migrate_db, set_vector_format, vector_db, VectorFormat};
 use indexer::server;
 
 #[derive(Subcommand)]
@@ -32,6 +38,31 @@ enum Command {
         full_text: bool,
         #[arg(long, default_value = "false")]
         vector: bool,
+        /// Index notes even if they haven't changed since they were
+        /// last indexed
+        #[arg(long, default_value = "false")]
+        force: bool,
+    },
+    /// Set up storage, clone the notes, and index them for the first
+    /// time. Asks for any settings that aren't passed as flags or set
+    /// in the environment.
+    Init {
+        /// URL of the notes repo, defaults to INDEXER_NOTES_REPO_URL
+        #[arg(long)]
+        repo_url: Option<String>,
+        /// SSH key for the notes repo, defaults to
+        /// INDEXER_NOTES_DEPLOY_KEY_PATH
+        #[arg(long)]
+        deploy_key_path: Option<String>,
+        /// Use the notes already in the notes directory
+        #[arg(long, default_value = "false")]
+        no_clone: bool,
+        /// Skip the first full index
+        #[arg(long, default_value = "false")]
+        no_index: bool,
+        /// Don't ask for settings that are missing
+        #[arg(long, default_value = "false")]
+        non_interactive: bool,
     },
     /// Query the search index
     Query {
@@ -54,46 +85,159 @@ struct Cli {
     command: Option<Command>,
 }
 
+// Credentials for the notes repo which are required to clone and
+// pull it
+fn git_auth() -> Result<GitAuth> {
+    let auth = GitAuth::from_env();
+    if !auth.is_configured() {
+        return Err(anyhow!(
+            "Missing env var INDEXER_NOTES_DEPLOY_KEY_PATH or INDEXER_NOTES_GIT_TOKEN"
+        ));
+    }
+    Ok(auth)
+}
+
+// Create the db, run migrations, and create the index directory.
+// Returns true if every note needs to be embedded again.
+fn init_storage(vec_db_path: &str, index_path: &str) -> bool {
+    // Initialize the vector DB
+    fs::create_dir_all(vec_db_path)
+        .unwrap_or_else(|err| println!("Ignoring vector DB create failed: {}", err));
+
+    let db = vector_db(vec_db_path).expect("Failed to connect to db");
+    let reembed = migrate_db(&db).expect("DB migration failed");
+
+    // Create the index directory if it doesn't already exist
+    fs::create_dir_all(index_path)
+        .unwrap_or_else(|err| println!("Ignoring index directory create failed: {}", err));
+    reembed
+}
+
+// Clone the notes repo and any other collections of notes
+fn clone_notes(auth: &GitAuth, repo_url: &str, notes_path: &str) {
+    maybe_clone_repo(auth, repo_url, notes_path);
+    for collection in Collection::from_env() {
+        if let Some(remote) = &collection.remote {
+            maybe_clone_repo(auth, remote, &collection.path);
+        }
+    }
+}
+
+// Ask for a setting on the terminal. Returns `None` if the answer is
+// blank or there is no one to ask.
+fn prompt(question: &str, interactive: bool) -> Option<String> {
+    if !interactive {
+        return None;
+    }
+    print!("{}: ", question);
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).ok()?;
+    let answer = answer.trim();
+    (!answer.is_empty()).then(|| answer.to_string())
+}
+
+// Settings needed to run the indexer again later. Secrets are left
+// commented out so they aren't written to disk.
+fn env_file(storage_path: &Path, repo_url: Option<&str>, auth: &GitAuth) -> String {
+    let setting = |name: &str, value: Option<&str>, placeholder: &str| match value {
+        Some(value) => format!("{}={}\n", name, value),
+        None => format!("# {}={}\n", name, placeholder),
+    };
+    [
+        "# Settings for the indexer. Load them with `set -a; . ./indexer.env; set +a`\n".to_string(),
+        setting(
+            "INDEXER_STORAGE_PATH",
+            Some(&storage_path.to_string_lossy()),
+            "",
+        ),
+        setting("INDEXER_NOTES_REPO_URL", repo_url, "git@github.com:me/notes.git"),
+        setting(
+            "INDEXER_NOTES_DEPLOY_KEY_PATH",
+            auth.deploy_key_path.as_deref(),
+            "/path/to/deploy_key",
+        ),
+        setting("INDEXER_NOTES_GIT_TOKEN", None, "token"),
+    ]
+    .concat()
+}
+
+// Print indexing progress on the terminal
+fn print_progress(count: &Cell<usize>, event: IndexEvent) {
+    match event {
+        IndexEvent::Started { total } => {
+            count.set(0);
+            println!("Indexing {} notes", total);
+        }
+        IndexEvent::Embedding { .. } => {}
+        IndexEvent::Indexed { path } => {
+            count.set(count.get() + 1);
+            println!("[{}] {}", count.get(), path);
+        }
+        IndexEvent::Failed { path, error } => {
+            count.set(count.get() + 1);
+            println!("[{}] {} failed: {}", count.get(), path, error);
+        }
+        IndexEvent::Removed { path } => {
+            println!("Removed {}", path);
+        }
+        IndexEvent::Unchanged { .. } => {
+            count.set(count.get() + 1);
+        }
+        IndexEvent::Finished {
+            indexed,
+            unchanged,
+            failed,
+        } => {
+            println!(
+                "Indexed {} notes, {} unchanged, {} failed",
+                indexed, unchanged, failed
+            );
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
 
     let storage_path = env::var("INDEXER_STORAGE_PATH").unwrap_or("./".to_string());
-    let index_path = format!("{}/index", storage_path);
-    let notes_path = format!("{}/notes", storage_path);
-    let vec_db_path = format!("{}/db", storage_path);
+    let storage_path = Path::new(&storage_path);
+    let index_path = storage_path.join("index").to_string_lossy().to_string();
+    let notes_path = storage_path.join("notes").to_string_lossy().to_string();
+    let vec_db_path = storage_path.join("db").to_string_lossy().to_string();
 
     // Default command
+    let mut migrated_embeddings = false;
     if args.init {
-        // Initialize the vector DB
-        fs::create_dir_all(&vec_db_path)
-            .unwrap_or_else(|err| println!("Ignoring vector DB create failed: {}", err));
-
-        let db = vector_db(&vec_db_path).expect("Failed to connect to db");
-        migrate_db(&db).expect("DB migration failed");
-
-        // Create the index directory if it doesn't already exist
-        fs::create_dir_all(&index_path)
-            .unwrap_or_else(|err| println!("Ignoring index directory create failed: {}", err));
+        migrated_embeddings = init_storage(&vec_db_path, &index_path);
 
         // Clone the notes repo and index it
         let repo_url =
             env::var("INDEXER_NOTES_REPO_URL").expect("Missing env var INDEXER_NOTES_REPO_URL");
-        let deploy_key_path = env::var("INDEXER_NOTES_DEPLOY_KEY_PATH")
-            .expect("Missing env var INDEXER_NOTES_REPO_URL");
-        maybe_clone_repo(&deploy_key_path, &repo_url, &notes_path);
+        let auth = git_auth()?;
+        clone_notes(&auth, &repo_url, &notes_path);
     }
 
     // You can check for the existence of subcommands, and if found use their
     // matches just as you would the top level cmd
     match args.command {
         Some(Command::Serve { host, port }) => {
-            server::serve(host, port, notes_path.clone(), index_path, vec_db_path).await;
+            server::serve(
+                host,
+                port,
+                notes_path.clone(),
+                index_path,
+                vec_db_path,
+                migrated_embeddings,
+            )
+            .await;
         }
         Some(Command::Index {
             all,
             full_text,
             vector,
+            force,
         }) => {
             if !all && !full_text && !vector {
                 return Err(anyhow!(
@@ -110,32 +254,134 @@ async fn main() -> Result<()> {
                 .with(tracing_subscriber::fmt::layer())
                 .init();
 
-            // Clone the notes repo
-            let deploy_key_path = env::var("INDEXER_NOTES_DEPLOY_KEY_PATH")
-                .expect("Missing env var INDEXER_NOTES_REPO_URL");
-            maybe_pull_and_reset_repo(&deploy_key_path, &notes_path);
-
+            let auth = git_auth()?;
             let mut db = vector_db(&vec_db_path).expect("Failed to connect to db");
+            let reembed = set_vector_format(&db, VectorFormat::from_env())
+                .expect("Failed to set vector format")
+                || migrated_embeddings;
+            if reembed && !vector && !all {
+                tracing::warn!("Embeddings were cleared, run with --vector to embed notes again");
+            }
+            let config = IndexConfig {
+                force,
+                ..IndexConfig::from_env()
+            };
+            let mut index_writer = index_writer(&index_path);
 
-            if full_text {
-                // Index for full text search
-                index_all(&mut db, &index_path, &notes_path, true, false, None)
-                    .expect("Indexing failed");
+            // Index the main notes directory and any other collections
+            let paths: Vec<String> = std::iter::once(notes_path.clone())
+                .chain(config.collections.iter().map(|c| c.path.clone()))
+                .collect();
+            for path in paths {
+                // Pull the latest notes
+                maybe_pull_and_reset_repo(&auth, &path);
+
+                let source = OrgDirectory::new(&path, config.age_identity_path.clone())
+                    .with_walk(config.walk.clone());
+
+                if full_text {
+                    // Index for full text search
+                    index_all(&mut db, &mut index_writer, &source, true, false, &config)
+                        .expect("Indexing failed");
+                }
+                if vector {
+                    // Index for vector search
+                    index_all(&mut db, &mut index_writer, &source, false, true, &config)
+                        .expect("Indexing failed");
+                }
+
+                if all {
+                    index_all(&mut db, &mut index_writer, &source, true, true, &config)
+                        .expect("Indexing failed");
+                }
             }
-            if vector {
-                // Index for vector search
-                index_all(&mut db, &index_path, &notes_path, false, true, None)
-                    .expect("Indexing failed");
+        }
+        Some(Command::Init {
+            repo_url,
+            deploy_key_path,
+            no_clone,
+            no_index,
+            non_interactive,
+        }) => {
+            let interactive = !non_interactive && io::stdin().is_terminal();
+
+            println!("Creating db and index in {}", storage_path.display());
+            init_storage(&vec_db_path, &index_path);
+
+            let mut auth = GitAuth::from_env();
+            if deploy_key_path.is_some() {
+                auth.deploy_key_path = deploy_key_path;
+            }
+            let mut repo_url = repo_url.or_else(|| env::var("INDEXER_NOTES_REPO_URL").ok());
+            if !no_clone {
+                if repo_url.is_none() {
+                    repo_url = prompt("Notes repo URL", interactive);
+                }
+                let repo_url = repo_url.as_deref().ok_or_else(|| {
+                    anyhow!("Missing --repo-url or env var INDEXER_NOTES_REPO_URL")
+                })?;
+                if !auth.is_configured() {
+                    auth.deploy_key_path = prompt("Deploy key path for the notes repo", interactive);
+                }
+                if !auth.is_configured() {
+                    return Err(anyhow!(
+                        "Missing --deploy-key-path or env var INDEXER_NOTES_DEPLOY_KEY_PATH or INDEXER_NOTES_GIT_TOKEN"
+                    ));
+                }
+                println!("Cloning {} into {}", repo_url, notes_path);
+                clone_notes(&auth, repo_url, &notes_path);
+            }
+
+            // Keep any settings that were already written
+            let env_path = storage_path.join("indexer.env");
+            if !env_path.exists() {
+                fs::write(&env_path, env_file(storage_path, repo_url.as_deref(), &auth))?;
+                println!("Wrote settings to {}", env_path.display());
             }
 
-            if all {
-                index_all(&mut db, &index_path, &notes_path, true, true, None)
+            if !no_index {
+                let mut db = vector_db(&vec_db_path).expect("Failed to connect to db");
+                set_vector_format(&db, VectorFormat::from_env())
+                    .expect("Failed to set vector format");
+                let config = IndexConfig::from_env();
+                let mut index_writer = index_writer(&index_path);
+                let count = Cell::new(0);
+                let progress = |event| print_progress(&count, event);
+                let paths: Vec<String> = std::iter::once(notes_path.clone())
+                    .chain(config.collections.iter().map(|c| c.path.clone()))
+                    .collect();
+                for path in paths {
+                    let source = OrgDirectory::new(&path, config.age_identity_path.clone())
+                        .with_walk(config.walk.clone());
+                    index_all_with_progress(
+                        &mut db,
+                        &mut index_writer,
+                        &source,
+                        true,
+                        true,
+                        &config,
+                        &progress,
+                    )
                     .expect("Indexing failed");
+                }
             }
+
+            println!("Done! Run the server with `indexer serve`");
         }
         Some(Command::Query { term, vector }) => {
             let db = vector_db(&vec_db_path).expect("Failed to connect to db");
-            let results = search_notes(&index_path, &db, vector, &term, 20);
+            let exclusions = SearchExclusions::from_env();
+            let results = search_notes(
+                &SearchIndex::open(&index_path),
+                &db,
+                vector,
+                &term,
+                20,
+                None,
+                None,
+                &exclusions,
+                &SearchFilters::default(),
+            );
             println!(
                 "{}",
                 json!({
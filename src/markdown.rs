@@ -0,0 +1,217 @@
+/// Markdown notes e.g. from an Obsidian vault are converted to org-mode
+/// when they're read so they're indexed the same way as org notes.
+/// The ID, title, and tags come from the YAML frontmatter.
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+use super::indexing::escape_org;
+
+/// Returns true if the path is a Markdown file
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension().unwrap_or_default() == "md"
+}
+
+// Fields from the frontmatter
+#[derive(Default)]
+struct Frontmatter {
+    id: Option<String>,
+    title: Option<String>,
+    tags: Vec<String>,
+    // Any other fields which are kept as properties
+    props: Vec<(String, String)>,
+}
+
+/// Convert the Markdown note at the path to org-mode. Notes without
+/// an `id` in the frontmatter get one from the file name so it stays
+/// the same each time the note is indexed. The title defaults to the
+/// file name.
+///
+/// ```rust
+/// use std::path::Path;
+/// use indexer::markdown::markdown_to_org;
+///
+/// let md = "---\nid: abc\ntitle: Rust notes\ntags: [rust, async]\narea: work\n---\n# Runtimes\nSee [tokio](https://tokio.rs).\n\n```rust\n#[tokio::main]\n```\n* One\n";
+/// let org = markdown_to_org(md, Path::new("rust.md"));
+/// assert_eq!(
+///     org,
+///     ":PROPERTIES:\n:ID: abc\n:AREA: work\n:END:\n#+TITLE: Rust notes\n#+FILETAGS: rust async\n\n* Runtimes\nSee [[https://tokio.rs][tokio]].\n\n#+begin_src rust\n#[tokio::main]\n#+end_src\n- One\n"
+/// );
+/// ```
+pub fn markdown_to_org(content: &str, path: &Path) -> String {
+    let (frontmatter, body) = split_frontmatter(content);
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let id = frontmatter.id.clone().unwrap_or_else(|| {
+        let mut hasher = DefaultHasher::new();
+        file_name.hash(&mut hasher);
+        format!("md-{}", hasher.finish())
+    });
+    let title = frontmatter.title.clone().unwrap_or_else(|| {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    });
+
+    let mut org = format!(":PROPERTIES:\n:ID: {}\n", id);
+    for (key, value) in frontmatter.props.iter() {
+        org.push_str(&format!(":{}: {}\n", key.to_uppercase(), value));
+    }
+    org.push_str(&format!(":END:\n#+TITLE: {}\n", title));
+    if !frontmatter.tags.is_empty() {
+        // Tags are separated by spaces in org-mode
+        let tags: Vec<String> = frontmatter
+            .tags
+            .iter()
+            .map(|t| t.replace(' ', "_"))
+            .collect();
+        org.push_str(&format!("#+FILETAGS: {}\n", tags.join(" ")));
+    }
+    org.push('\n');
+    org.push_str(&markdown_body_to_org(body));
+    org
+}
+
+// Split the frontmatter between `---` lines from the rest of the note
+fn split_frontmatter(content: &str) -> (Frontmatter, &str) {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return (Frontmatter::default(), content);
+    };
+    let Some(end) = rest.find("\n---") else {
+        return (Frontmatter::default(), content);
+    };
+    // The body starts on the line after the closing `---`
+    let after = &rest[end + 1..];
+    let body = after.find('\n').map_or("", |i| &after[i + 1..]);
+    (parse_frontmatter(&rest[..end]), body)
+}
+
+// Only the simple YAML used in frontmatter is supported: `key: value`
+// fields and lists as `[a, b]` or `- a` lines
+fn parse_frontmatter(yaml: &str) -> Frontmatter {
+    let mut frontmatter = Frontmatter::default();
+    let mut list_key: Option<String> = None;
+    for line in yaml.lines() {
+        if let Some(item) = line.trim().strip_prefix("- ") {
+            if list_key.as_deref() == Some("tags") {
+                frontmatter.tags.push(unquote(item));
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        list_key = value.is_empty().then(|| key.clone());
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            "id" => frontmatter.id = Some(unquote(value)),
+            "title" => frontmatter.title = Some(unquote(value)),
+            "tags" => {
+                frontmatter.tags = value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split([',', ' '])
+                    .map(unquote)
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            }
+            _ => frontmatter.props.push((key, unquote(value))),
+        }
+    }
+    frontmatter
+}
+
+fn unquote(s: &str) -> String {
+    s.trim().trim_matches(['"', '\'']).trim().to_string()
+}
+
+// Convert the Markdown syntax that means something different in
+// org-mode: headings, code blocks, links, and `*` lists which would
+// otherwise be read as headlines
+fn markdown_body_to_org(body: &str) -> String {
+    let mut org = String::new();
+    let mut in_code = false;
+    for line in body.lines() {
+        let trimmed = line.trim_start();
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            let lang = lang.trim();
+            if in_code {
+                org.push_str("#+end_src\n");
+            } else if lang.is_empty() {
+                org.push_str("#+begin_src\n");
+            } else {
+                org.push_str(&format!("#+begin_src {}\n", lang));
+            }
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            org.push_str(&escape_org(line));
+            org.push('\n');
+            continue;
+        }
+
+        let level = line.chars().take_while(|c| *c == '#').count();
+        let converted = if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+            format!("{}{}", "*".repeat(level), &line[level..])
+        } else if let Some(item) = line.strip_prefix("* ") {
+            format!("- {}", item)
+        } else {
+            line.to_string()
+        };
+        org.push_str(&convert_links(&converted));
+        org.push('\n');
+    }
+    // Close a code block that was never closed so the rest of the
+    // note isn't swallowed by it
+    if in_code {
+        org.push_str("#+end_src\n");
+    }
+    org
+}
+
+// `[text](url)` links become `[[url][text]]` and images become
+// `[[src]]`
+fn convert_links(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let is_image = rest[..start].ends_with('!');
+        let after = &rest[start + 1..];
+        let link = after.find("](").and_then(|mid| {
+            let url_start = mid + 2;
+            after[url_start..]
+                .find(')')
+                .map(|end| (&after[..mid], &after[url_start..url_start + end]))
+        });
+        match link {
+            // Text with brackets in it is probably not a link
+            Some((text, url)) if !text.contains(['[', ']']) && !url.contains(' ') => {
+                let prefix = if is_image {
+                    &rest[..start - 1]
+                } else {
+                    &rest[..start]
+                };
+                out.push_str(prefix);
+                if is_image || text.is_empty() {
+                    out.push_str(&format!("[[{}]]", url));
+                } else {
+                    out.push_str(&format!("[[{}][{}]]", url, text));
+                }
+                rest = &after[text.len() + 2 + url.len() + 1..];
+            }
+            _ => {
+                out.push_str(&rest[..start + 1]);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
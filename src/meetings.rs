@@ -0,0 +1,250 @@
+/// Save meeting transcripts e.g. from Zoom or Meet as notes with who
+/// said what so meetings are searchable like any other note
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use super::indexing::{escape_org, new_org_id};
+
+/// Something said in the meeting
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utterance {
+    /// Name of the person speaking, if the transcript has one
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Format of an uploaded transcript
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptFormat {
+    /// WebVTT captions
+    Vtt,
+    /// A list of `{"speaker": ..., "text": ...}` objects
+    Json,
+}
+
+impl TranscriptFormat {
+    /// Guess the format from the content of the transcript
+    pub fn detect(transcript: &str) -> Self {
+        if transcript.trim_start().starts_with('[') {
+            Self::Json
+        } else {
+            Self::Vtt
+        }
+    }
+}
+
+/// Parse the transcript into what was said by whom. Consecutive
+/// lines from the same speaker are combined.
+pub fn parse_transcript(transcript: &str, format: TranscriptFormat) -> Result<Vec<Utterance>> {
+    let utterances = match format {
+        TranscriptFormat::Vtt => parse_vtt(transcript),
+        TranscriptFormat::Json => parse_json(transcript)?,
+    };
+    if utterances.is_empty() {
+        return Err(anyhow!("Transcript is empty"));
+    }
+    Ok(merge_speakers(utterances))
+}
+
+// Cues in WebVTT captions. Speakers are either voice tags like
+// `<v Alice>Hello</v>` or a prefix like `Alice: Hello` which is what
+// Zoom uses.
+fn parse_vtt(transcript: &str) -> Vec<Utterance> {
+    let mut utterances = Vec::new();
+    // Header, note, and style blocks run until the next blank line
+    let mut skip_block = false;
+    for line in transcript.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            skip_block = false;
+            continue;
+        }
+        if line.starts_with("WEBVTT") || line.starts_with("NOTE") || line == "STYLE" {
+            skip_block = true;
+        }
+        // Cue numbers and timings
+        if skip_block || line.contains("-->") || line.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let utterance = if let Some(rest) = line.strip_prefix("<v ") {
+            let (speaker, text) = rest.split_once('>').unwrap_or(("", rest));
+            Utterance {
+                speaker: Some(speaker.trim().to_string()).filter(|s| !s.is_empty()),
+                text: text.trim_end_matches("</v>").trim().to_string(),
+            }
+        } else {
+            match line.split_once(": ") {
+                Some((speaker, text)) if is_speaker_name(speaker) => Utterance {
+                    speaker: Some(speaker.trim().to_string()),
+                    text: text.trim().to_string(),
+                },
+                _ => Utterance {
+                    speaker: None,
+                    text: line.to_string(),
+                },
+            }
+        };
+        if !utterance.text.is_empty() {
+            utterances.push(utterance);
+        }
+    }
+    utterances
+}
+
+// Short prefixes are names. Anything longer is part of a sentence
+// that happens to have a colon in it.
+fn is_speaker_name(s: &str) -> bool {
+    let s = s.trim();
+    !s.is_empty() && s.len() <= 50 && s.split_whitespace().count() <= 4
+}
+
+#[derive(Deserialize)]
+struct JsonUtterance {
+    #[serde(alias = "name")]
+    speaker: Option<String>,
+    text: String,
+}
+
+fn parse_json(transcript: &str) -> Result<Vec<Utterance>> {
+    let items: Vec<JsonUtterance> = serde_json::from_str(transcript)?;
+    Ok(items
+        .into_iter()
+        .map(|u| Utterance {
+            speaker: u
+                .speaker
+                .map(|s| collapse_whitespace(&s))
+                .filter(|s| !s.is_empty()),
+            text: u.text.trim().to_string(),
+        })
+        .filter(|u| !u.text.is_empty())
+        .collect())
+}
+
+// Speakers and titles are written on one line of the note so
+// newlines would break the org syntax
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Captions split sentences into short cues so they're combined back
+// into one utterance per turn
+fn merge_speakers(utterances: Vec<Utterance>) -> Vec<Utterance> {
+    let mut merged: Vec<Utterance> = Vec::new();
+    for u in utterances {
+        match merged.last_mut() {
+            Some(last) if last.speaker == u.speaker => {
+                last.text.push(' ');
+                last.text.push_str(&u.text);
+            }
+            _ => merged.push(u),
+        }
+    }
+    merged
+}
+
+/// A meeting transcript converted to a note
+pub struct Meeting {
+    pub id: String,
+    pub title: String,
+    pub date: NaiveDate,
+    pub tags: Vec<String>,
+    pub utterances: Vec<Utterance>,
+}
+
+impl Meeting {
+    pub fn new(
+        title: &str,
+        date: NaiveDate,
+        tags: Vec<String>,
+        utterances: Vec<Utterance>,
+    ) -> Self {
+        Self {
+            id: new_org_id(),
+            title: collapse_whitespace(title),
+            date,
+            tags,
+            utterances,
+        }
+    }
+
+    /// Everyone who spoke in the order they first spoke
+    pub fn attendees(&self) -> Vec<&str> {
+        let mut attendees: Vec<&str> = Vec::new();
+        for speaker in self.utterances.iter().filter_map(|u| u.speaker.as_deref()) {
+            if !attendees.contains(&speaker) {
+                attendees.push(speaker);
+            }
+        }
+        attendees
+    }
+
+    /// File name of the note using the meeting's ID so it's unique
+    pub fn file_name(&self) -> String {
+        format!("meeting-{}.org", self.id)
+    }
+
+    /// Render the meeting as an org-mode note with the attendees and
+    /// the transcript as a list of who said what. The date is an
+    /// inactive timestamp so the note shows up for notes on that
+    /// date. Lines of the transcript that look like org syntax are
+    /// escaped so they don't start a headline.
+    ///
+    /// ```rust
+    /// use chrono::NaiveDate;
+    /// use indexer::meetings::{parse_transcript, Meeting, TranscriptFormat};
+    ///
+    /// let vtt = "WEBVTT\n\n1\n00:00:01.000 --> 00:00:03.000\n<v Alice>Let's ship it.</v>\n\n2\n00:00:03.000 --> 00:00:05.000\n<v Alice>Friday works.</v>\n\n3\n00:00:05.000 --> 00:00:07.000\nBob: Sounds good\n";
+    /// let utterances = parse_transcript(vtt, TranscriptFormat::Vtt).unwrap();
+    /// let date = NaiveDate::from_ymd_opt(2025, 1, 6).unwrap();
+    /// let mut meeting = Meeting::new("Launch sync", date, vec![], utterances);
+    /// meeting.id = "abc".to_string();
+    /// assert_eq!(meeting.attendees(), vec!["Alice", "Bob"]);
+    /// let org = meeting.to_org();
+    /// assert!(org.starts_with(":PROPERTIES:\n:ID: abc\n:END:\n#+TITLE: Launch sync\n#+FILETAGS: meeting\n\n[2025-01-06 Mon]\n"));
+    /// assert!(org.ends_with("* Transcript\n- Alice :: Let's ship it. Friday works.\n- Bob :: Sounds good\n"));
+    ///
+    /// let json = r#"[{"speaker": "Alice", "text": "Agenda:\n* Launch\n#+TITLE: Oops"}]"#;
+    /// let utterances = parse_transcript(json, TranscriptFormat::Json).unwrap();
+    /// let mut meeting = Meeting::new("Launch\nsync", date, vec![], utterances);
+    /// meeting.id = "abc".to_string();
+    /// let org = meeting.to_org();
+    /// assert!(org.contains("#+TITLE: Launch sync\n"));
+    /// assert!(org.ends_with("- Alice :: Agenda:\n  ,* Launch\n  ,#+TITLE: Oops\n"));
+    /// ```
+    pub fn to_org(&self) -> String {
+        let mut tags = vec!["meeting".to_string()];
+        tags.extend(self.tags.iter().filter(|t| *t != "meeting").cloned());
+
+        let attendees: String = self
+            .attendees()
+            .iter()
+            .map(|a| format!("- {}\n", a))
+            .collect();
+        let transcript: String = self
+            .utterances
+            .iter()
+            .map(|u| {
+                // Lines after the first are indented so they stay in
+                // the list item
+                let text = escape_org(&u.text).replace('\n', "\n  ");
+                match &u.speaker {
+                    Some(speaker) => format!("- {} :: {}\n", speaker, text),
+                    None => format!("- {}\n", text),
+                }
+            })
+            .collect();
+
+        format!(
+            ":PROPERTIES:\n:ID: {}\n:END:\n#+TITLE: {}\n#+FILETAGS: {}\n\n[{}]\n\n* Attendees\n{}\n* Transcript\n{}",
+            self.id,
+            self.title,
+            tags.join(" "),
+            self.date.format("%Y-%m-%d %a"),
+            attendees,
+            transcript
+        )
+    }
+}
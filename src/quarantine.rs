@@ -0,0 +1,95 @@
+/// Notes that fail to index are recorded with the error so they can
+/// be fixed and retried. Notes that keep failing are quarantined and
+/// skipped by routine indexing until they are retried.
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+
+/// Number of failed attempts before a note is quarantined
+pub const MAX_INDEX_ATTEMPTS: i64 = 3;
+
+/// A note that failed to index
+#[derive(Debug, Serialize)]
+pub struct IndexError {
+    pub id: i64,
+    pub file_name: String,
+    /// Error from the latest attempt
+    pub error: String,
+    pub attempts: i64,
+    /// True if routine indexing skips the note
+    pub quarantined: bool,
+    pub updated_at: String,
+}
+
+/// Record a failed attempt to index the note
+pub fn record_index_error(db: &Connection, file_name: &str, error: &str) -> Result<()> {
+    db.execute(
+        r"
+          INSERT INTO index_error(file_name, error)
+          VALUES (?1, ?2)
+          ON CONFLICT(file_name) DO UPDATE SET
+            error = excluded.error,
+            attempts = attempts + 1,
+            updated_at = CURRENT_TIMESTAMP
+        ",
+        [file_name, error],
+    )?;
+    Ok(())
+}
+
+/// Forget any failures for the note e.g. once it indexes successfully
+pub fn clear_index_error(db: &Connection, file_name: &str) -> Result<()> {
+    db.execute("DELETE FROM index_error WHERE file_name = ?1", [file_name])?;
+    Ok(())
+}
+
+/// Returns true if the note failed too many times to keep retrying
+pub fn is_quarantined(db: &Connection, file_name: &str) -> Result<bool> {
+    db.query_row(
+        "SELECT COUNT(*) > 0 FROM index_error WHERE file_name = ?1 AND attempts >= ?2",
+        rusqlite::params![file_name, MAX_INDEX_ATTEMPTS],
+        |r| r.get(0),
+    )
+}
+
+/// All notes that failed to index, most recent first
+pub fn index_errors(db: &Connection) -> Result<Vec<IndexError>> {
+    db.prepare(
+        r"
+          SELECT id, file_name, error, attempts, updated_at
+          FROM index_error
+          ORDER BY updated_at DESC, id DESC
+        ",
+    )?
+    .query_map([], |r| {
+        let attempts = r.get(3)?;
+        Ok(IndexError {
+            id: r.get(0)?,
+            file_name: r.get(1)?,
+            error: r.get(2)?,
+            attempts,
+            quarantined: attempts >= MAX_INDEX_ATTEMPTS,
+            updated_at: r.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// Look up the failure by ID
+pub fn index_error(db: &Connection, id: i64) -> Result<Option<IndexError>> {
+    db.query_row(
+        "SELECT id, file_name, error, attempts, updated_at FROM index_error WHERE id = ?1",
+        [id],
+        |r| {
+            let attempts = r.get(3)?;
+            Ok(IndexError {
+                id: r.get(0)?,
+                file_name: r.get(1)?,
+                error: r.get(2)?,
+                attempts,
+                quarantined: attempts >= MAX_INDEX_ATTEMPTS,
+                updated_at: r.get(4)?,
+            })
+        },
+    )
+    .optional()
+}
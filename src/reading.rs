@@ -0,0 +1,176 @@
+/// Read-later queue of web pages and notes with how far along they've
+/// been read
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingStatus {
+    Queued,
+    Reading,
+    Finished,
+}
+
+impl ReadingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Reading => "reading",
+            Self::Finished => "finished",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "reading" => Self::Reading,
+            "finished" => Self::Finished,
+            _ => Self::Queued,
+        }
+    }
+
+    /// The status that goes with reading this far into it
+    ///
+    /// ```rust
+    /// use indexer::reading::ReadingStatus;
+    ///
+    /// assert_eq!(ReadingStatus::from_progress(0), ReadingStatus::Queued);
+    /// assert_eq!(ReadingStatus::from_progress(40), ReadingStatus::Reading);
+    /// assert_eq!(ReadingStatus::from_progress(100), ReadingStatus::Finished);
+    /// ```
+    pub fn from_progress(progress: u8) -> Self {
+        match progress {
+            0 => Self::Queued,
+            100.. => Self::Finished,
+            _ => Self::Reading,
+        }
+    }
+}
+
+/// Something to read later
+#[derive(Debug, Serialize)]
+pub struct ReadingItem {
+    pub id: i64,
+    pub url: Option<String>,
+    /// ID of the note to read or the bookmark note of the URL
+    pub note_id: Option<String>,
+    /// Title of the note, if any
+    pub title: Option<String>,
+    /// Percent read from 0 to 100
+    pub progress: u8,
+    pub status: ReadingStatus,
+    pub added_at: String,
+    pub updated_at: String,
+}
+
+const READING_COLUMNS: &str = r"
+  reading.id, reading.url, reading.note_meta_id, note_meta.title,
+  reading.progress, reading.status, reading.added_at, reading.updated_at
+";
+
+fn item_from_row(r: &rusqlite::Row) -> Result<ReadingItem> {
+    let status: String = r.get(5)?;
+    Ok(ReadingItem {
+        id: r.get(0)?,
+        url: r.get(1)?,
+        note_id: r.get(2)?,
+        title: r.get(3)?,
+        progress: r.get(4)?,
+        status: ReadingStatus::parse(&status),
+        added_at: r.get(6)?,
+        updated_at: r.get(7)?,
+    })
+}
+
+/// Add the URL and/or note to the queue. Adding something that's
+/// already in the queue returns the existing item, filling in the
+/// URL or note if it was missing.
+pub fn enqueue_reading(
+    db: &Connection,
+    url: Option<&str>,
+    note_id: Option<&str>,
+) -> Result<ReadingItem> {
+    let existing: Option<i64> = db
+        .query_row(
+            "SELECT id FROM reading WHERE url = ?1 OR note_meta_id = ?2 ORDER BY id LIMIT 1",
+            rusqlite::params![url, note_id],
+            |r| r.get(0),
+        )
+        .optional()?;
+    let id = match existing {
+        Some(id) => {
+            db.execute(
+                r"
+                  UPDATE reading
+                  SET url = COALESCE(url, ?2), note_meta_id = COALESCE(note_meta_id, ?3)
+                  WHERE id = ?1
+                ",
+                rusqlite::params![id, url, note_id],
+            )?;
+            id
+        }
+        None => {
+            db.execute(
+                "INSERT INTO reading(url, note_meta_id) VALUES (?1, ?2)",
+                rusqlite::params![url, note_id],
+            )?;
+            db.last_insert_rowid()
+        }
+    };
+    reading_item(db, id).map(|item| item.expect("Reading item was just saved"))
+}
+
+/// Look up an item in the queue by ID
+pub fn reading_item(db: &Connection, id: i64) -> Result<Option<ReadingItem>> {
+    db.query_row(
+        &format!(
+            r"
+              SELECT {}
+              FROM reading
+              LEFT JOIN note_meta ON note_meta.id = reading.note_meta_id
+              WHERE reading.id = ?1
+            ",
+            READING_COLUMNS
+        ),
+        [id],
+        item_from_row,
+    )
+    .optional()
+}
+
+/// Set how far along the item has been read. The status follows the
+/// progress unless it's given. Returns `None` if the item doesn't
+/// exist.
+pub fn update_reading_progress(
+    db: &Connection,
+    id: i64,
+    progress: u8,
+    status: Option<ReadingStatus>,
+) -> Result<Option<ReadingItem>> {
+    let status = status.unwrap_or(ReadingStatus::from_progress(progress));
+    db.execute(
+        r"
+          UPDATE reading
+          SET progress = ?2, status = ?3, updated_at = CURRENT_TIMESTAMP
+          WHERE id = ?1
+        ",
+        rusqlite::params![id, progress, status.as_str()],
+    )?;
+    reading_item(db, id)
+}
+
+/// The queue in the order things were added, optionally only ones
+/// with the status
+pub fn reading_list(db: &Connection, status: Option<ReadingStatus>) -> Result<Vec<ReadingItem>> {
+    db.prepare(&format!(
+        r"
+          SELECT {}
+          FROM reading
+          LEFT JOIN note_meta ON note_meta.id = reading.note_meta_id
+          WHERE ?1 IS NULL OR reading.status = ?1
+          ORDER BY reading.added_at, reading.id
+        ",
+        READING_COLUMNS
+    ))?
+    .query_map([status.map(|s| s.as_str())], item_from_row)?
+    .collect()
+}
@@ -0,0 +1,113 @@
+/// Support for recurring tasks using org repeater cookies e.g.
+/// `<2025-01-01 Wed +1w>`
+use std::fmt;
+
+use chrono::{Days, Months, NaiveDate};
+use orgize::ast::{RepeaterType, TimeUnit, Timestamp};
+
+/// Returns the start date of the timestamp
+pub fn timestamp_date(ts: &Timestamp) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(
+        ts.year_start()?.parse().ok()?,
+        ts.month_start()?.parse().ok()?,
+        ts.day_start()?.parse().ok()?,
+    )
+}
+
+/// An org repeater cookie like `+1w`, `++1m`, or `.+2d`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repeater {
+    kind: RepeaterType,
+    value: u32,
+    unit: TimeUnit,
+}
+
+impl Repeater {
+    /// Returns the repeater of the timestamp if it has one
+    pub fn from_timestamp(ts: &Timestamp) -> Option<Self> {
+        Some(Self {
+            kind: ts.repeater_type()?,
+            value: ts.repeater_value()?,
+            unit: ts.repeater_unit()?,
+        })
+    }
+
+    /// Calculate the next occurrence after a task repeating from
+    /// `date` is completed on `today`. This follows the same rules as
+    /// org-mode:
+    ///
+    /// - `+1w` shifts the date by the interval once
+    /// - `++1w` shifts the date by the interval until it's in the future
+    /// - `.+1w` shifts from today rather than the original date
+    ///
+    /// ```rust
+    /// use chrono::NaiveDate;
+    /// use orgize::{Org, ast::Timestamp};
+    /// use indexer::recurrence::Repeater;
+    ///
+    /// let date = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    /// let today = NaiveDate::from_ymd_opt(2025, 1, 20).unwrap();
+    ///
+    /// let ts = Org::parse("<2025-01-01 Wed +1w>").first_node::<Timestamp>().unwrap();
+    /// let repeater = Repeater::from_timestamp(&ts).unwrap();
+    /// assert_eq!(repeater.to_string(), "+1w");
+    /// assert_eq!(repeater.next_occurrence(date, today).to_string(), "2025-01-08");
+    ///
+    /// let ts = Org::parse("<2025-01-01 Wed ++1w>").first_node::<Timestamp>().unwrap();
+    /// let repeater = Repeater::from_timestamp(&ts).unwrap();
+    /// assert_eq!(repeater.next_occurrence(date, today).to_string(), "2025-01-22");
+    ///
+    /// let ts = Org::parse("<2025-01-01 Wed .+1m>").first_node::<Timestamp>().unwrap();
+    /// let repeater = Repeater::from_timestamp(&ts).unwrap();
+    /// assert_eq!(repeater.next_occurrence(date, today).to_string(), "2025-02-20");
+    /// ```
+    pub fn next_occurrence(&self, date: NaiveDate, today: NaiveDate) -> NaiveDate {
+        match self.kind {
+            RepeaterType::Cumulate => self.shift(date),
+            RepeaterType::CatchUp => {
+                let mut next = self.shift(date);
+                while next <= today {
+                    let shifted = self.shift(next);
+                    // Guard against an interval that doesn't move
+                    // the date forward
+                    if shifted == next {
+                        break;
+                    }
+                    next = shifted;
+                }
+                next
+            }
+            RepeaterType::Restart => self.shift(today),
+        }
+    }
+
+    fn shift(&self, date: NaiveDate) -> NaiveDate {
+        let shifted = match self.unit {
+            // Dates don't have a time so hours round up to a day
+            TimeUnit::Hour => date.checked_add_days(Days::new(self.value.div_ceil(24) as u64)),
+            TimeUnit::Day => date.checked_add_days(Days::new(self.value as u64)),
+            TimeUnit::Week => date.checked_add_days(Days::new(self.value as u64 * 7)),
+            TimeUnit::Month => date.checked_add_months(Months::new(self.value)),
+            TimeUnit::Year => date.checked_add_months(Months::new(self.value * 12)),
+        };
+        shifted.unwrap_or(date)
+    }
+}
+
+impl fmt::Display for Repeater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            RepeaterType::Cumulate => "+",
+            RepeaterType::CatchUp => "++",
+            RepeaterType::Restart => ".+",
+        };
+        let unit = match self.unit {
+            TimeUnit::Hour => "h",
+            TimeUnit::Day => "d",
+            TimeUnit::Week => "w",
+            TimeUnit::Month => "m",
+            TimeUnit::Year => "y",
+        };
+        write!(f, "{}{}{}", kind, self.value, unit)
+    }
+}
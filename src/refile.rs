@@ -0,0 +1,231 @@
+/// Move a headline and everything under it to another note, the same
+/// as `org-refile`. This is mostly used to move entries out of the
+/// capture file.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use orgize::ast::Headline;
+use orgize::rowan::ast::AstNode;
+use rusqlite::{Connection, OptionalExtension};
+use tantivy::{IndexWriter, Term};
+
+use super::capture::CAPTURE_FILE_NAME;
+use super::indexing::{new_org_id, parse_org, task_id, TaskKeywords};
+use super::schema::note_schema;
+use super::source::{notes, NoteWalk};
+
+/// Where to move the headline
+#[derive(Debug)]
+pub struct RefileTarget {
+    /// File name of the note relative to the notes directory
+    pub file_name: String,
+    /// ID or title of the headline in the note to put it under.
+    /// Defaults to the top level of the note.
+    pub parent: Option<String>,
+}
+
+/// The result of refiling
+#[derive(Debug)]
+pub struct Refiled {
+    /// ID of the headline which is new if it didn't have one before
+    pub id: String,
+    pub title: String,
+    /// Files that were changed and need to be re-indexed
+    pub paths: Vec<PathBuf>,
+}
+
+/// Move the headline with the ID to the target note. The headline is
+/// looked up in the indexed tasks and then the capture file.
+///
+/// Headlines without an org-id are given one so links to it keep
+/// working wherever it's moved to. Links to the headline using
+/// `file:` links are rewritten as `id:` links.
+pub fn refile(
+    db: &Connection,
+    keywords: &TaskKeywords,
+    notes_path: &str,
+    index_writer: &mut IndexWriter,
+    id: &str,
+    target: &RefileTarget,
+) -> Result<Refiled> {
+    let target_path = target_path(notes_path, &target.file_name)?;
+    let source_path = db
+        .query_row(
+            "SELECT file_name FROM note_meta WHERE id = ?1 AND type = 'task'",
+            [id],
+            |r| r.get::<_, String>(0),
+        )
+        .optional()?
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(notes_path).join(CAPTURE_FILE_NAME));
+
+    let mut source = fs::read_to_string(&source_path)?;
+    let org = parse_org(&source, keywords);
+    let headline = org
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Headline::cast)
+        .find(|h| task_id(h) == id)
+        .ok_or_else(|| anyhow!("Headline {} not found", id))?;
+    let title = headline.title_raw().trim().to_string();
+    let range = headline.syntax().text_range();
+    let mut subtree = source[range].to_string();
+    if !subtree.ends_with('\n') {
+        subtree.push('\n');
+    }
+
+    let has_org_id = headline.properties().and_then(|p| p.get("ID")).is_some();
+    let new_id = if has_org_id {
+        id.to_string()
+    } else {
+        let new_id = new_org_id();
+        subtree = with_org_id(&subtree, &new_id);
+        new_id
+    };
+
+    source.replace_range(usize::from(range.start())..usize::from(range.end()), "");
+    let same_file = source_path == target_path;
+    let mut dest = if same_file {
+        source.clone()
+    } else {
+        fs::read_to_string(&target_path)?
+    };
+
+    // Insert at the end of the parent's subtree so it's the last child
+    let dest_org = parse_org(&dest, keywords);
+    let (insert_at, level) = if let Some(parent) = &target.parent {
+        let parent = dest_org
+            .document()
+            .syntax()
+            .descendants()
+            .filter_map(Headline::cast)
+            .find(|h| task_id(h) == *parent || h.title_raw().trim() == parent)
+            .ok_or_else(|| anyhow!("Parent headline {} not found", parent))?;
+        (
+            usize::from(parent.syntax().text_range().end()),
+            parent.level() + 1,
+        )
+    } else {
+        (dest.len(), 1)
+    };
+    let subtree = with_level(&subtree, headline.level(), level);
+    if insert_at > 0 && !dest[..insert_at].ends_with('\n') {
+        dest.insert(insert_at, '\n');
+        dest.insert_str(insert_at + 1, &subtree);
+    } else {
+        dest.insert_str(insert_at, &subtree);
+    }
+
+    if !same_file {
+        fs::write(&source_path, &source)?;
+    }
+    fs::write(&target_path, &dest)?;
+
+    let mut paths = vec![source_path.clone(), target_path];
+    paths.dedup();
+    let source_name = source_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    for path in rewrite_file_links(notes_path, &source_name, &title, &new_id)? {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    // The task will be indexed again under the new ID so the old one
+    // needs to be removed
+    if new_id != id {
+        db.execute("DELETE FROM note_meta WHERE id = ?1", [id])?;
+        let id_field = note_schema().get_field("id")?;
+        index_writer.delete_term(Term::from_field_text(id_field, id));
+        index_writer.commit()?;
+    }
+
+    Ok(Refiled {
+        id: new_id,
+        title,
+        paths,
+    })
+}
+
+// Only allow files directly in the notes directory that already exist
+fn target_path(notes_path: &str, file_name: &str) -> Result<PathBuf> {
+    let is_plain_name =
+        Path::new(file_name).file_name().and_then(|n| n.to_str()) == Some(file_name);
+    let path = Path::new(notes_path).join(file_name);
+    if !is_plain_name || !file_name.ends_with(".org") || !path.is_file() {
+        return Err(anyhow!("Note {} not found", file_name));
+    }
+    Ok(path)
+}
+
+// Add an org-id to the headline's property drawer, creating the
+// drawer if it doesn't exist. The drawer has to come after any
+// planning line e.g. `SCHEDULED:`.
+fn with_org_id(subtree: &str, id: &str) -> String {
+    let mut lines: Vec<String> = subtree.lines().map(String::from).collect();
+    let mut idx = 1;
+    let is_planning = |l: &str| {
+        let l = l.trim_start();
+        l.starts_with("SCHEDULED:") || l.starts_with("DEADLINE:") || l.starts_with("CLOSED:")
+    };
+    if lines.get(idx).is_some_and(|l| is_planning(l)) {
+        idx += 1;
+    }
+    let id_line = format!(":ID: {}", id);
+    if lines.get(idx).is_some_and(|l| l.trim() == ":PROPERTIES:") {
+        lines.insert(idx + 1, id_line);
+    } else {
+        lines.splice(
+            idx..idx,
+            [":PROPERTIES:".to_string(), id_line, ":END:".to_string()],
+        );
+    }
+    lines.join("\n") + "\n"
+}
+
+// Change the level of every headline in the subtree so the top
+// headline is at the new level
+fn with_level(subtree: &str, from: usize, to: usize) -> String {
+    subtree
+        .lines()
+        .map(|l| {
+            let stars = l.chars().take_while(|c| *c == '*').count();
+            let is_headline = stars > 0 && l[stars..].starts_with(' ');
+            if is_headline {
+                let level = (stars + to).saturating_sub(from).max(1);
+                format!("{}{}", "*".repeat(level), &l[stars..])
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+        + "\n"
+}
+
+// Replace links like `[[file:capture.org::*Title]]` with an org-id
+// link. Returns the files that were changed.
+fn rewrite_file_links(
+    notes_path: &str,
+    source_name: &str,
+    title: &str,
+    id: &str,
+) -> Result<Vec<PathBuf>> {
+    let from = format!("[[file:{}::*{}]", source_name, title);
+    let to = format!("[[id:{}]", id);
+    let mut changed = Vec::new();
+    for path in notes(notes_path, &NoteWalk::default()) {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        if content.contains(&from) {
+            fs::write(&path, content.replace(&from, &to))?;
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
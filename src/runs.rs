@@ -0,0 +1,188 @@
+/// Runs of indexing every note or the ones that changed, with how far
+/// along they are, so callers can tell when indexing finished or
+/// failed
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::Serialize;
+
+use super::indexing::IndexEvent;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexRunStatus {
+    /// Waiting for indexing that's already running to finish
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl IndexRunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "running" => Self::Running,
+            "completed" => Self::Completed,
+            "failed" => Self::Failed,
+            _ => Self::Queued,
+        }
+    }
+}
+
+/// A run of indexing notes
+#[derive(Clone, Debug, Serialize)]
+pub struct IndexRun {
+    pub id: i64,
+    /// What started the run e.g. `api`
+    pub trigger: String,
+    pub status: IndexRunStatus,
+    /// Number of notes indexed, skipped because they didn't change,
+    /// failed, and removed so far
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+    pub removed: usize,
+    /// Error that stopped the run, if it failed
+    pub error: Option<String>,
+    pub queued_at: String,
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+impl IndexRun {
+    /// Count the notes in the progress event
+    ///
+    /// ```rust
+    /// use indexer::indexing::IndexEvent;
+    /// use indexer::runs::{IndexRun, IndexRunStatus};
+    ///
+    /// let mut run = IndexRun {
+    ///     id: 1,
+    ///     trigger: "api".to_string(),
+    ///     status: IndexRunStatus::Running,
+    ///     indexed: 0,
+    ///     unchanged: 0,
+    ///     failed: 0,
+    ///     removed: 0,
+    ///     error: None,
+    ///     queued_at: "2025-01-22 09:00:00".to_string(),
+    ///     finished_at: None,
+    ///     duration_ms: None,
+    /// };
+    /// run.record(&IndexEvent::Indexed { path: "a.org".to_string() });
+    /// run.record(&IndexEvent::Failed { path: "b.org".to_string(), error: "Oops".to_string() });
+    /// assert_eq!((run.indexed, run.failed, run.removed), (1, 1, 0));
+    /// ```
+    pub fn record(&mut self, event: &IndexEvent) {
+        match event {
+            IndexEvent::Indexed { .. } => self.indexed += 1,
+            IndexEvent::Unchanged { .. } => self.unchanged += 1,
+            IndexEvent::Failed { .. } => self.failed += 1,
+            IndexEvent::Removed { .. } => self.removed += 1,
+            _ => {}
+        }
+    }
+
+    /// Mark the run as finished after `duration`, failed if there's
+    /// an error
+    pub fn finish(&mut self, duration: Duration, error: Option<String>) {
+        self.status = if error.is_some() {
+            IndexRunStatus::Failed
+        } else {
+            IndexRunStatus::Completed
+        };
+        self.error = error;
+        self.duration_ms = Some(duration.as_millis() as u64);
+    }
+}
+
+const INDEX_RUN_COLUMNS: &str = r"
+  id, trigger, status, indexed, failed, removed, error, queued_at,
+  finished_at, duration_ms, unchanged
+";
+
+fn run_from_row(r: &rusqlite::Row) -> Result<IndexRun> {
+    let status: String = r.get(2)?;
+    Ok(IndexRun {
+        id: r.get(0)?,
+        trigger: r.get(1)?,
+        status: IndexRunStatus::parse(&status),
+        indexed: r.get(3)?,
+        failed: r.get(4)?,
+        removed: r.get(5)?,
+        error: r.get(6)?,
+        queued_at: r.get(7)?,
+        finished_at: r.get(8)?,
+        duration_ms: r.get(9)?,
+        unchanged: r.get(10)?,
+    })
+}
+
+/// Record a new run that hasn't started yet
+pub fn queue_index_run(db: &Connection, trigger: &str) -> Result<IndexRun> {
+    db.execute("INSERT INTO index_run(trigger) VALUES (?1)", [trigger])?;
+    let id = db.last_insert_rowid();
+    index_run(db, id).map(|run| run.expect("Index run was just saved"))
+}
+
+/// Save the status and counts of the run. The finish time is set
+/// once it completes or fails.
+pub fn save_index_run(db: &Connection, run: &IndexRun) -> Result<()> {
+    let finished = matches!(
+        run.status,
+        IndexRunStatus::Completed | IndexRunStatus::Failed
+    );
+    db.execute(
+        r"
+          UPDATE index_run
+          SET status = ?2, indexed = ?3, failed = ?4, removed = ?5, error = ?6,
+            duration_ms = ?7, unchanged = ?9,
+            finished_at = CASE WHEN ?8 THEN CURRENT_TIMESTAMP ELSE NULL END
+          WHERE id = ?1
+        ",
+        rusqlite::params![
+            run.id,
+            run.status.as_str(),
+            run.indexed,
+            run.failed,
+            run.removed,
+            run.error,
+            run.duration_ms,
+            finished,
+            run.unchanged
+        ],
+    )?;
+    Ok(())
+}
+
+/// Look up a run by ID
+pub fn index_run(db: &Connection, id: i64) -> Result<Option<IndexRun>> {
+    db.query_row(
+        &format!("SELECT {} FROM index_run WHERE id = ?1", INDEX_RUN_COLUMNS),
+        [id],
+        run_from_row,
+    )
+    .optional()
+}
+
+/// The most recent run, if there has been one
+pub fn latest_index_run(db: &Connection) -> Result<Option<IndexRun>> {
+    db.query_row(
+        &format!(
+            "SELECT {} FROM index_run ORDER BY id DESC LIMIT 1",
+            INDEX_RUN_COLUMNS
+        ),
+        [],
+        run_from_row,
+    )
+    .optional()
+}
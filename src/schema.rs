@@ -1,13 +1,147 @@
+use std::env;
+use std::path::Path;
+
+use tantivy::directory::MmapDirectory;
 use tantivy::schema::*;
+use tantivy::tokenizer::{
+    Language, LowerCaser, RegexTokenizer, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    StopWordFilter, TextAnalyzer,
+};
+use tantivy::Index;
+
+/// Tokenizer for the title field
+pub const TITLE_TOKENIZER: &str = "title";
+/// Tokenizer for the body field
+pub const BODY_TOKENIZER: &str = "body";
+/// Tokenizer for tags which are matched exactly e.g. `tags:go`
+/// doesn't match a note tagged `golang`
+pub const TAG_TOKENIZER: &str = "tag";
+
+// Text field that uses the named tokenizer
+fn text_with_tokenizer(tokenizer: &str) -> TextOptions {
+    TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(tokenizer)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    )
+}
 
 pub fn note_schema() -> Schema {
     let mut schema_builder = Schema::builder();
-    schema_builder.add_text_field("id", TEXT | STORED);
+    // Not tokenized so notes can be deleted by ID e.g. org-ids are
+    // UUIDs that would otherwise be split on the hyphens
+    schema_builder.add_text_field("id", STRING | STORED);
     schema_builder.add_text_field("type", TEXT | STORED);
-    schema_builder.add_text_field("title", TEXT | STORED);
-    schema_builder.add_text_field("tags", TEXT | STORED);
+    schema_builder.add_text_field("title", text_with_tokenizer(TITLE_TOKENIZER) | STORED);
+    schema_builder.add_text_field("tags", text_with_tokenizer(TAG_TOKENIZER) | STORED);
     schema_builder.add_text_field("status", TEXT | STORED);
-    schema_builder.add_text_field("body", TEXT | STORED);
+    schema_builder.add_text_field("body", text_with_tokenizer(BODY_TOKENIZER) | STORED);
     schema_builder.add_text_field("file_name", TEXT | STORED);
+    // Org properties and keywords e.g. `prop.client:acme`
+    schema_builder.add_json_field("prop", TEXT | STORED);
+    // Whether the note is encrypted e.g. `-encrypted:true`
+    schema_builder.add_text_field("encrypted", STRING | STORED);
+    // Whether a feed item has been read e.g. `read:false`
+    schema_builder.add_text_field("read", STRING | STORED);
+    // Prefixes of words in the title and aliases for search as you
+    // type e.g. `title_prefix:ind`
+    schema_builder.add_text_field("title_prefix", STRING);
+    // Collection of notes the note is in e.g. `collection:work`
+    schema_builder.add_text_field("collection", STRING | STORED);
+    // Dates mentioned by timestamps in the note e.g.
+    // `mentions_date:2025-06-12`
+    schema_builder.add_text_field("mentions_date", STRING);
     schema_builder.build()
 }
+
+/// Longest prefix that is indexed. Longer words in queries are
+/// truncated to this length.
+pub const MAX_PREFIX_LEN: usize = 20;
+
+/// Returns the lowercased prefixes of each word in the text for the
+/// `title_prefix` field
+///
+/// ```rust
+/// use indexer::schema::title_prefixes;
+///
+/// assert_eq!(title_prefixes("Go Lang"), vec!["g", "go", "l", "la", "lan", "lang"]);
+/// ```
+pub fn title_prefixes(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .flat_map(|w| {
+            let word: Vec<char> = w.to_lowercase().chars().collect();
+            (1..=word.len().min(MAX_PREFIX_LEN))
+                .map(|i| word[..i].iter().collect::<String>())
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// Stop words and stemming for the title and body fields
+#[derive(Clone, Debug, Default)]
+pub struct TokenizerConfig {
+    /// Words that are not indexed or searched
+    pub stop_words: Vec<String>,
+    /// Fields where words are stemmed e.g. `body`
+    pub stemmed_fields: Vec<String>,
+}
+
+impl TokenizerConfig {
+    /// Read the config from environment variables. Stop words are a
+    /// comma separated list in `INDEXER_STOP_WORDS` and fields to stem
+    /// (`title` or `body`) are in `INDEXER_STEMMED_FIELDS`. Stemming
+    /// uses English.
+    pub fn from_env() -> Self {
+        let list = |var: &str| -> Vec<String> {
+            env::var(var)
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        Self {
+            stop_words: list("INDEXER_STOP_WORDS"),
+            stemmed_fields: list("INDEXER_STEMMED_FIELDS"),
+        }
+    }
+
+    fn text_analyzer(&self, field: &str) -> TextAnalyzer {
+        let mut builder = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .dynamic();
+        if !self.stop_words.is_empty() {
+            builder = builder.filter_dynamic(StopWordFilter::remove(self.stop_words.clone()));
+        }
+        if self.stemmed_fields.iter().any(|f| f == field) {
+            builder = builder.filter_dynamic(Stemmer::new(Language::English));
+        }
+        builder.build()
+    }
+
+    /// Register the tokenizers used by the schema with the index.
+    /// This needs to happen every time the index is opened.
+    pub fn register(&self, index: &Index) {
+        let tokenizers = index.tokenizers();
+        tokenizers.register(TITLE_TOKENIZER, self.text_analyzer("title"));
+        tokenizers.register(BODY_TOKENIZER, self.text_analyzer("body"));
+        // Tags are stored as a comma separated list
+        let tag_tokenizer = TextAnalyzer::builder(
+            RegexTokenizer::new(r"[^,\s]+").expect("Invalid tag tokenizer pattern"),
+        )
+        .filter(LowerCaser)
+        .build();
+        tokenizers.register(TAG_TOKENIZER, tag_tokenizer);
+    }
+}
+
+/// Open the full text search index, creating it if it doesn't exist,
+/// with the configured tokenizers registered
+pub fn open_index(index_dir_path: impl AsRef<Path>) -> Index {
+    let dir = MmapDirectory::open(index_dir_path).expect("Index not found");
+    let index = Index::open_or_create(dir, note_schema()).expect("Unable to open or create index");
+    TokenizerConfig::from_env().register(&index);
+    index
+}
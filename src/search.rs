@@ -1,15 +1,283 @@
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, RwLock};
+
 use itertools::Itertools;
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use serde::Serialize;
 use serde_json::json;
 use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::query::{
+    BooleanQuery, Occur, Query, QueryParser, QueryParserError, RegexQuery, TermQuery,
+};
 use tantivy::schema::*;
-use tantivy::{Index, ReloadPolicy};
+use tantivy::{Index, IndexReader, ReloadPolicy};
 use zerocopy::AsBytes;
 
-use super::schema::note_schema;
+use super::ann::AnnIndex;
+use super::db::VectorFormat;
+use super::indexing::{embeddings_available, embeddings_model, reading_time_minutes};
+use super::schema::{note_schema, open_index, MAX_PREFIX_LEN};
+
+/// The full text index and a reader that are opened once and shared
+/// between searches. The reader picks up new commits automatically
+/// after a short delay or immediately with `reload`.
+///
+/// Also holds the approximate nearest neighbor index when it's
+/// enabled for similarity search.
+#[derive(Clone)]
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    ann: Arc<RwLock<Option<AnnIndex>>>,
+}
+
+impl SearchIndex {
+    pub fn open(index_path: &str) -> Self {
+        let index = open_index(index_path);
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()
+            .expect("Reader failed to load");
+        Self {
+            index,
+            reader,
+            ann: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Replace the approximate nearest neighbor index used for
+    /// similarity search. Similarity search compares every embedding
+    /// when there isn't one.
+    pub fn set_ann(&self, ann: Option<AnnIndex>) {
+        *self.ann.write().unwrap_or_else(|e| e.into_inner()) = ann;
+    }
+
+    /// Reload the reader so changes that were just committed are
+    /// visible to the next search
+    pub fn reload(&self) {
+        if let Err(err) = self.reader.reload() {
+            tracing::warn!("Failed to reload search index: {}", err);
+        }
+    }
+}
+
+/// Notes with this word in the title are excluded from search results
+/// unless `INDEXER_SEARCH_EXCLUDE_TITLES` is set
+const DEFAULT_EXCLUDED_TITLE: &str = "journal";
+
+/// Rules for excluding notes from search results. These are applied
+/// to both full-text and similarity search so that the results are
+/// consistent regardless of which index a hit came from.
+#[derive(Clone, Debug, Default)]
+pub struct SearchExclusions {
+    /// Exclude notes that have any of these tags
+    pub tags: Vec<String>,
+    /// Exclude documents of these types e.g. "task"
+    pub types: Vec<String>,
+    /// Exclude notes whose title contains any of these words
+    pub titles: Vec<String>,
+}
+
+impl SearchExclusions {
+    /// Read exclusions from comma separated environment variables
+    /// `INDEXER_SEARCH_EXCLUDE_TAGS`, `INDEXER_SEARCH_EXCLUDE_TYPES`,
+    /// and `INDEXER_SEARCH_EXCLUDE_TITLES`. Titles containing
+    /// "journal" are excluded unless the titles are set, even to an
+    /// empty string.
+    pub fn from_env() -> Self {
+        let parse = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        let var = |key: &str| env::var(key).map(|v| parse(&v));
+        Self {
+            tags: var("INDEXER_SEARCH_EXCLUDE_TAGS").unwrap_or_default(),
+            types: var("INDEXER_SEARCH_EXCLUDE_TYPES").unwrap_or_default(),
+            titles: var("INDEXER_SEARCH_EXCLUDE_TITLES")
+                .unwrap_or_else(|_| vec![DEFAULT_EXCLUDED_TITLE.to_string()]),
+        }
+    }
+
+    /// Returns the IDs that are not excluded in the same order. This
+    /// uses the same rules as similarity search.
+    pub fn filter_ids(&self, db: &Connection, ids: &[String]) -> Result<Vec<String>> {
+        db.prepare(&format!(
+            r"
+              SELECT note_meta.id
+              FROM json_each(?4) AS ids
+              JOIN note_meta ON note_meta.id = ids.value
+              WHERE {EXCLUSIONS_SQL}
+              ORDER BY ids.key
+            ",
+        ))?
+        .query_map(
+            rusqlite::params![
+                json!(self.types).to_string(),
+                json!(self.tags).to_string(),
+                json!(self.titles).to_string(),
+                json!(ids).to_string(),
+            ],
+            |r| r.get(0),
+        )?
+        .collect()
+    }
+
+    /// Wrap the query so that any documents matching an exclusion
+    /// rule are removed from the full-text search results.
+    fn apply_to_query(&self, schema: &Schema, query: Box<dyn Query>) -> Box<dyn Query> {
+        let tags = schema.get_field("tags").unwrap();
+        let r#type = schema.get_field("type").unwrap();
+        let title = schema.get_field("title").unwrap();
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        for tag in self.tags.iter() {
+            clauses.push((Occur::MustNot, tag_query(tags, tag)));
+        }
+        for doc_type in self.types.iter() {
+            let term = Term::from_field_text(r#type, doc_type);
+            clauses.push((
+                Occur::MustNot,
+                Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+            ));
+        }
+        for word in self.titles.iter() {
+            // Match the word anywhere in a title token to mirror the
+            // `LIKE` matching used for similarity search
+            let pattern = format!(".*{}.*", regex_escape(&word.to_lowercase()));
+            let query = RegexQuery::from_pattern(&pattern, title).expect("Invalid title exclusion");
+            clauses.push((Occur::MustNot, Box::new(query)));
+        }
+        Box::new(BooleanQuery::new(clauses))
+    }
+}
+
+/// SQL for the exclusion rules where `?1`, `?2`, and `?3` are JSON
+/// arrays of the types, tags, and title words to exclude. Tags are
+/// compared to each item in the comma separated list of the note's
+/// tags, or the start of each item for nested tags like `project/*`,
+/// and titles are matched by substring, both ignoring case, to match
+/// the full text index.
+const EXCLUSIONS_SQL: &str = r"
+  IFNULL(note_meta.type, 'note') NOT IN (SELECT value FROM json_each(?1))
+  AND NOT EXISTS (
+    SELECT 1 FROM json_each(?2)
+    WHERE instr(
+      ',' || lower(IFNULL(note_meta.tags, '')) || ',',
+      ',' || lower(CASE WHEN value LIKE '%/*' THEN rtrim(value, '*') ELSE value || ',' END)
+    ) > 0
+  )
+  AND NOT EXISTS (
+    SELECT 1 FROM json_each(?3)
+    WHERE instr(lower(note_meta.title), lower(value)) > 0
+  )
+";
+
+/// Limit search results to notes matching every filter. These are
+/// applied to both full-text and similarity search. Similarity search
+/// only compares against the notes that match so filtered results
+/// aren't crowded out by nearer notes that don't.
+#[derive(Clone, Debug, Default)]
+pub struct SearchFilters {
+    /// Only documents of these types e.g. "task"
+    pub types: Vec<String>,
+    /// Only notes that have all of these tags
+    pub tags: Vec<String>,
+    /// Only notes in these collections
+    pub collections: Vec<String>,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty() && self.tags.is_empty() && self.collections.is_empty()
+    }
+
+    /// Wrap the query so only documents matching the filters are
+    /// returned from the full-text search
+    fn apply_to_query(&self, schema: &Schema, query: Box<dyn Query>) -> Box<dyn Query> {
+        if self.is_empty() {
+            return query;
+        }
+        let tags = schema.get_field("tags").unwrap();
+        let r#type = schema.get_field("type").unwrap();
+        let collection = schema.get_field("collection").unwrap();
+
+        // Matches any of the values for the field
+        let any_of = |field: Field, values: &[String]| -> Box<dyn Query> {
+            let clauses: Vec<(Occur, Box<dyn Query>)> = values
+                .iter()
+                .map(|v| {
+                    let term = Term::from_field_text(field, v);
+                    let query: Box<dyn Query> =
+                        Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+                    (Occur::Should, query)
+                })
+                .collect();
+            Box::new(BooleanQuery::new(clauses))
+        };
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+        if !self.types.is_empty() {
+            clauses.push((Occur::Must, any_of(r#type, &self.types)));
+        }
+        for tag in self.tags.iter() {
+            clauses.push((Occur::Must, tag_query(tags, tag)));
+        }
+        if !self.collections.is_empty() {
+            clauses.push((Occur::Must, any_of(collection, &self.collections)));
+        }
+        Box::new(BooleanQuery::new(clauses))
+    }
+}
+
+/// SQL for the filters where `?7`, `?8`, and `?9` are JSON arrays of
+/// the types, tags, and collections to include. Empty arrays match
+/// everything. Tags are matched the same way as exclusions.
+const FILTERS_SQL: &str = r"
+  (json_array_length(?7) = 0 OR IFNULL(note_meta.type, 'note') IN (SELECT value FROM json_each(?7)))
+  AND NOT EXISTS (
+    SELECT 1 FROM json_each(?8)
+    WHERE instr(
+      ',' || lower(IFNULL(note_meta.tags, '')) || ',',
+      ',' || lower(CASE WHEN value LIKE '%/*' THEN rtrim(value, '*') ELSE value || ',' END)
+    ) = 0
+  )
+  AND (json_array_length(?9) = 0 OR note_meta.collection IN (SELECT value FROM json_each(?9)))
+";
+
+// Matches notes with the tag, or any tag nested under it for tags
+// like `project/*`, the same way as the SQL for exclusions and
+// filters
+fn tag_query(field: Field, tag: &str) -> Box<dyn Query> {
+    // Tags are lowercased when indexed
+    let tag = tag.to_lowercase();
+    match tag.strip_suffix('*').filter(|parent| parent.ends_with('/')) {
+        Some(parent) => {
+            let pattern = format!("{}.*", regex_escape(parent));
+            Box::new(RegexQuery::from_pattern(&pattern, field).expect("Invalid tag pattern"))
+        }
+        None => {
+            let term = Term::from_field_text(field, &tag);
+            Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+        }
+    }
+}
+
+fn regex_escape(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| {
+            if c.is_ascii_punctuation() {
+                vec!['\\', c]
+            } else {
+                vec![c]
+            }
+        })
+        .collect()
+}
 
 #[derive(Serialize)]
 pub enum SearchHitType {
@@ -26,23 +294,94 @@ pub struct SearchHit {
     pub score: f32,
 }
 
-fn fulltext_search(index_path: &str, query: &str, limit: usize) -> Vec<SearchHit> {
+#[derive(Serialize)]
+pub struct Suggestion {
+    pub id: String,
+    pub title: String,
+    pub r#type: String,
+}
+
+/// Search as you type by matching the start of each word in the query
+/// against words in titles and aliases. This is much cheaper than a
+/// full search since it only needs exact term lookups.
+pub fn suggest(
+    search_index: &SearchIndex,
+    query: &str,
+    limit: usize,
+    exclusions: &SearchExclusions,
+) -> Vec<Suggestion> {
     let schema = note_schema();
-    let index_path = tantivy::directory::MmapDirectory::open(index_path).expect("Index not found");
-    let idx = Index::open(index_path).expect("Unable to open index");
+    let title_prefix = schema.get_field("title_prefix").unwrap();
+    let clauses: Vec<(Occur, Box<dyn Query>)> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let prefix: String = w.to_lowercase().chars().take(MAX_PREFIX_LEN).collect();
+            let term = Term::from_field_text(title_prefix, &prefix);
+            let query: Box<dyn Query> =
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs));
+            (Occur::Must, query)
+        })
+        .collect();
+    if clauses.is_empty() {
+        return Vec::new();
+    }
+    let query = exclusions.apply_to_query(&schema, Box::new(BooleanQuery::new(clauses)));
+    let searcher = search_index.reader.searcher();
+
+    searcher
+        .search(&query, &TopDocs::with_limit(limit))
+        .expect("Search failed")
+        .iter()
+        .map(|(_score, doc_addr)| {
+            let doc = searcher
+                .doc::<TantivyDocument>(*doc_addr)
+                .expect("Doc not found")
+                .to_named_doc(&schema)
+                .0;
+            let field = |name: &str| -> String {
+                doc.get(name)
+                    .and_then(|v| v.first())
+                    .and_then(|v| v.as_ref().as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            Suggestion {
+                id: field("id"),
+                title: field("title"),
+                r#type: field("type"),
+            }
+        })
+        .collect()
+}
+
+// Parse the full text query searching the title and body by default
+// with filters and exclusions applied
+fn parse_query(
+    idx: &Index,
+    schema: &Schema,
+    query: &str,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+) -> std::result::Result<Box<dyn Query>, QueryParserError> {
     let title = schema.get_field("title").unwrap();
     let body = schema.get_field("body").unwrap();
+    let query_parser = QueryParser::for_index(idx, vec![title, body]);
+    let query = query_parser.parse_query(query)?;
+    let query = filters.apply_to_query(schema, query);
+    Ok(exclusions.apply_to_query(schema, query))
+}
 
-    let reader = idx
-        .reader_builder()
-        .reload_policy(ReloadPolicy::OnCommitWithDelay)
-        .try_into()
-        .expect("Reader failed to load");
-
-    let searcher = reader.searcher();
-    let query_parser = QueryParser::for_index(&idx, vec![title, body]);
-    let query = query_parser
-        .parse_query(query)
+pub fn fulltext_search(
+    search_index: &SearchIndex,
+    query: &str,
+    limit: usize,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+) -> Vec<SearchHit> {
+    let schema = note_schema();
+    let searcher = search_index.reader.searcher();
+    let query = parse_query(&search_index.index, &schema, query, exclusions, filters)
         .expect("Failed to parse query");
 
     searcher
@@ -74,40 +413,208 @@ fn fulltext_search(index_path: &str, query: &str, limit: usize) -> Vec<SearchHit
 /// Returns the note ID and similarity distance for the query. Results
 /// are ordered by ascending distance because sqlite-vec only supports
 /// ascending distance.
-pub fn search_similar_notes(db: &Connection, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
-    let embeddings_model = TextEmbedding::try_new(
-        InitOptions::new(EmbeddingModel::BGESmallENV15).with_show_download_progress(true),
-    )
-    .unwrap();
+///
+/// `k` is the number of nearest neighbors to consider and defaults to
+/// `limit`. Hits with a distance greater than `threshold` are dropped
+/// so weak matches don't get mixed in with the results.
+#[allow(clippy::too_many_arguments)]
+pub fn search_similar_notes(
+    db: &Connection,
+    query: &str,
+    limit: usize,
+    k: Option<usize>,
+    threshold: Option<f32>,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+    ann: Option<&AnnIndex>,
+) -> Result<Vec<SearchHit>> {
+    // Without the model there's nothing to compare to
+    let Some(embeddings_model) = embeddings_model() else {
+        return Ok(Vec::new());
+    };
     let query_vector = embeddings_model.embed(vec![query], None).unwrap();
-    let q = query_vector[0].clone();
-    let result: Vec<SearchHit> = db
-        .prepare(
+    search_similar_vector(
+        db,
+        &query_vector[0],
+        limit,
+        k,
+        threshold,
+        exclusions,
+        filters,
+        ann,
+    )
+}
+
+/// Same as `search_similar_notes` but with the embedding of the
+/// query.
+///
+/// Without filters this is a KNN query of the `k` nearest neighbors.
+/// With filters, the notes that match are found first and only those
+/// are compared to the query. Otherwise the nearest neighbors could
+/// all be filtered out leaving fewer results than expected. In that
+/// case `k` is the number of nearest matching notes.
+///
+/// If there is an `ann` index it's used to find the `k` approximate
+/// nearest neighbors instead, unless there are filters.
+#[allow(clippy::too_many_arguments)]
+pub fn search_similar_vector(
+    db: &Connection,
+    query_vector: &[f32],
+    limit: usize,
+    k: Option<usize>,
+    threshold: Option<f32>,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+    ann: Option<&AnnIndex>,
+) -> Result<Vec<SearchHit>> {
+    let k = k.unwrap_or(limit);
+    if let (Some(ann), true) = (ann, filters.is_empty()) {
+        let neighbors = ann.search(query_vector, k);
+        let ids: Vec<String> = neighbors.iter().map(|(id, _)| id.clone()).collect();
+        let allowed: HashSet<String> = exclusions.filter_ids(db, &ids)?.into_iter().collect();
+        return Ok(neighbors
+            .into_iter()
+            .filter(|(id, distance)| {
+                allowed.contains(id) && threshold.is_none_or(|t| *distance <= t)
+            })
+            .take(limit)
+            .map(|(id, distance)| SearchHit {
+                r#type: SearchHitType::Similarity,
+                id,
+                score: distance,
+            })
+            .collect());
+    }
+
+    // Binary embeddings only narrow down the candidates which are
+    // then rescored with the int8 embeddings
+    let format = VectorFormat::stored(db)?;
+    let oversample = if format == VectorFormat::Binary {
+        RESCORE_OVERSAMPLE
+    } else {
+        1
+    };
+    let query_param = format.quantize("?4");
+    let distance = if format == VectorFormat::Binary {
+        format!("vec_distance_hamming(vec_items.embedding, {query_param})")
+    } else {
+        format!("vec_distance_l2(vec_items.embedding, {query_param})")
+    };
+    // Notes have an embedding for each chunk so each note is as close
+    // as its closest chunk
+    let sql = if filters.is_empty() {
+        format!(
             r"
+          WITH neighbors AS MATERIALIZED (
+            SELECT note_meta_id, distance
+            FROM vec_items
+            WHERE embedding MATCH {query_param} AND k = ?5
+          )
           SELECT
             note_meta.id,
-            note_meta.file_name,
-            note_meta.title,
-            note_meta.tags,
-            note_meta.body,
-            distance
-          FROM vec_items
-          JOIN note_meta on note_meta_id=note_meta.id
-          AND LOWER(note_meta.title) NOT LIKE LOWER('%journal%')
-          WHERE embedding MATCH ? AND k = ?
-          ORDER BY distance
-          LIMIT ?
+            MIN(neighbors.distance) AS closest
+          FROM neighbors
+          JOIN note_meta on neighbors.note_meta_id=note_meta.id
+          AND {EXCLUSIONS_SQL}
+          AND {FILTERS_SQL}
+          GROUP BY note_meta.id
+          ORDER BY closest
+          LIMIT ?6
+        ",
+        )
+    } else {
+        format!(
+            r"
+          WITH neighbors AS MATERIALIZED (
+            SELECT
+              note_meta.id,
+              {distance} AS distance
+            FROM note_meta
+            JOIN vec_items ON vec_items.note_meta_id = note_meta.id
+            WHERE {EXCLUSIONS_SQL}
+            AND {FILTERS_SQL}
+          )
+          SELECT
+            id,
+            MIN(distance) AS closest
+          FROM neighbors
+          GROUP BY id
+          ORDER BY closest
+          LIMIT min(?5, ?6)
         ",
+        )
+    };
+    let mut hits: Vec<SearchHit> = db
+        .prepare(&sql)?
+        .query_map(
+            rusqlite::params![
+                json!(exclusions.types).to_string(),
+                json!(exclusions.tags).to_string(),
+                json!(exclusions.titles).to_string(),
+                query_vector.as_bytes(),
+                (k * oversample) as i64,
+                (limit * oversample) as i64,
+                json!(filters.types).to_string(),
+                json!(filters.tags).to_string(),
+                json!(filters.collections).to_string(),
+            ],
+            |r| {
+                Ok(SearchHit {
+                    r#type: SearchHitType::Similarity,
+                    id: r.get(0)?,
+                    score: r.get::<_, f32>(1)? * format.distance_scale(),
+                })
+            },
         )?
-        .query_map([q.as_bytes(), limit.as_bytes(), limit.as_bytes()], |r| {
+        .collect::<Result<Vec<SearchHit>, _>>()?;
+    if format == VectorFormat::Binary {
+        hits = rescore(db, query_vector, hits)?;
+        hits.truncate(limit);
+    }
+    Ok(hits
+        .into_iter()
+        .filter(|hit| threshold.is_none_or(|t| hit.score <= t))
+        .collect())
+}
+
+/// Number of candidates per result to rescore when embeddings are
+/// stored as binary
+const RESCORE_OVERSAMPLE: usize = 4;
+
+// Replace the distance of each hit with the distance to the int8
+// embedding and sort them by it
+fn rescore(db: &Connection, query_vector: &[f32], hits: Vec<SearchHit>) -> Result<Vec<SearchHit>> {
+    let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+    let int8 = VectorFormat::Int8;
+    db.prepare(&format!(
+        r"
+          WITH distances AS MATERIALIZED (
+            SELECT
+              vec_items_int8.note_meta_id,
+              vec_distance_l2(vec_items_int8.embedding, {}) AS distance
+            FROM json_each(?1) AS ids
+            JOIN vec_items_int8 ON vec_items_int8.note_meta_id = ids.value
+          )
+          SELECT
+            note_meta_id,
+            MIN(distance) AS closest
+          FROM distances
+          GROUP BY note_meta_id
+          ORDER BY closest
+        ",
+        int8.quantize("?2")
+    ))?
+    .query_map(
+        rusqlite::params![json!(ids).to_string(), query_vector.as_bytes()],
+        |r| {
             Ok(SearchHit {
                 r#type: SearchHitType::Similarity,
                 id: r.get(0)?,
-                score: r.get(5)?,
+                score: r.get::<_, f32>(1)? * int8.distance_scale(),
             })
-        })?
-        .collect::<Result<Vec<SearchHit>, _>>()?;
-    Ok(result)
+        },
+    )?
+    .collect()
 }
 
 #[derive(Serialize)]
@@ -115,35 +622,157 @@ pub struct SearchResult {
     id: String,
     r#type: String,
     title: String,
-    file_name: String,
+    // Feed items don't have a file
+    file_name: Option<String>,
     tags: Option<String>,
     is_task: bool,
     task_status: Option<String>,
     body: String,
+    scheduled: Option<String>,
+    deadline: Option<String>,
+    // Repeater of a recurring task e.g. `+1w`
+    repeat: Option<String>,
+    // Next due date of a recurring task that is done
+    next_occurrence: Option<String>,
+    // Collection of notes the result is from
+    collection: Option<String>,
+    word_count: Option<i64>,
+    heading_count: Option<i64>,
+    reading_time_minutes: Option<i64>,
+    // When the note file was last modified
+    modified_at: Option<String>,
+    // Raw similarity distance when the result came from the vector
+    // search, lower is more similar
+    distance: Option<f32>,
+}
+
+// Columns in `note_meta` needed to construct a `SearchResult` in the
+// order expected by `SearchResult::from_row`
+const SEARCH_RESULT_COLUMNS: &str = r"
+            note_meta.id,
+            note_meta.type,
+            note_meta.file_name,
+            note_meta.title,
+            note_meta.tags,
+            note_meta.body,
+            note_meta.status,
+            note_meta.scheduled,
+            note_meta.deadline,
+            note_meta.repeat,
+            note_meta.next_occurrence,
+            note_meta.collection,
+            note_meta.word_count,
+            note_meta.heading_count,
+            note_meta.modified_at";
+
+impl SearchResult {
+    fn from_row(r: &rusqlite::Row, distance: Option<f32>) -> Result<Self> {
+        let maybe_task_status: Option<String> = r.get(6)?;
+        let word_count: Option<i64> = r.get(12)?;
+        Ok(Self {
+            id: r.get(0)?,
+            r#type: r.get(1)?,
+            file_name: r.get(2)?,
+            title: r.get(3)?,
+            tags: r.get(4)?,
+            body: r.get(5)?,
+            is_task: maybe_task_status.is_some(),
+            task_status: maybe_task_status,
+            scheduled: r.get(7)?,
+            deadline: r.get(8)?,
+            repeat: r.get(9)?,
+            next_occurrence: r.get(10)?,
+            collection: r.get(11)?,
+            word_count,
+            heading_count: r.get(13)?,
+            reading_time_minutes: word_count.map(reading_time_minutes),
+            modified_at: r.get(14)?,
+            distance,
+        })
+    }
 }
 
 // Performs a full-text search of all notes for the given query. If
 // `include_similarity`, also includes vector search results appended
 // to the end of the list of results. This way, if there is a keyword
 // search miss, there may be semantically similar results.
+//
+// `similarity_k` and `similarity_threshold` are passed through to
+// `search_similar_notes`. Notes matching any of the `exclusions` are
+// removed from both full-text and similarity results and only notes
+// matching the `filters` are included.
+#[allow(clippy::too_many_arguments)]
 pub fn search_notes(
-    index_path: &str,
+    search_index: &SearchIndex,
     db: &Connection,
     include_similarity: bool,
     query: &str,
     limit: usize,
+    similarity_k: Option<usize>,
+    similarity_threshold: Option<f32>,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
 ) -> Vec<SearchResult> {
-    let search_hits = if include_similarity {
-        let mut result = fulltext_search(index_path, query, limit);
-        let similarity_query = query.replace("-title:journal ", "");
-        let mut vec_search_result =
-            search_similar_notes(db, &similarity_query, limit).unwrap_or_default();
+    // Similarity search is skipped if the embeddings model isn't
+    // available rather than trying to load it for every search
+    let query_vector = if include_similarity && embeddings_available() {
+        embeddings_model().map(|model| model.embed(vec![query], None).unwrap().remove(0))
+    } else {
+        None
+    };
+    search_notes_with_vector(
+        search_index,
+        db,
+        query,
+        query_vector.as_deref(),
+        limit,
+        similarity_k,
+        similarity_threshold,
+        exclusions,
+        filters,
+    )
+}
+
+/// Same as `search_notes` but with the embedding of the query. The
+/// similarity search is only included if there is a `query_vector`.
+#[allow(clippy::too_many_arguments)]
+pub fn search_notes_with_vector(
+    search_index: &SearchIndex,
+    db: &Connection,
+    query: &str,
+    query_vector: Option<&[f32]>,
+    limit: usize,
+    similarity_k: Option<usize>,
+    similarity_threshold: Option<f32>,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+) -> Vec<SearchResult> {
+    // Keep track of the similarity distance so it can be included in
+    // the results, even for notes that were also found by the full
+    // text search
+    let mut distances: HashMap<String, f32> = HashMap::new();
+
+    let search_hits = if let Some(query_vector) = query_vector {
+        let mut result = fulltext_search(search_index, query, limit, exclusions, filters);
+        let ann = search_index.ann.read().unwrap_or_else(|e| e.into_inner());
+        let mut vec_search_result = search_similar_vector(
+            db,
+            query_vector,
+            limit,
+            similarity_k,
+            similarity_threshold,
+            exclusions,
+            filters,
+            ann.as_ref(),
+        )
+        .unwrap_or_default();
+        distances.extend(vec_search_result.iter().map(|i| (i.id.clone(), i.score)));
 
         // Combine the results, dedupe, then sort by score
         result.append(&mut vec_search_result);
         result.into_iter().unique_by(|i| i.id.clone()).collect()
     } else {
-        fulltext_search(index_path, query, limit)
+        fulltext_search(search_index, query, limit, exclusions, filters)
     };
 
     // Search the db for the metadata and construct results
@@ -152,33 +781,18 @@ pub fn search_notes(
     let result_ids_str = result_ids_serialized.to_string();
 
     let results: Vec<SearchResult> = db
-        .prepare(
+        .prepare(&format!(
             r"
-          SELECT
-            id,
-            type,
-            file_name,
-            title,
-            tags,
-            body,
-            status
+          SELECT {}
           FROM note_meta
           WHERE note_meta.id in (SELECT value from json_each(?))
         ",
-        )
+            SEARCH_RESULT_COLUMNS
+        ))
         .unwrap()
         .query_map([result_ids_str.as_bytes()], |r| {
-            let maybe_task_status: Option<String> = r.get(6)?;
-            Ok(SearchResult {
-                id: r.get(0)?,
-                r#type: r.get(1)?,
-                file_name: r.get(2)?,
-                title: r.get(3)?,
-                tags: r.get(4)?,
-                body: r.get(5)?,
-                is_task: maybe_task_status.is_some(),
-                task_status: maybe_task_status,
-            })
+            let id: String = r.get(0)?;
+            SearchResult::from_row(r, distances.get(&id).copied())
         })
         .unwrap()
         .collect::<Result<Vec<SearchResult>, _>>()
@@ -186,3 +800,255 @@ pub fn search_notes(
 
     results
 }
+
+/// Notes most similar to the embedding e.g. of text being written,
+/// ordered by ascending distance. Arguments are the same as
+/// `search_similar_vector`.
+#[allow(clippy::too_many_arguments)]
+pub fn similar_notes(
+    search_index: &SearchIndex,
+    db: &Connection,
+    query_vector: &[f32],
+    limit: usize,
+    k: Option<usize>,
+    threshold: Option<f32>,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+) -> Result<Vec<SearchResult>> {
+    let ann = search_index.ann.read().unwrap_or_else(|e| e.into_inner());
+    let hits = search_similar_vector(
+        db,
+        query_vector,
+        limit,
+        k,
+        threshold,
+        exclusions,
+        filters,
+        ann.as_ref(),
+    )?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT {}, NULL FROM note_meta WHERE note_meta.id = ?1",
+        SEARCH_RESULT_COLUMNS
+    ))?;
+    let mut results = Vec::new();
+    for hit in hits {
+        let result = stmt
+            .query_row([&hit.id], |r| SearchResult::from_row(r, Some(hit.score)))
+            .optional()?;
+        results.extend(result);
+    }
+    Ok(results)
+}
+
+/// Returns the related notes for the note ID that were computed at
+/// indexing time, ordered by ascending distance.
+pub fn related_notes(db: &Connection, id: &str) -> Result<Vec<SearchResult>> {
+    db.prepare(&format!(
+        r"
+          SELECT {}, note_related.distance
+          FROM note_related
+          JOIN note_meta ON note_meta.id = note_related.related_id
+          WHERE note_related.note_meta_id = ?
+          ORDER BY note_related.distance
+        ",
+        SEARCH_RESULT_COLUMNS
+    ))?
+    .query_map([id], |r| SearchResult::from_row(r, r.get(15)?))?
+    .collect::<Result<Vec<SearchResult>, _>>()
+}
+
+/// Favorite notes, most recently added first. Favorites for notes
+/// that are no longer indexed are skipped.
+pub fn favorite_notes(db: &Connection) -> Result<Vec<SearchResult>> {
+    db.prepare(&format!(
+        r"
+          SELECT {}, NULL
+          FROM note_favorite
+          JOIN note_meta ON note_meta.id = note_favorite.note_meta_id
+          ORDER BY note_favorite.created_at DESC, note_favorite.rowid DESC
+        ",
+        SEARCH_RESULT_COLUMNS
+    ))?
+    .query_map([], |r| SearchResult::from_row(r, None))?
+    .collect::<Result<Vec<SearchResult>, _>>()
+}
+
+/// A random note matching the filters, skipping excluded notes.
+/// Only notes are picked unless the filters include other types. The
+/// same seed always picks the same note e.g. to resurface a note of
+/// the day.
+pub fn random_note(
+    db: &Connection,
+    exclusions: &SearchExclusions,
+    filters: &SearchFilters,
+    seed: Option<u64>,
+) -> Result<Option<SearchResult>> {
+    let mut filters = filters.clone();
+    if filters.types.is_empty() {
+        filters.types = vec!["note".to_string()];
+    }
+    // Mix the seed so consecutive seeds don't pick neighboring notes
+    let seed = match seed {
+        Some(seed) => seed.wrapping_mul(0x9E37_79B9_7F4A_7C15),
+        None => rand::random::<u64>(),
+    };
+
+    db.query_row(
+        &format!(
+            r"
+              SELECT {SEARCH_RESULT_COLUMNS}, NULL
+              FROM note_meta
+              WHERE {EXCLUSIONS_SQL}
+              AND {FILTERS_SQL}
+              ORDER BY note_meta.id
+              LIMIT 1 OFFSET ?6 % max(1, (
+                SELECT COUNT(*) FROM note_meta
+                WHERE {EXCLUSIONS_SQL}
+                AND {FILTERS_SQL}
+              ))
+            "
+        ),
+        rusqlite::params![
+            json!(exclusions.types).to_string(),
+            json!(exclusions.tags).to_string(),
+            json!(exclusions.titles).to_string(),
+            // Only used by similarity search
+            rusqlite::types::Null,
+            rusqlite::types::Null,
+            (seed >> 1) as i64,
+            json!(filters.types).to_string(),
+            json!(filters.tags).to_string(),
+            json!(filters.collections).to_string(),
+        ],
+        |r| SearchResult::from_row(r, None),
+    )
+    .optional()
+}
+
+/// Metadata about a note or task without the full body
+#[derive(Serialize)]
+pub struct NoteSummary {
+    id: String,
+    r#type: String,
+    title: String,
+    file_name: Option<String>,
+    tags: Option<String>,
+    status: Option<String>,
+    scheduled: Option<String>,
+    deadline: Option<String>,
+    snippet: String,
+}
+
+const SNIPPET_LEN: usize = 200;
+
+// Returns the start of the text of the body skipping over org
+// metadata like property drawers and keywords
+fn snippet(body: &str) -> String {
+    let text = body
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with(':') && !l.starts_with("#+"))
+        .join(" ");
+    text.chars().take(SNIPPET_LEN).collect()
+}
+
+/// Returns a summary of each note or task in the same order as the
+/// IDs. IDs that don't exist are skipped.
+pub fn note_summaries(db: &Connection, ids: &[String]) -> Result<Vec<NoteSummary>> {
+    db.prepare(
+        r"
+          SELECT
+            note_meta.id,
+            note_meta.type,
+            note_meta.title,
+            note_meta.file_name,
+            note_meta.tags,
+            note_meta.status,
+            note_meta.scheduled,
+            note_meta.deadline,
+            note_meta.body
+          FROM json_each(?1) AS ids
+          JOIN note_meta ON note_meta.id = ids.value
+          ORDER BY ids.key
+        ",
+    )?
+    .query_map([json!(ids).to_string()], |r| {
+        let body: String = r.get(8)?;
+        Ok(NoteSummary {
+            id: r.get(0)?,
+            r#type: r.get(1)?,
+            title: r.get(2)?,
+            file_name: r.get(3)?,
+            tags: r.get(4)?,
+            status: r.get(5)?,
+            scheduled: r.get(6)?,
+            deadline: r.get(7)?,
+            snippet: snippet(&body),
+        })
+    })?
+    .collect()
+}
+
+/// A term the full text query matches on a field
+#[derive(Serialize)]
+pub struct QueryTerm {
+    field: String,
+    text: String,
+}
+
+/// How a query is run against the full text index and similarity
+/// search
+#[derive(Serialize)]
+pub struct QueryExplanation {
+    query: String,
+    /// The parsed full text query including exclusions
+    full_text_query: String,
+    /// Terms the full text query looks up in each field
+    terms: Vec<QueryTerm>,
+    /// Similarity search embeds the query as is and applies
+    /// exclusions with this SQL
+    similarity_sql: String,
+    similarity_params: serde_json::Value,
+}
+
+/// Explain how the query is parsed and which fields and backends it
+/// uses without running it
+pub fn explain_query(
+    search_index: &SearchIndex,
+    query: &str,
+    exclusions: &SearchExclusions,
+) -> std::result::Result<QueryExplanation, QueryParserError> {
+    let schema = note_schema();
+    let parsed = parse_query(
+        &search_index.index,
+        &schema,
+        query,
+        exclusions,
+        &SearchFilters::default(),
+    )?;
+
+    let mut terms = Vec::new();
+    parsed.query_terms(&mut |term, _| {
+        let text = term
+            .value()
+            .as_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("{:?}", term));
+        terms.push(QueryTerm {
+            field: schema.get_field_name(term.field()).to_string(),
+            text,
+        });
+    });
+
+    Ok(QueryExplanation {
+        query: query.to_string(),
+        full_text_query: format!("{:?}", parsed),
+        terms,
+        similarity_sql: EXCLUSIONS_SQL.trim().to_string(),
+        similarity_params: json!({
+            "types": exclusions.types,
+            "tags": exclusions.tags,
+            "titles": exclusions.titles,
+        }),
+    })
+}
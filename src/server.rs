@@ -1,10 +1,19 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex, RwLock};
 
-use axum::response::Html;
+use axum::body::Body;
+use chrono::{Datelike, Local, NaiveDate};
+use axum::extract::Request;
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum_server::tls_rustls::RustlsConfig;
 use tantivy::doc;
 
 use axum::extract::Query;
@@ -12,35 +21,102 @@ use axum::{
     extract::{Path, State},
     response::Json,
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
-use orgize::ParseConfig;
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
+use tantivy::IndexWriter;
 use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
 use serde_json::{json, Value};
-use tower_http::cors::CorsLayer;
+use tokio::time::{Duration, Instant};
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
+use tokio_stream::{Stream, StreamExt};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::indexing::index_all;
+use crate::indexing::{
+    embeddings_available, embeddings_model, EMBEDDING_MODEL, index_all, index_all_with_progress, indexed_body, parse_org, reading_time_minutes, IndexConfig, IndexService,
+    DEFAULT_COLLECTION,
+};
 
-use super::db::vector_db;
-use super::git::{diff_last_commit_files, maybe_pull_and_reset_repo};
-use super::search::{search_notes, SearchResult};
+use super::ann::{ann_vectors, AnnConfig, AnnIndex};
+use super::db::{set_vector_format, vector_db, VectorFormat, EMBEDDING_DIMENSIONS};
+use super::audit::{audit_log, record_audit};
+use super::changes::note_changes;
+use super::dates::{date_mentions, DATE_FORMAT};
+use super::diff::{unified_diff, DiffAgainst};
+use super::favorites::set_favorite;
+use super::lint::{lint_sources, NoteLint};
+use super::hash::sha256_hex;
+use super::highlights::{highlights_by_id, Highlight, HIGHLIGHT_TYPE};
+use super::git::{commit_and_push_repo, file_at_revision, maybe_pull_and_reset_repo, GitAuth, PushError};
+use super::bookmarks::fetch_bookmark;
+use super::clock::{time_report, GroupBy};
+use super::capture::{append_capture, append_capture_at, CaptureEntry};
+use super::feeds::{fetch_feed, index_feed_items, mark_feed_item_read, FeedConfig};
+use super::ical::{calendar_tasks, render_calendar};
+use super::links::{backlinks, broken_links, orphan_notes, render_html};
+use super::share::{create_share_link, revoke_share_links, shared_note};
+use super::conflicts::id_conflicts;
+use super::meetings::{parse_transcript, Meeting, TranscriptFormat};
+use super::reading::{enqueue_reading, reading_list, update_reading_progress, ReadingStatus};
+use super::quarantine::{clear_index_error, index_error, index_errors};
+use super::runs::{latest_index_run, queue_index_run, save_index_run, IndexRunStatus};
+use super::refile::{refile, RefileTarget};
+use super::tasks::{set_task_status, task_board, BoardColumn};
+use super::tags::{is_valid_tag, note_tag_tree, rename_tag};
+use super::markdown::{is_markdown, markdown_to_org};
+use super::source::{read_note, GitChanges, OrgDirectory, SourceProvider};
+use super::sync::{
+    release_key, reserve_key, save_response, saved_response, KeyReservation, SavedResponse,
+    SyncBatchRequest, SyncOperation, SyncRequest, SyncResult, IDEMPOTENCY_KEY_HEADER,
+};
+use super::search::{
+    explain_query, favorite_notes, fulltext_search, note_summaries, random_note, related_notes, search_notes, similar_notes, suggest, SearchExclusions,
+    SearchFilters, SearchIndex, SearchResult,
+};
 
 type SharedState = Arc<RwLock<AppState>>;
 
 pub struct AppConfig {
     pub notes_path: String,
     pub index_path: String,
+    pub search_exclusions: SearchExclusions,
+    pub index_config: IndexConfig,
+    // Secret token required to access the calendar feed, if set
+    pub ical_token: Option<String>,
+    // Secret for signing public share links
+    pub share_secret: String,
+    // Columns of the task board and the statuses in each
+    pub board_columns: Vec<BoardColumn>,
+    // API tokens for quick adds keyed by device name
+    pub quick_add_tokens: BTreeMap<String, String>,
+    // Browser origins allowed to call the API e.g. the
+    // `chrome-extension://` origin of an extension
+    pub cors_origins: Vec<String>,
+    // Approximate nearest neighbor search for similarity search
+    pub ann: AnnConfig,
+    // Serve over HTTPS with the certificate, if set
+    pub tls: Option<TlsConfig>,
+    // Keep-alive and heartbeats for server-sent events
+    pub sse: SseConfig,
+}
+
+// PEM encoded certificate chain and private key for serving HTTPS
+// directly rather than behind a reverse proxy
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct LastSelection {
     id: String,
     title: String,
-    file_name: String,
+    file_name: Option<String>,
 }
 
 pub struct AppState {
@@ -48,18 +124,56 @@ pub struct AppState {
     latest_selection: Option<LastSelection>,
     db: Mutex<Connection>,
     config: AppConfig,
+    // Full text index reader shared between searches
+    search_index: SearchIndex,
+    // The only writer to the full text index
+    index_service: IndexService,
 }
 
 impl AppState {
     pub fn new(db: Connection, config: AppConfig) -> Self {
+        let search_index = SearchIndex::open(&config.index_path);
+        let vectors = ann_vectors(&db, &config.ann).expect("Loading embeddings failed");
+        search_index.set_ann(vectors.map(|v| AnnIndex::build(v, config.ann.probes)));
         Self {
             latest_selection: None,
             db: Mutex::new(db),
+            search_index,
+            index_service: IndexService::open(&config.index_path),
             config,
         }
     }
 }
 
+// Rebuild the approximate nearest neighbor index so new embeddings
+// are included. Building is slow for many embeddings so it runs in
+// the background and searches use the previous index until it's done.
+fn rebuild_ann(state: &SharedState) {
+    let state = Arc::clone(state);
+    tokio::task::spawn_blocking(move || {
+        let (search_index, ann_config, vectors) = {
+            let shared_state = state.read().expect("Unable to read share state");
+            let db = shared_state
+                .db
+                .lock()
+                // Ignoring any previous panics since we are trying to get the
+                // db connection and it's probably fine
+                .unwrap_or_else(|e| e.into_inner());
+            (
+                shared_state.search_index.clone(),
+                shared_state.config.ann.clone(),
+                ann_vectors(&db, &shared_state.config.ann),
+            )
+        };
+        match vectors {
+            Ok(vectors) => {
+                search_index.set_ann(vectors.map(|v| AnnIndex::build(v, ann_config.probes)))
+            }
+            Err(err) => tracing::error!("Loading embeddings failed: {}", err),
+        }
+    });
+}
+
 async fn kv_get(State(state): State<SharedState>) -> Json<Option<Value>> {
     if let Some(LastSelection {
         id,
@@ -86,44 +200,149 @@ async fn kv_set(State(state): State<SharedState>, Json(data): Json<LastSelection
     });
 }
 
+#[derive(Debug, Deserialize)]
+struct SearchRequest {
+    query: Option<String>,
+    #[serde(default)]
+    include_similarity: bool,
+    // Number of nearest neighbors to consider for similarity search
+    similarity_k: Option<usize>,
+    // Maximum similarity distance for a hit to be included
+    similarity_threshold: Option<f32>,
+    // Comma separated filters applied to all results
+    r#type: Option<String>,
+    tags: Option<String>,
+    collection: Option<String>,
+}
+
+// Split a comma separated query param into its values
+fn comma_separated(v: &Option<String>) -> Vec<String> {
+    v.as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl SearchRequest {
+    fn filters(&self) -> SearchFilters {
+        SearchFilters {
+            types: comma_separated(&self.r#type),
+            tags: comma_separated(&self.tags),
+            collections: comma_separated(&self.collection),
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct SearchResponse {
     query: Option<String>,
     results: Vec<SearchResult>,
+    // False when similarity results were skipped because the
+    // embeddings model isn't available
+    similarity_available: bool,
 }
 
 // Fulltext search of all notes
 async fn search(
     State(state): State<SharedState>,
-    Query(params): Query<HashMap<String, String>>,
+    Query(params): Query<SearchRequest>,
 ) -> Json<SearchResponse> {
-    let query = params.get("query");
+    let filters = params.filters();
+    let query = params.query;
     let shared_state = state.read().unwrap();
-    let index_path = &shared_state.config.index_path;
+    let exclusions = &shared_state.config.search_exclusions;
     // Ignoring any previous panics since we are trying to get the
     // db connection and it's probably fine
     let db = shared_state.db.lock().unwrap_or_else(|e| e.into_inner());
 
-    let results = if let Some(query) = query {
-        let include_similarity = params.contains_key("include_similarity")
-            && params.get("include_similarity").unwrap() == "true";
-        search_notes(index_path, &db, include_similarity, query, 20)
+    let results = if let Some(query) = &query {
+        search_notes(
+            &shared_state.search_index,
+            &db,
+            params.include_similarity,
+            query,
+            20,
+            params.similarity_k,
+            params.similarity_threshold,
+            exclusions,
+            &filters,
+        )
     } else {
         Vec::new()
     };
 
     let resp = SearchResponse {
-        query: query.map(|s| s.to_string()),
+        query,
         results,
+        similarity_available: embeddings_available(),
     };
 
     Json(resp)
 }
 
-// Build the index for all notes
-async fn index_notes(State(state): State<SharedState>) -> Json<Value> {
+#[derive(Debug, Deserialize)]
+struct IndexRequest {
+    // Re-index every note rather than just the ones that changed
+    #[serde(default)]
+    full: bool,
+    // Index notes even if their file hasn't changed since it was
+    // last indexed
+    #[serde(default)]
+    force: bool,
+    // Only re-index these files relative to the notes directory
+    paths: Option<Vec<String>>,
+    // Regenerate embeddings for the notes
+    #[serde(default = "default_true")]
+    embeddings: bool,
+    // Pull the latest from origin before indexing
+    #[serde(default = "default_true")]
+    pull: bool,
+    // Only index this collection. Paths are relative to the
+    // collection's directory.
+    collection: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for IndexRequest {
+    fn default() -> Self {
+        Self {
+            full: false,
+            force: false,
+            paths: None,
+            embeddings: true,
+            pull: true,
+            collection: None,
+        }
+    }
+}
+
+// Build the index for all notes. By default this pulls the latest
+// from origin and indexes the notes changed in the last commit.
+async fn index_notes(
+    State(state): State<SharedState>,
+    payload: Option<Json<IndexRequest>>,
+) -> Response {
+    let Json(params) = payload.unwrap_or_default();
     let shared_state = state.read().expect("Unable to read share state");
 
+    // Only one index can run at a time. Rather than waiting, let the
+    // caller know so it can try again later.
+    let Some(mut index_writer) = shared_state.index_service.try_writer() else {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "success": false,
+                "error": "Indexing already in progress",
+            })),
+        )
+            .into_response();
+    };
+
     let mut db = shared_state
         .db
         .lock()
@@ -132,34 +351,332 @@ async fn index_notes(State(state): State<SharedState>) -> Json<Value> {
         .unwrap_or_else(|e| e.into_inner());
 
     let AppConfig {
-        index_path,
         notes_path,
-    } = &state.read().expect("Failed to read state").config;
-    let deploy_key_path = env::var("INDEXER_NOTES_DEPLOY_KEY_PATH")
-        .expect("Missing env var INDEXER_NOTES_DEPLOY_KEY_PATH");
-
-    // Pull the latest from origin
-    maybe_pull_and_reset_repo(&deploy_key_path, notes_path);
-
-    // Determine which notes changed
-    let diff = diff_last_commit_files(&deploy_key_path, notes_path);
-    // NOTE: This assumes all notes are in one directory at the root
-    // of `notes_path`. This will not work if note files are in
-    // different directories!
-    let paths: Vec<PathBuf> = diff
-        .iter()
-        .map(|f| PathBuf::from(format!("{}/{}", notes_path, f)))
+        index_config,
+        ..
+    } = &shared_state.config;
+    let index_config = &IndexConfig {
+        force: params.force,
+        ..index_config.clone()
+    };
+    let auth = GitAuth::from_env();
+
+    // Pulling and diffing the last commit both need credentials for
+    // the remote
+    let needs_git = params.pull || (!params.full && params.paths.is_none());
+    if needs_git && !auth.is_configured() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "Missing env var INDEXER_NOTES_DEPLOY_KEY_PATH or INDEXER_NOTES_GIT_TOKEN",
+            })),
+        )
+            .into_response();
+    }
+
+    // The main notes directory and any other collections
+    let collections: Vec<(&str, &str)> = std::iter::once((DEFAULT_COLLECTION, notes_path.as_str()))
+        .chain(
+            index_config
+                .collections
+                .iter()
+                .map(|c| (c.name.as_str(), c.path.as_str())),
+        )
+        .filter(|(name, _)| params.collection.as_deref().is_none_or(|c| c == *name))
         .collect();
-    let filter_paths = if paths.is_empty() { None } else { Some(paths) };
+    if collections.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "Unknown collection",
+            })),
+        )
+            .into_response();
+    }
+
+    // Keep track of the run so callers can check on it with
+    // `/notes/index/status`
+    let mut run = queue_index_run(&db, "api").expect("Failed to save index run");
+    run.status = IndexRunStatus::Running;
+    save_index_run(&db, &run).expect("Failed to save index run");
+    shared_state.index_service.set_run(Some(run));
+    let started = Instant::now();
+
+    let mut error = None;
+    for (_, path) in collections {
+        // Pull the latest from origin
+        if params.pull {
+            maybe_pull_and_reset_repo(&auth, path);
+        }
+
+        let notes_dir = OrgDirectory::new(path, index_config.age_identity_path.clone())
+            .with_walk(index_config.walk.clone());
+        let source: Box<dyn SourceProvider> = if params.full {
+            Box::new(notes_dir)
+        } else if let Some(paths) = &params.paths {
+            let paths = paths
+                .iter()
+                .map(|p| std::path::Path::new(path).join(p))
+                .collect();
+            Box::new(notes_dir.with_paths(paths))
+        } else {
+            // Re-index just the notes that changed
+            Box::new(GitChanges::new(notes_dir, &auth))
+        };
+        if let Err(err) = index_all_with_progress(
+            &mut db,
+            &mut index_writer,
+            source.as_ref(),
+            true,
+            params.embeddings,
+            index_config,
+            &shared_state.index_service.progress(),
+        ) {
+            error = Some(err.to_string());
+            break;
+        }
+    }
+
+    let mut run = shared_state
+        .index_service
+        .set_run(None)
+        .expect("Index run is missing");
+    run.finish(started.elapsed(), error.clone());
+    save_index_run(&db, &run).expect("Failed to save index run");
+    shared_state.search_index.reload();
 
-    // Re-index just the notes that changed
-    index_all(&mut db, index_path, notes_path, true, true, filter_paths)
-        .expect("Vector indexing failed");
+    if let Some(error) = error {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "success": false,
+                "error": error,
+                "run": run,
+            })),
+        )
+            .into_response();
+    }
+    if params.embeddings {
+        rebuild_ann(&state);
+    }
 
     let resp = json!({
         "success": true,
+        "run": run,
     });
-    Json(resp)
+    Json(resp).into_response()
+}
+
+// The indexing run that's queued or in progress, otherwise the last
+// one that finished. The run in progress is kept in memory since the
+// db is busy while indexing.
+async fn notes_index_status(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    if let Some(run) = shared_state.index_service.current_run() {
+        return Json(json!({ "run": run }));
+    }
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let run = latest_index_run(&db).expect("Index run query failed");
+    Json(json!({ "run": run }))
+}
+
+// Embed every note again in the background e.g. after the vector
+// format changed. Waits for any indexing in progress to finish.
+async fn reembed_notes(state: SharedState) {
+    let index_service = state
+        .read()
+        .expect("Unable to read share state")
+        .index_service
+        .clone();
+    // Queued until any indexing in progress finishes
+    let run = {
+        let shared_state = state.read().expect("Unable to read share state");
+        let db = shared_state.db.lock().unwrap_or_else(|e| e.into_inner());
+        queue_index_run(&db, "reembed").expect("Failed to save index run")
+    };
+    let mut index_writer = index_service.writer().await;
+    let task_state = Arc::clone(&state);
+    let result = tokio::task::spawn_blocking(move || {
+        let shared_state = task_state.read().expect("Unable to read share state");
+        let mut db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        let mut run = run;
+        run.status = IndexRunStatus::Running;
+        save_index_run(&db, &run)?;
+        shared_state.index_service.set_run(Some(run));
+        let started = Instant::now();
+        let AppConfig {
+            notes_path,
+            index_config,
+            ..
+        } = &shared_state.config;
+        let paths = std::iter::once(notes_path.as_str())
+            .chain(index_config.collections.iter().map(|c| c.path.as_str()));
+        let mut result = Ok(());
+        for path in paths {
+            let notes_dir = OrgDirectory::new(path, index_config.age_identity_path.clone())
+                .with_walk(index_config.walk.clone());
+            result = index_all_with_progress(
+                &mut db,
+                &mut index_writer,
+                &notes_dir,
+                false,
+                true,
+                index_config,
+                &shared_state.index_service.progress(),
+            );
+            if result.is_err() {
+                break;
+            }
+        }
+        let mut run = shared_state
+            .index_service
+            .set_run(None)
+            .expect("Index run is missing");
+        run.finish(started.elapsed(), result.as_ref().err().map(|e| e.to_string()));
+        save_index_run(&db, &run)?;
+        anyhow::Ok(result?)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => rebuild_ann(&state),
+        Ok(Err(err)) => tracing::error!("Embedding notes failed: {}", err),
+        Err(err) => tracing::error!("Embedding notes failed: {}", err),
+    }
+}
+
+// Stream the progress of indexing as server-sent events so clients
+// can show which notes are being indexed. Each event is JSON with
+// the kind of event in `event`.
+async fn index_progress_stream(
+    State(state): State<SharedState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let events = shared_state.index_service.subscribe();
+    let stream = BroadcastStream::new(events).filter_map(|event| {
+        // Skip events that were dropped because the client fell
+        // behind rather than ending the stream
+        let event = event.ok()?;
+        Some(Ok(Event::default().json_data(event).expect("Failed to serialize event")))
+    });
+    shared_state.config.sse.sse(stream)
+}
+
+// Notes that failed to index and whether they are quarantined
+async fn notes_index_errors(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let errors = index_errors(&db).expect("Index errors query failed");
+    Json(json!({ "errors": errors }))
+}
+
+// Notes that weren't indexed because another file has the same ID
+async fn notes_index_conflicts(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let conflicts = id_conflicts(&db).expect("ID conflicts query failed");
+    Json(json!({ "conflicts": conflicts }))
+}
+
+// Directory of the collection the note is in so it's indexed and
+// pushed with the rest of its repo
+fn collection_root<'a>(config: &'a AppConfig, file_name: &str) -> &'a str {
+    let name = config.index_config.collection_for(file_name);
+    config
+        .index_config
+        .collections
+        .iter()
+        .find(|c| c.name == name)
+        .map(|c| c.path.as_str())
+        .unwrap_or(&config.notes_path)
+}
+
+#[derive(Debug, Deserialize)]
+struct RetryRequest {
+    // Regenerate embeddings for the note
+    #[serde(default = "default_true")]
+    embeddings: bool,
+}
+
+// Index a note that failed again, even if it's quarantined
+async fn retry_index_error(
+    State(state): State<SharedState>,
+    Path(id): Path<i64>,
+    payload: Option<Json<RetryRequest>>,
+) -> Response {
+    let embeddings = payload.map(|Json(p)| p.embeddings).unwrap_or(true);
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let Some(failed) = index_error(&db, id).expect("Index error query failed") else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Index error not found" })),
+        )
+            .into_response();
+    };
+
+    // Start over so the note isn't skipped as quarantined
+    clear_index_error(&db, &failed.file_name).expect("Clearing index error failed");
+    let index_config = &shared_state.config.index_config;
+    let root = collection_root(&shared_state.config, &failed.file_name);
+    let source = OrgDirectory::new(root, index_config.age_identity_path.clone())
+        .with_walk(index_config.walk.clone())
+        .with_paths(vec![PathBuf::from(&failed.file_name)]);
+    index_all(
+        &mut db,
+        &mut index_writer,
+        &source,
+        true,
+        embeddings,
+        index_config,
+    )
+    .expect("Indexing failed");
+    shared_state.search_index.reload();
+    if embeddings {
+        rebuild_ann(&state);
+    }
+
+    let still_failing = index_errors(&db)
+        .expect("Index errors query failed")
+        .into_iter()
+        .find(|e| e.file_name == failed.file_name);
+    match still_failing {
+        Some(err) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "success": false, "error": err.error })),
+        )
+            .into_response(),
+        None => Json(json!({ "success": true })).into_response(),
+    }
 }
 
 // Render a note in org-mode format by ID
@@ -168,7 +685,7 @@ async fn view_note(
     State(state): State<SharedState>,
     // This is the org-id of the note
     Path(id): Path<String>,
-) -> Html<String> {
+) -> Response {
     let shared_state = state.read().expect("Unable to read share state");
 
     let db = shared_state
@@ -188,6 +705,7 @@ async fn view_note(
             tags
           FROM note_meta
           WHERE id = ?
+          AND file_name IS NOT NULL
           LIMIT 1
         ",
         )
@@ -196,83 +714,2312 @@ async fn view_note(
         .expect("Query failed")
         .collect::<Result<Vec<String>, _>>()
         .expect("Query failed");
-    let file_name = result.first();
-    if let Some(f) = file_name {
-        let content = fs::read_to_string(f).expect("Failed to get file content");
+    let Some(file_name) = result.first() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let index_config = &shared_state.config.index_config;
+    let content = match read_note_content(file_name, index_config) {
+        Ok(content) => content,
+        Err(status) => return status.into_response(),
+    };
 
-        // Render the org-mode content in HTML
-        let config = ParseConfig {
-            ..Default::default()
-        };
-        // TODO: replace org-id links with note viewer links
-        let output = config.parse(content).to_html();
+    // Render the org-mode content in HTML with links to other
+    // notes pointing to the note viewer
+    let output = render_html(&db, &parse_org(&content, &index_config.keywords));
 
-        Html(output)
-    } else {
-        // TODO replace with a 404
-        Html("".to_string())
-    }
+    Html(output).into_response()
 }
 
-pub fn app(app_state: AppState) -> Router {
-    let shared_state = SharedState::new(RwLock::new(app_state));
-    let cors = CorsLayer::permissive();
-    let serve_dir = ServeDir::new("./web-ui/src");
+// Read the note as org-mode the same way it's indexed. The file may
+// have been deleted since it was last indexed.
+fn read_note_content(file_name: &str, index_config: &IndexConfig) -> Result<String, StatusCode> {
+    read_note(
+        std::path::Path::new(file_name),
+        index_config.age_identity_path.as_deref(),
+    )
+    .map_err(|err| {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            StatusCode::NOT_FOUND
+        } else {
+            tracing::error!("Failed to read note {}: {}", file_name, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })
+}
 
-    Router::new()
-        // Search API endpoint
-        .route("/notes/search", get(search))
-        // Storage for selected search hits
-        .route("/notes/search/latest", get(kv_get).post(kv_set))
-        // Index content endpoint
-        .route("/notes/index", post(index_notes))
-        // View a specific note
-        .route("/notes/:id/view", get(view_note))
-        // Static server of assets in ./web-ui
-        .nest_service("/", serve_dir.clone())
-        .layer(TraceLayer::new_for_http())
-        .layer(cors)
-        .with_state(Arc::clone(&shared_state))
+#[derive(Debug, Deserialize)]
+struct ShareRequest {
+    // How long the link works for, defaults to a week
+    expires_in_hours: Option<i64>,
 }
 
-// Run the server
-pub async fn serve(
-    host: String,
-    port: String,
-    notes_path: String,
-    index_path: String,
-    vec_db_path: String,
-) {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                    .into()
-            }),
+// Create a public link for sharing a note by ID
+async fn share_note(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+    Query(params): Query<ShareRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let hours = params.expires_in_hours.unwrap_or(24 * 7);
+    if hours < 1 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "expires_in_hours must be at least 1" })),
         )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-    let db = vector_db(&vec_db_path).expect("Failed to connect to db");
-    let app_config = AppConfig {
-        notes_path,
-        index_path,
+            .into_response();
+    }
+
+    let link = create_share_link(
+        &db,
+        &shared_state.config.share_secret,
+        &id,
+        chrono::Duration::hours(hours),
+    )
+    .expect("Creating share link failed");
+    match link {
+        Some(link) => Json(json!({
+            "success": true,
+            "url": format!("/share/{}", link.token),
+            "token": link.token,
+            "expires_at": link.expires_at.to_rfc3339(),
+        }))
+        .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Note not found" })),
+        )
+            .into_response(),
+    }
+}
+
+// Revoke all public links for a note by ID
+async fn unshare_note(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let revoked = revoke_share_links(&db, &id).expect("Revoking share links failed");
+    Json(json!({ "success": true, "revoked": revoked }))
+}
+
+// Render a shared note in HTML for anyone with the token. Links to
+// other notes are not rewritten since those notes aren't shared.
+async fn view_shared_note(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let shared = shared_note(&db, &shared_state.config.share_secret, &token)
+        .expect("Share link query failed");
+    // Expired, revoked, and invalid links are indistinguishable so
+    // tokens can't be probed
+    let Some((_id, file_name)) = shared else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let index_config = &shared_state.config.index_config;
+    let content = match read_note_content(&file_name, index_config) {
+        Ok(content) => content,
+        Err(status) => return status.into_response(),
     };
-    let app_state = AppState::new(db, app_config);
-    let app = app(app_state);
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", host, port))
-        .await
-        .unwrap();
+    Html(parse_org(&content, &index_config.keywords).to_html()).into_response()
+}
 
-    tracing::debug!(
-        "Server started. Listening on {}",
-        listener.local_addr().unwrap()
-    );
+#[derive(Serialize)]
+struct RelatedResponse {
+    id: String,
+    results: Vec<SearchResult>,
+}
+
+// Related notes for a note by ID. These are computed at indexing
+// time so this is a lookup rather than a similarity search.
+async fn related(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+) -> Json<RelatedResponse> {
+    let shared_state = state.read().expect("Unable to read share state");
+
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let results = related_notes(&db, &id).expect("Related notes query failed");
+
+    Json(RelatedResponse { id, results })
+}
+
+#[derive(Debug, Deserialize)]
+struct HighlightSearchRequest {
+    query: String,
+    // Only highlights from sources containing this
+    source: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HighlightSearchResponse {
+    query: String,
+    results: Vec<Highlight>,
+}
+
+// Full text search of just the quotes in notes
+async fn search_highlights(
+    State(state): State<SharedState>,
+    Query(params): Query<HighlightSearchRequest>,
+) -> Json<HighlightSearchResponse> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let filters = SearchFilters {
+        types: vec![HIGHLIGHT_TYPE.to_string()],
+        ..Default::default()
+    };
+    let ids: Vec<String> = fulltext_search(
+        &shared_state.search_index,
+        &params.query,
+        20,
+        &shared_state.config.search_exclusions,
+        &filters,
+    )
+    .into_iter()
+    .map(|hit| hit.id)
+    .collect();
+    let results = highlights_by_id(&db, &ids, params.source.as_deref())
+        .expect("Highlights query failed");
 
-    axum::serve(listener, app).await.unwrap();
+    Json(HighlightSearchResponse {
+        query: params.query,
+        results,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct RandomNoteRequest {
+    // Comma separated filters, the same as search
+    r#type: Option<String>,
+    tags: Option<String>,
+    collection: Option<String>,
+    // Pick the same note for the rest of the day
+    #[serde(default)]
+    daily: bool,
+}
+
+// A random note to resurface something forgotten
+async fn random_note_handler(
+    State(state): State<SharedState>,
+    Query(params): Query<RandomNoteRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let filters = SearchFilters {
+        types: comma_separated(&params.r#type),
+        tags: comma_separated(&params.tags),
+        collections: comma_separated(&params.collection),
+    };
+    let seed = params
+        .daily
+        .then(|| Local::now().date_naive().num_days_from_ce() as u64);
+    let note = random_note(&db, &shared_state.config.search_exclusions, &filters, seed)
+        .expect("Random note query failed");
+
+    match note {
+        Some(note) => Json(note).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "error": "No matching notes",
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NoteDiffRequest {
+    // `disk` (the default) or a git revision e.g. `HEAD~1`
+    against: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NoteDiff {
+    id: String,
+    against: String,
+    // True if the indexed body is different
+    stale: bool,
+    diff: String,
+}
+
+// Unified diff between the indexed body of a note or task and the
+// note on disk or at a git revision
+async fn note_diff(
+    State(state): State<SharedState>,
+    // This is the org-id of the note or task
+    Path(id): Path<String>,
+    Query(params): Query<NoteDiffRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let keywords = &shared_state.config.index_config.keywords;
+
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let indexed = db
+        .query_row(
+            "SELECT file_name, body, encrypted FROM note_meta WHERE id = ?1 AND type IN ('note', 'task')",
+            [&id],
+            |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?, r.get::<_, bool>(2)?)),
+        )
+        .optional()
+        .expect("Note diff query failed");
+    let Some((file_name, body, encrypted)) = indexed else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "error": "Note not found",
+            })),
+        )
+            .into_response();
+    };
+    // The body of encrypted notes is never stored so there is
+    // nothing to compare to
+    if encrypted {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "Encrypted notes can't be diffed",
+            })),
+        )
+            .into_response();
+    }
+
+    let against = DiffAgainst::parse(params.against.as_deref());
+    let path = std::path::Path::new(&file_name);
+    // Markdown notes are compared as the org-mode they're indexed as
+    let content = match &against {
+        DiffAgainst::Disk => read_note(path, None).ok(),
+        DiffAgainst::Revision(revision) => {
+            file_at_revision(&GitAuth::from_env(), path, revision).map(|c| {
+                if is_markdown(path) {
+                    markdown_to_org(&c, path)
+                } else {
+                    c
+                }
+            })
+        }
+    };
+    // A note that was removed diffs as if it's empty
+    let current = content
+        .and_then(|c| indexed_body(&c, keywords, &id))
+        .unwrap_or_default();
+    let diff = unified_diff(&body, &current, against.name());
+
+    Json(NoteDiff {
+        id,
+        against: against.name().to_string(),
+        stale: !diff.is_empty(),
+        diff,
+    })
+    .into_response()
+}
+
+// Lint every note in the notes directory and collections
+fn lint_notes(config: &AppConfig) -> Vec<NoteLint> {
+    let index_config = &config.index_config;
+    let sources: Vec<OrgDirectory> = std::iter::once(config.notes_path.as_str())
+        .chain(index_config.collections.iter().map(|c| c.path.as_str()))
+        .map(|path| {
+            OrgDirectory::new(path, index_config.age_identity_path.clone())
+                .with_walk(index_config.walk.clone())
+        })
+        .collect();
+    let sources: Vec<&dyn SourceProvider> = sources.iter().map(|s| s as &dyn SourceProvider).collect();
+    lint_sources(&sources, &index_config.keywords)
+}
+
+// Problems with every note e.g. missing IDs and notes that share an ID
+async fn notes_lint(State(state): State<SharedState>) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let notes = lint_notes(&shared_state.config);
+    Json(json!({ "notes": notes })).into_response()
+}
+
+// Problems with the note. Other notes are checked too so notes that
+// share its ID are found.
+async fn note_lint(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let file_name = {
+        let db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        db.query_row(
+            "SELECT file_name FROM note_meta WHERE id = ?1 AND type = 'note'",
+            [&id],
+            |r| r.get::<_, String>(0),
+        )
+        .optional()
+        .expect("Note lint query failed")
+    };
+    let Some(file_name) = file_name else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "error": "Note not found",
+            })),
+        )
+            .into_response();
+    };
+
+    let lint = lint_notes(&shared_state.config)
+        .into_iter()
+        .find(|lint| lint.file_name == file_name)
+        .unwrap_or(NoteLint {
+            file_name,
+            id: Some(id),
+            issues: vec![],
+        });
+    Json(lint).into_response()
+}
+
+#[derive(Serialize)]
+struct NoteStats {
+    id: String,
+    word_count: Option<i64>,
+    heading_count: Option<i64>,
+    reading_time_minutes: Option<i64>,
+    modified_at: Option<String>,
+}
+
+// Size and last modified time of a note by ID without the body
+async fn note_stats(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let stats = db
+        .query_row(
+            "SELECT word_count, heading_count, modified_at FROM note_meta WHERE id = ?1",
+            [&id],
+            |r| {
+                let word_count: Option<i64> = r.get(0)?;
+                Ok(NoteStats {
+                    id: id.clone(),
+                    word_count,
+                    heading_count: r.get(1)?,
+                    reading_time_minutes: word_count.map(reading_time_minutes),
+                    modified_at: r.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .expect("Note stats query failed");
+
+    match stats {
+        Some(stats) => Json(stats).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "success": false,
+                "error": "Note not found",
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplainRequest {
+    query: String,
+}
+
+// Show how a search query is parsed and run
+async fn explain_search(
+    State(state): State<SharedState>,
+    Query(params): Query<ExplainRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let config = &shared_state.config;
+    match explain_query(
+        &shared_state.search_index,
+        &params.query,
+        &config.search_exclusions,
+    ) {
+        Ok(explanation) => Json(json!(explanation)).into_response(),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SemanticSearchRequest {
+    // Any text e.g. a paragraph that's being written
+    text: String,
+    limit: Option<usize>,
+    // Number of nearest neighbors to consider
+    similarity_k: Option<usize>,
+    // Maximum similarity distance for a note to be included
+    similarity_threshold: Option<f32>,
+    // Comma separated filters
+    r#type: Option<String>,
+    tags: Option<String>,
+    collection: Option<String>,
+}
+
+// Notes most similar to the text, nearest first
+async fn semantic_search(
+    State(state): State<SharedState>,
+    Json(payload): Json<SemanticSearchRequest>,
+) -> Response {
+    // Generating embeddings is blocking so run it on a separate
+    // thread to avoid stalling the server
+    let text = payload.text.clone();
+    let embedding = tokio::task::spawn_blocking(move || {
+        embeddings_model().map(|model| model.embed(vec![text], None))
+    })
+    .await
+    .expect("Embedding task failed");
+    let query_vector = match embedding {
+        Some(Ok(mut vectors)) => vectors.remove(0),
+        None => return embeddings_unavailable(),
+        Some(Err(err)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let filters = SearchFilters {
+        types: comma_separated(&payload.r#type),
+        tags: comma_separated(&payload.tags),
+        collections: comma_separated(&payload.collection),
+    };
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = similar_notes(
+        &shared_state.search_index,
+        &db,
+        &query_vector,
+        payload.limit.unwrap_or(20),
+        payload.similarity_k,
+        payload.similarity_threshold,
+        &shared_state.config.search_exclusions,
+        &filters,
+    )
+    .expect("Similarity search failed");
+    Json(json!({ "results": results })).into_response()
+}
+
+// Maximum number of texts to embed in one request
+const MAX_EMBEDDING_TEXTS: usize = 256;
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsRequest {
+    texts: Vec<String>,
+}
+
+// Embeddings for the texts using the same model as the index so they
+// can be compared to the embeddings of notes
+async fn embeddings(Json(payload): Json<EmbeddingsRequest>) -> Response {
+    if payload.texts.is_empty() || payload.texts.len() > MAX_EMBEDDING_TEXTS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": format!("Expected 1 to {} texts", MAX_EMBEDDING_TEXTS),
+            })),
+        )
+            .into_response();
+    }
+
+    // Generating embeddings is blocking so run it on a separate
+    // thread to avoid stalling the server
+    let texts = payload.texts;
+    let embedding = tokio::task::spawn_blocking(move || {
+        embeddings_model().map(|model| model.embed(texts, None))
+    })
+    .await
+    .expect("Embedding task failed");
+    match embedding {
+        None => embeddings_unavailable(),
+        Some(Ok(vectors)) => Json(json!({
+            "model": format!("{:?}", EMBEDDING_MODEL),
+            "dimensions": EMBEDDING_DIMENSIONS,
+            "embeddings": vectors,
+        }))
+        .into_response(),
+        Some(Err(err)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// Response for requests that need embeddings when the model isn't
+// available e.g. it couldn't be downloaded
+fn embeddings_unavailable() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({
+            "success": false,
+            "similarity_available": false,
+            "error": "Embeddings model is unavailable",
+        })),
+    )
+        .into_response()
+}
+
+// Whether the server is ready to handle requests along with each
+// check. The embeddings model is optional since full text search
+// works without it.
+async fn readyz(State(state): State<SharedState>) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let db_ready = db.query_row("SELECT 1", [], |r| r.get::<_, i64>(0)).is_ok();
+    let status = if db_ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "ready": db_ready,
+            "checks": {
+                "db": db_ready,
+                "embeddings_model": embeddings_available(),
+            },
+        })),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct CalendarRequest {
+    // Optional full text query to filter which tasks are included
+    query: Option<String>,
+    token: Option<String>,
+}
+
+// iCalendar feed of task scheduled and deadline dates that can be
+// subscribed to from a calendar app
+async fn tasks_calendar(
+    State(state): State<SharedState>,
+    Query(params): Query<CalendarRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let config = &shared_state.config;
+
+    // Calendar apps can't set headers so the token is passed in the
+    // URL. It's compared in constant time so it can't be guessed from
+    // how long the comparison takes.
+    if let Some(ical_token) = &config.ical_token {
+        let token = params.token.as_deref().unwrap_or_default();
+        if !bool::from(ical_token.as_bytes().ct_eq(token.as_bytes())) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let ids = params.query.map(|query| {
+        fulltext_search(
+            &shared_state.search_index,
+            &query,
+            1000,
+            &config.search_exclusions,
+            &SearchFilters::default(),
+        )
+            .into_iter()
+            .map(|hit| hit.id)
+            .collect()
+    });
+
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let tasks = calendar_tasks(&db, ids).expect("Calendar tasks query failed");
+
+    (
+        [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        render_calendar(&tasks),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct TimeReportRequest {
+    // Dates formatted like `2025-01-06`
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    group_by: GroupBy,
+}
+
+// Total time clocked grouped by file, tag, or headline
+async fn time_report_handler(
+    State(state): State<SharedState>,
+    Query(params): Query<TimeReportRequest>,
+) -> Response {
+    let parse_date = |d: &Option<String>| {
+        d.as_deref()
+            .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+            .transpose()
+    };
+    let (from, to) = match (parse_date(&params.from), parse_date(&params.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let report = time_report(&db, from, to, params.group_by).expect("Time report query failed");
+    Json(json!({ "results": report })).into_response()
+}
+
+// Kanban style board of tasks grouped into columns by status
+async fn tasks_board(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let columns = task_board(&db, &shared_state.config.board_columns)
+        .expect("Task board query failed");
+    Json(json!({ "columns": columns }))
+}
+
+#[derive(Debug, Deserialize)]
+struct TaskStatusRequest {
+    status: String,
+}
+
+// Notes that were changed but couldn't be pushed to origin are a
+// server error rather than a problem with the request
+fn error_status(err: &anyhow::Error, status: StatusCode) -> StatusCode {
+    if err.is::<PushError>() {
+        StatusCode::BAD_GATEWAY
+    } else {
+        status
+    }
+}
+
+// Move a task to a different status by editing the note file and
+// then re-indexing it
+async fn update_task_status(
+    State(state): State<SharedState>,
+    // This is the org-id of the task
+    Path(id): Path<String>,
+    Json(payload): Json<TaskStatusRequest>,
+) -> Response {
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    match apply_task_status(
+        &mut db,
+        &mut index_writer,
+        &shared_state.config,
+        &id,
+        &payload.status,
+    ) {
+        Ok(_) => {
+            shared_state.search_index.reload();
+            Json(json!({ "success": true })).into_response()
+        }
+        Err(err) => (
+            error_status(&err, StatusCode::BAD_REQUEST),
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// Set the task status in the note file, push it, and re-index it
+fn apply_task_status(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    config: &AppConfig,
+    id: &str,
+    status: &str,
+) -> anyhow::Result<()> {
+    let index_config = &config.index_config;
+    let path = set_task_status(db, &index_config.keywords, id, status)?;
+    let message = format!("Set task {} to {}", id, status.to_uppercase());
+    record_audit(db, None, "task_status", &message)?;
+    let root = collection_root(config, &path.to_string_lossy());
+
+    // Only the full text and meta need updating since the note's
+    // content is otherwise unchanged
+    let source = OrgDirectory::new(root, index_config.age_identity_path.clone())
+        .with_walk(index_config.walk.clone())
+        .with_paths(vec![path]);
+    index_all(db, index_writer, &source, true, false, index_config)?;
+
+    // Push the change so it isn't lost the next time notes are
+    // pulled from origin
+    let auth = GitAuth::from_env();
+    if auth.is_configured() {
+        commit_and_push_repo(&auth, root, &message)?;
+    }
+    Ok(())
+}
+
+// Tags used by notes and tasks as a tree of nested tags with the
+// number of each for browsing tags
+async fn tags_tree(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let tags = note_tag_tree(&db).expect("Tag tree query failed");
+    Json(json!({ "tags": tags }))
+}
+
+#[derive(Debug, Deserialize)]
+struct TagRenameRequest {
+    from: String,
+    to: String,
+}
+
+// Rename a tag in every note, merging it with `to` if a note already
+// has it, and then re-index the notes that changed
+async fn rename_tag_handler(
+    State(state): State<SharedState>,
+    Json(payload): Json<TagRenameRequest>,
+) -> Response {
+    let TagRenameRequest { from, to } = payload;
+    if !is_valid_tag(&from) || !is_valid_tag(&to) || from == to {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "Tags must be different and can't be empty or contain spaces or colons",
+            })),
+        )
+            .into_response();
+    }
+
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    match apply_tag_rename(&mut db, &mut index_writer, &shared_state.config, &from, &to) {
+        Ok(files) => {
+            shared_state.search_index.reload();
+            Json(json!({ "success": true, "files": files })).into_response()
+        }
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// Rename the tag in the notes directory and each collection, push the
+// changes, and re-index the notes that changed. Returns the file names
+// of the notes that changed.
+fn apply_tag_rename(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    config: &AppConfig,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<Vec<String>> {
+    let AppConfig {
+        notes_path,
+        index_config,
+        ..
+    } = config;
+    let dirs = std::iter::once(notes_path.as_str())
+        .chain(index_config.collections.iter().map(|c| c.path.as_str()));
+    let auth = GitAuth::from_env();
+    let mut files = Vec::new();
+    for dir in dirs {
+        let paths = rename_tag(dir, &index_config.walk, from, to)?;
+        if paths.is_empty() {
+            continue;
+        }
+        let message = format!("Rename tag {} to {} in {} notes", from, to, paths.len());
+        record_audit(db, None, "tag_rename", &message)?;
+
+        files.extend(paths.iter().map(|p| p.display().to_string()));
+        let source = OrgDirectory::new(dir, index_config.age_identity_path.clone())
+            .with_walk(index_config.walk.clone())
+            .with_paths(paths);
+        index_all(db, index_writer, &source, true, false, index_config)?;
+
+        if auth.is_configured() {
+            commit_and_push_repo(&auth, dir, &message)?;
+        }
+    }
+    Ok(files)
+}
+
+#[derive(Debug, Deserialize)]
+struct RefileRequest {
+    // File name of the note to move the headline to
+    file_name: String,
+    // ID or title of the headline to put it under
+    parent: Option<String>,
+}
+
+// Move a headline to another note
+async fn refile_note(
+    State(state): State<SharedState>,
+    // This is the org-id of the headline
+    Path(id): Path<String>,
+    Json(payload): Json<RefileRequest>,
+) -> Response {
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let target = RefileTarget {
+        file_name: payload.file_name,
+        parent: payload.parent,
+    };
+
+    match apply_refile(&mut db, &mut index_writer, &shared_state.config, &id, &target) {
+        Ok(new_id) => {
+            shared_state.search_index.reload();
+            Json(json!({ "success": true, "id": new_id })).into_response()
+        }
+        Err(err) => (
+            error_status(&err, StatusCode::BAD_REQUEST),
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// Refile the headline, push it, and re-index the changed notes.
+// Returns the ID of the headline which changes if it didn't have an
+// org-id before.
+fn apply_refile(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    config: &AppConfig,
+    id: &str,
+    target: &RefileTarget,
+) -> anyhow::Result<String> {
+    let index_config = &config.index_config;
+    // Headlines are refiled within the collection they're in and
+    // anything else comes from the capture file in the main notes
+    // directory
+    let root = db
+        .query_row(
+            "SELECT file_name FROM note_meta WHERE id = ?1 AND type = 'task'",
+            [id],
+            |r| r.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|file_name| collection_root(config, &file_name))
+        .unwrap_or(&config.notes_path);
+    let refiled = refile(
+        db,
+        &index_config.keywords,
+        root,
+        index_writer,
+        id,
+        target,
+    )?;
+    let message = format!("Refile {} to {}", refiled.title, target.file_name);
+    record_audit(db, None, "refile", &message)?;
+
+    let source = OrgDirectory::new(root, index_config.age_identity_path.clone())
+        .with_walk(index_config.walk.clone())
+        .with_paths(refiled.paths);
+    index_all(db, index_writer, &source, true, false, index_config)?;
+
+    let auth = GitAuth::from_env();
+    if auth.is_configured() {
+        commit_and_push_repo(&auth, root, &message)?;
+    }
+    Ok(refiled.id)
+}
+
+// Document types, task keywords, and note types so clients can
+// render filters that match how notes are indexed
+async fn config_schema(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let IndexConfig {
+        keywords,
+        note_types,
+        indexed_properties,
+        collections,
+        ..
+    } = &shared_state.config.index_config;
+    let collections: Vec<&str> = std::iter::once(DEFAULT_COLLECTION)
+        .chain(collections.iter().map(|c| c.name.as_str()))
+        .collect();
+    Json(json!({
+        "types": ["note", "task", "feed_item"],
+        "collections": collections,
+        "todo_keywords": keywords.todo,
+        "done_keywords": keywords.done,
+        "note_types": note_types,
+        "indexed_properties": indexed_properties,
+    }))
+}
+
+// Notes that link to the note, explicitly or by mentioning its title
+async fn notes_backlinks(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let links = backlinks(&db, &id).expect("Backlinks query failed");
+    Json(json!({ "id": id, "links": links }))
+}
+
+// Links to notes that don't exist
+async fn notes_broken_links(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let links = broken_links(&db).expect("Broken links query failed");
+    Json(json!({ "links": links }))
+}
+
+// Report on the health of the links between notes
+async fn notes_report(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let broken = broken_links(&db).expect("Broken links query failed");
+    let orphans = orphan_notes(&db).expect("Orphan notes query failed");
+    Json(json!({
+        "broken_links": broken,
+        "orphans": orphans,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct FavoriteRequest {
+    favorite: bool,
+}
+
+// Add or remove a note from favorites
+async fn update_favorite(
+    State(state): State<SharedState>,
+    // This is the org-id of the note
+    Path(id): Path<String>,
+    Json(params): Json<FavoriteRequest>,
+) -> Response {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let found = set_favorite(&db, &id, params.favorite).expect("Updating favorite failed");
+    if !found {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Note not found" })),
+        )
+            .into_response();
+    }
+    Json(json!({ "success": true, "favorite": params.favorite })).into_response()
+}
+
+// Favorite notes, most recently added first
+async fn notes_favorites(State(state): State<SharedState>) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = favorite_notes(&db).expect("Favorites query failed");
+    Json(json!({ "results": results }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadingRequest {
+    url: Option<String>,
+    // ID of a note to read
+    note_id: Option<String>,
+}
+
+// Add a web page or note to the read-later queue
+async fn enqueue_reading_handler(
+    State(state): State<SharedState>,
+    Json(payload): Json<ReadingRequest>,
+) -> Response {
+    if payload.url.is_none() && payload.note_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Missing url or note_id" })),
+        )
+            .into_response();
+    }
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(note_id) = &payload.note_id {
+        let exists: bool = db
+            .query_row("SELECT COUNT(*) > 0 FROM note_meta WHERE id = ?1", [note_id], |r| r.get(0))
+            .expect("Note query failed");
+        if !exists {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "success": false, "error": "Note not found" })),
+            )
+                .into_response();
+        }
+    }
+    let item = enqueue_reading(&db, payload.url.as_deref(), payload.note_id.as_deref())
+        .expect("Adding to reading queue failed");
+    Json(item).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadingListRequest {
+    status: Option<ReadingStatus>,
+}
+
+// The read-later queue, oldest first
+async fn reading_list_handler(
+    State(state): State<SharedState>,
+    Query(params): Query<ReadingListRequest>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = reading_list(&db, params.status).expect("Reading list query failed");
+    Json(json!({ "results": results }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadingProgressRequest {
+    // Percent read from 0 to 100
+    progress: u8,
+    // Defaults to the status that goes with the progress
+    status: Option<ReadingStatus>,
+}
+
+// Update how far along an item in the read-later queue has been read
+async fn update_reading_progress_handler(
+    State(state): State<SharedState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ReadingProgressRequest>,
+) -> Response {
+    if payload.progress > 100 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Progress must be from 0 to 100" })),
+        )
+            .into_response();
+    }
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    match update_reading_progress(&db, id, payload.progress, payload.status)
+        .expect("Updating reading progress failed")
+    {
+        Some(item) => Json(item).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Reading item not found" })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NoteChangesRequest {
+    // Only changes on or after the date e.g. 2025-01-06
+    since: Option<String>,
+}
+
+// How notes changed each time they were indexed, most recent first
+async fn notes_changes(
+    State(state): State<SharedState>,
+    Query(params): Query<NoteChangesRequest>,
+) -> Response {
+    let since = match params
+        .since
+        .as_deref()
+        .map(|d| NaiveDate::parse_from_str(d, DATE_FORMAT))
+        .transpose()
+    {
+        Ok(since) => since,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = note_changes(&db, since).expect("Note changes query failed");
+    Json(json!({ "results": results })).into_response()
+}
+
+// Notes that mention the date e.g. what's happening on 2025-06-12
+async fn notes_on_date(
+    State(state): State<SharedState>,
+    Path(date): Path<String>,
+) -> Response {
+    let date = match NaiveDate::parse_from_str(&date, DATE_FORMAT) {
+        Ok(date) => date,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = date_mentions(&db, date).expect("Date mentions query failed");
+    Json(json!({ "date": date, "results": results })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditRequest {
+    limit: Option<usize>,
+}
+
+// Changes made to notes through the API, newest first
+async fn audit(
+    State(state): State<SharedState>,
+    Query(params): Query<AuditRequest>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let entries = audit_log(&db, params.limit.unwrap_or(100)).expect("Audit log query failed");
+    Json(json!({ "entries": entries }))
+}
+
+// Mark a feed item as read so it's no longer in the reading queue
+async fn feed_item_read(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Response {
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    match mark_feed_item_read(&db, &mut index_writer, &id) {
+        Ok(_) => {
+            shared_state.search_index.reload();
+            Json(json!({ "success": true })).into_response()
+        }
+        Err(err) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// Periodically fetch the configured feeds and index new items
+async fn poll_feeds(state: SharedState, feed_config: FeedConfig) {
+    let mut interval = tokio::time::interval(feed_config.poll_interval);
+    loop {
+        interval.tick().await;
+
+        let mut items = Vec::new();
+        for url in feed_config.urls.iter() {
+            match fetch_feed(url).await {
+                Ok(feed_items) => items.extend(feed_items),
+                Err(err) => tracing::warn!("Fetching feed {} failed: {}", url, err),
+            }
+        }
+
+        let index_service = state.read().expect("Unable to read share state").index_service.clone();
+        let mut index_writer = index_service.writer().await;
+
+        // Generating embeddings is blocking so run it on a separate
+        // thread to avoid stalling the server
+        let task_state = Arc::clone(&state);
+        let result = tokio::task::spawn_blocking(move || {
+            let shared_state = task_state.read().expect("Unable to read share state");
+            let mut db = shared_state
+                .db
+                .lock()
+                // Ignoring any previous panics since we are trying to get the
+                // db connection and it's probably fine
+                .unwrap_or_else(|e| e.into_inner());
+            let model = embeddings_model();
+            let count = index_feed_items(&mut db, &mut index_writer, &items, model.as_ref());
+            shared_state.search_index.reload();
+            count
+        })
+        .await;
+        match result {
+            Ok(Ok(count)) => {
+                tracing::debug!("Indexed {} new feed items", count);
+                if count > 0 {
+                    rebuild_ann(&state);
+                }
+            }
+            Ok(Err(err)) => tracing::error!("Indexing feed items failed: {}", err),
+            Err(err) => tracing::error!("Feed indexing task failed: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BookmarkRequest {
+    url: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Add the bookmark to the read-later queue
+    #[serde(default)]
+    read_later: bool,
+}
+
+// Archive a web page as a note and index it
+async fn create_bookmark(
+    State(state): State<SharedState>,
+    Json(payload): Json<BookmarkRequest>,
+) -> Response {
+    save_bookmark(state, payload, None).await
+}
+
+// Save the bookmark on behalf of the named token, if any
+async fn save_bookmark(state: SharedState, payload: BookmarkRequest, actor: Option<String>) -> Response {
+    // Tags are written to `#+FILETAGS:` where spaces and newlines
+    // would break the note
+    if !payload.tags.iter().all(|t| is_valid_tag(t)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "Tags can't be empty or contain spaces or colons",
+            })),
+        )
+            .into_response();
+    }
+
+    // Fetch the page before taking any locks since it can be slow
+    let bookmark = match fetch_bookmark(&payload.url, payload.tags).await {
+        Ok(bookmark) => bookmark,
+        Err(err) => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let AppConfig {
+        notes_path,
+        index_config,
+        ..
+    } = &shared_state.config;
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let path = std::path::Path::new(notes_path).join(bookmark.file_name());
+    fs::write(&path, bookmark.to_org()).expect("Failed to write bookmark note");
+    let message = format!("Bookmark {}", bookmark.url);
+    record_audit(&db, actor.as_deref(), "bookmark", &message).expect("Recording audit log failed");
+
+    // Push the change so it isn't lost the next time notes are
+    // pulled from origin
+    let auth = GitAuth::from_env();
+    let pushed = if auth.is_configured() {
+        commit_and_push_repo(&auth, notes_path, &message)
+    } else {
+        Ok(())
+    };
+
+    let source = OrgDirectory::new(notes_path, index_config.age_identity_path.clone())
+        .with_walk(index_config.walk.clone())
+        .with_paths(vec![path]);
+    index_all(&mut db, &mut index_writer, &source, true, true, index_config)
+        .expect("Indexing failed");
+    shared_state.search_index.reload();
+    rebuild_ann(&state);
+
+    if payload.read_later {
+        enqueue_reading(&db, Some(&bookmark.url), Some(&bookmark.id))
+            .expect("Adding to reading queue failed");
+    }
+
+    if let Err(err) = pushed {
+        return (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response();
+    }
+
+    Json(json!({
+        "success": true,
+        "id": bookmark.id,
+        "title": bookmark.title,
+    }))
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct MeetingRequest {
+    title: String,
+    // Contents of a WebVTT or JSON transcript file
+    transcript: String,
+    // Detected from the transcript if not set
+    format: Option<TranscriptFormat>,
+    // Defaults to today e.g. 2025-01-06
+    date: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+// Save a meeting transcript as a note and index it
+async fn create_meeting(
+    State(state): State<SharedState>,
+    Json(payload): Json<MeetingRequest>,
+) -> Response {
+    let date = match &payload.date {
+        Some(date) => match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+            Ok(date) => date,
+            Err(_) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "success": false, "error": "Invalid date, expected YYYY-MM-DD" })),
+                )
+                    .into_response()
+            }
+        },
+        None => Local::now().date_naive(),
+    };
+    let format = payload
+        .format
+        .unwrap_or_else(|| TranscriptFormat::detect(&payload.transcript));
+    let utterances = match parse_transcript(&payload.transcript, format) {
+        Ok(utterances) => utterances,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": err.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let meeting = Meeting::new(&payload.title, date, payload.tags, utterances);
+
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let AppConfig {
+        notes_path,
+        index_config,
+        ..
+    } = &shared_state.config;
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let path = std::path::Path::new(notes_path).join(meeting.file_name());
+    fs::write(&path, meeting.to_org()).expect("Failed to write meeting note");
+    let message = format!("Meeting {}", meeting.title);
+    record_audit(&db, None, "meeting", &message).expect("Recording audit log failed");
+
+    // Push the change so it isn't lost the next time notes are
+    // pulled from origin
+    let auth = GitAuth::from_env();
+    let pushed = if auth.is_configured() {
+        commit_and_push_repo(&auth, notes_path, &message)
+    } else {
+        Ok(())
+    };
+
+    let source = OrgDirectory::new(notes_path, index_config.age_identity_path.clone())
+        .with_walk(index_config.walk.clone())
+        .with_paths(vec![path]);
+    index_all(&mut db, &mut index_writer, &source, true, true, index_config)
+        .expect("Indexing failed");
+    shared_state.search_index.reload();
+    rebuild_ann(&state);
+
+    if let Err(err) = pushed {
+        return (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({ "success": false, "error": err.to_string() })),
+        )
+            .into_response();
+    }
+
+    Json(json!({
+        "success": true,
+        "id": meeting.id,
+        "title": meeting.title,
+        "attendees": meeting.attendees(),
+    }))
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct QuickAddRequest {
+    url: Option<String>,
+    title: Option<String>,
+    selection: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Add a saved page to the read-later queue
+    #[serde(default)]
+    read_later: bool,
+}
+
+// Name of the device a quick add came from
+#[derive(Clone)]
+struct QuickAddDevice(String);
+
+// Check the quick add token before the request is handled or
+// replayed by its idempotency key
+async fn quick_add_auth(
+    State(state): State<SharedState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let device = {
+        let shared_state = state.read().expect("Unable to read share state");
+        // Compare in constant time so the token can't be guessed from
+        // how long the comparison takes
+        shared_state
+            .config
+            .quick_add_tokens
+            .iter()
+            .find(|(_, t)| token.is_some_and(|token| bool::from(t.as_bytes().ct_eq(token.as_bytes()))))
+            .map(|(device, _)| device.clone())
+    };
+    let Some(device) = device else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    request.extensions_mut().insert(QuickAddDevice(device));
+    next.run(request).await
+}
+
+// Minimal endpoint for saving from a browser extension. A selection
+// is appended to the capture file, otherwise the page is saved as a
+// bookmark note.
+async fn quick_add(
+    State(state): State<SharedState>,
+    Extension(QuickAddDevice(device)): Extension<QuickAddDevice>,
+    Json(payload): Json<QuickAddRequest>,
+) -> Response {
+    tracing::debug!("Quick add from {}", device);
+
+    if let Some(selection) = payload.selection {
+        let entry = CaptureEntry {
+            title: payload
+                .title
+                .or(payload.url.clone())
+                .unwrap_or_else(|| "Quick add".to_string()),
+            url: payload.url,
+            body: Some(selection),
+            tags: payload.tags,
+        };
+        let shared_state = state.read().expect("Unable to read share state");
+        append_capture(&shared_state.config.notes_path, &entry)
+            .expect("Failed to append to capture file");
+        let db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        record_audit(&db, Some(&device), "capture", &format!("Capture {}", entry.title))
+            .expect("Recording audit log failed");
+        return Json(json!({ "success": true })).into_response();
+    }
+
+    let Some(url) = payload.url else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Missing url or selection" })),
+        )
+            .into_response();
+    };
+    let bookmark = BookmarkRequest {
+        url,
+        tags: payload.tags,
+        read_later: payload.read_later,
+    };
+    save_bookmark(state, bookmark, Some(device)).await
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptureRequest {
+    title: String,
+    body: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+// Append an entry to the capture file
+async fn capture(
+    State(state): State<SharedState>,
+    Json(payload): Json<CaptureRequest>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let entry = CaptureEntry {
+        title: payload.title,
+        url: payload.url,
+        body: payload.body,
+        tags: payload.tags,
+    };
+    append_capture(&shared_state.config.notes_path, &entry)
+        .expect("Failed to append to capture file");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    record_audit(&db, None, "capture", &format!("Capture {}", entry.title))
+        .expect("Recording audit log failed");
+    Json(json!({ "success": true }))
+}
+
+// Apply a single queued operation from an offline client
+fn apply_sync_operation(
+    db: &mut Connection,
+    index_writer: &mut IndexWriter,
+    config: &AppConfig,
+    request: &SyncRequest,
+) -> anyhow::Result<()> {
+    match &request.operation {
+        SyncOperation::Capture {
+            title,
+            body,
+            url,
+            tags,
+        } => {
+            let entry = CaptureEntry {
+                title: title.clone(),
+                url: url.clone(),
+                body: body.clone(),
+                tags: tags.clone(),
+            };
+            let created = request
+                .created_at()
+                .unwrap_or_else(|| chrono::Local::now().naive_local());
+            append_capture_at(&config.notes_path, &entry, created)?;
+            record_audit(db, None, "capture", &format!("Capture {}", entry.title))?;
+        }
+        SyncOperation::TaskStatus { id, status } => {
+            apply_task_status(db, index_writer, config, id, status)?;
+        }
+        SyncOperation::FeedItemRead { id } => {
+            mark_feed_item_read(db, index_writer, id)?;
+        }
+    }
+    Ok(())
+}
+
+// Replay operations queued by an offline client in the order they
+// were queued. Each operation has its own idempotency key so the
+// batch can be safely retried if the connection drops part way.
+async fn sync_batch(
+    State(state): State<SharedState>,
+    Json(payload): Json<SyncBatchRequest>,
+) -> Json<Value> {
+    let index_service = state.read().expect("Unable to read share state").index_service.clone();
+    let mut index_writer = index_service.writer().await;
+    let shared_state = state.read().expect("Unable to read share state");
+    let mut db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+
+    let mut results = Vec::new();
+    for request in payload.operations.iter() {
+        let key = format!("sync:{}", request.idempotency_key);
+        if let Some(saved) = saved_response(&db, &key).expect("Idempotency key lookup failed") {
+            let mut result: SyncResult =
+                serde_json::from_str(&saved.body).expect("Invalid saved sync result");
+            result.replayed = true;
+            results.push(result);
+            continue;
+        }
+
+        let result = match apply_sync_operation(&mut db, &mut index_writer, &shared_state.config, request) {
+            Ok(_) => SyncResult {
+                idempotency_key: request.idempotency_key.clone(),
+                success: true,
+                replayed: false,
+                error: None,
+            },
+            Err(err) => SyncResult {
+                idempotency_key: request.idempotency_key.clone(),
+                success: false,
+                replayed: false,
+                error: Some(err.to_string()),
+            },
+        };
+        // Only successful operations are saved so failures can be
+        // retried
+        if result.success {
+            let saved = SavedResponse {
+                status: StatusCode::OK.as_u16(),
+                content_type: None,
+                body: json!(result).to_string(),
+            };
+            save_response(&db, &key, &saved).expect("Saving idempotency key failed");
+        }
+        results.push(result);
+    }
+    shared_state.search_index.reload();
+
+    Json(json!({ "results": results }))
+}
+
+// Largest request or response body that is saved for an idempotency
+// key, the same as the default limit for JSON requests
+const MAX_IDEMPOTENT_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// Return the saved response for POST requests with an idempotency
+// key that were already handled successfully, otherwise handle the
+// request and save the response. Keys are scoped to the route and
+// the body so a key can't replay the response of a different request.
+async fn idempotency(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| format!("{} {}:{}", request.method(), request.uri().path(), v));
+    let Some(key) = key.filter(|_| request.method() == Method::POST) else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_IDEMPOTENT_BODY_BYTES).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+    let reservation = {
+        let shared_state = state.read().expect("Unable to read share state");
+        let db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        reserve_key(&db, &key, &sha256_hex(&bytes)).expect("Idempotency key lookup failed")
+    };
+    match reservation {
+        KeyReservation::Reserved => {}
+        KeyReservation::Saved(saved) => {
+            let status = StatusCode::from_u16(saved.status).unwrap_or(StatusCode::OK);
+            let mut response = (status, saved.body).into_response();
+            if let Some(content_type) = saved.content_type.and_then(|c| c.parse().ok()) {
+                response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+            }
+            return response;
+        }
+        KeyReservation::InFlight => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({ "success": false, "error": "A request with this idempotency key is in progress" })),
+            )
+                .into_response()
+        }
+        KeyReservation::Mismatch => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "success": false, "error": "Idempotency key was used for a different request" })),
+            )
+                .into_response()
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    let release = || {
+        let shared_state = state.read().expect("Unable to read share state");
+        let db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        release_key(&db, &key).expect("Releasing idempotency key failed");
+    };
+    // Failed requests can be retried with the same key
+    if !response.status().is_success() {
+        release();
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_IDEMPOTENT_BODY_BYTES).await else {
+        release();
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let saved = SavedResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string()),
+        body: String::from_utf8_lossy(&bytes).to_string(),
+    };
+    {
+        let shared_state = state.read().expect("Unable to read share state");
+        let db = shared_state
+            .db
+            .lock()
+            // Ignoring any previous panics since we are trying to get the
+            // db connection and it's probably fine
+            .unwrap_or_else(|e| e.into_inner());
+        save_response(&db, &key, &saved).expect("Saving idempotency key failed");
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[derive(Debug, Deserialize)]
+struct SuggestRequest {
+    q: String,
+    limit: Option<usize>,
+}
+
+// Search as you type on note titles and aliases
+async fn notes_suggest(
+    State(state): State<SharedState>,
+    Query(params): Query<SuggestRequest>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let config = &shared_state.config;
+    let results = suggest(
+        &shared_state.search_index,
+        &params.q,
+        params.limit.unwrap_or(10),
+        &config.search_exclusions,
+    );
+    Json(json!({ "results": results }))
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkRequest {
+    ids: Vec<String>,
+}
+
+// Metadata for many notes at once
+async fn notes_bulk(
+    State(state): State<SharedState>,
+    Json(payload): Json<BulkRequest>,
+) -> Json<Value> {
+    let shared_state = state.read().expect("Unable to read share state");
+    let db = shared_state
+        .db
+        .lock()
+        // Ignoring any previous panics since we are trying to get the
+        // db connection and it's probably fine
+        .unwrap_or_else(|e| e.into_inner());
+    let results = note_summaries(&db, &payload.ids).expect("Bulk notes query failed");
+    Json(json!({ "results": results }))
+}
+
+pub fn app(app_state: AppState) -> Router {
+    app_with_state(SharedState::new(RwLock::new(app_state)))
+}
+
+fn app_with_state(shared_state: SharedState) -> Router {
+    // Only the configured origins, like browser extensions, can call
+    // the API from another origin. The web UI is served from the same
+    // origin so it doesn't need to be allowed.
+    let cors_origins: Vec<HeaderValue> = {
+        let shared_state = shared_state.read().expect("Unable to read share state");
+        shared_state
+            .config
+            .cors_origins
+            .iter()
+            .filter_map(|origin| origin.parse().ok())
+            .collect()
+    };
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(cors_origins))
+        .allow_methods(Any)
+        .allow_headers(Any);
+    let serve_dir = ServeDir::new("./web-ui/src");
+    // Requests that create notes can be retried with an idempotency
+    // key without creating them twice
+    let idempotent = || middleware::from_fn_with_state(Arc::clone(&shared_state), idempotency);
+
+    Router::new()
+        // Whether the server is ready and the embeddings model is
+        // available
+        .route("/readyz", get(readyz))
+        // Search API endpoint
+        .route("/notes/search", get(search))
+        // How a search query is parsed and run
+        .route("/notes/search/explain", get(explain_search))
+        // Notes similar to any text
+        .route("/notes/search/semantic", post(semantic_search))
+        // Embeddings for any text using the index's model
+        .route("/embeddings", post(embeddings))
+        // Metadata for many notes at once
+        .route("/notes/bulk", post(notes_bulk))
+        // Search quotes from books and articles
+        .route("/highlights/search", get(search_highlights))
+        // A random note, optionally the same one all day
+        .route("/notes/random", get(random_note_handler))
+        // Search as you type on titles
+        .route("/notes/suggest", get(notes_suggest))
+        // Storage for selected search hits
+        .route("/notes/search/latest", get(kv_get).post(kv_set))
+        // Index content endpoint
+        .route("/notes/index", post(index_notes))
+        // Progress of indexing as server-sent events
+        .route("/notes/index/stream", get(index_progress_stream))
+        // The indexing run in progress or the last one
+        .route("/notes/index/status", get(notes_index_status))
+        // Notes that failed to index
+        .route("/notes/index/errors", get(notes_index_errors))
+        .route("/notes/index/conflicts", get(notes_index_conflicts))
+        .route("/notes/index/errors/:id/retry", post(retry_index_error))
+        // View a specific note
+        .route("/notes/:id/view", get(view_note))
+        // Public links for sharing a note
+        .route("/notes/:id/share", post(share_note).delete(unshare_note))
+        .route("/share/:token", get(view_shared_note))
+        // Links to notes that don't exist
+        .route("/notes/links/broken", get(notes_broken_links))
+        // Broken links and orphan notes
+        .route("/notes/report", get(notes_report))
+        // Favorite notes
+        .route("/notes/favorites", get(notes_favorites))
+        // Read-later queue
+        .route("/reading", get(reading_list_handler).post(enqueue_reading_handler))
+        .route("/reading/:id/progress", post(update_reading_progress_handler))
+        // What changed in notes each time they were indexed
+        .route("/notes/changes", get(notes_changes))
+        // Notes with timestamps on the date
+        .route("/notes/dates/:date", get(notes_on_date))
+        .route("/notes/:id/favorite", post(update_favorite))
+        // Word count, reading time, and last modified time
+        .route("/notes/:id/stats", get(note_stats))
+        // Changes to a note since it was indexed
+        .route("/notes/:id/diff", get(note_diff))
+        // Problems with notes e.g. notes that share an ID
+        .route("/notes/lint", get(notes_lint))
+        .route("/notes/:id/lint", get(note_lint))
+        // Related notes computed at indexing time
+        .route("/notes/:id/related", get(related))
+        // Notes linking to a note including inferred links
+        .route("/notes/:id/backlinks", get(notes_backlinks))
+        // Append to the capture file
+        .route("/notes/capture", post(capture).layer(idempotent()))
+        // Replay operations queued while offline
+        .route("/sync/batch", post(sync_batch))
+        // Save a web page as a note
+        .route("/bookmarks", post(create_bookmark).layer(idempotent()))
+        // Save a meeting transcript as a note
+        .route("/meetings", post(create_meeting).layer(idempotent()))
+        // Save a selection or bookmark from a browser extension
+        .route(
+            "/quick-add",
+            post(quick_add)
+                .layer(idempotent())
+                .layer(middleware::from_fn_with_state(Arc::clone(&shared_state), quick_add_auth)),
+        )
+        // Mark a feed item as read
+        .route("/feeds/items/:id/read", post(feed_item_read))
+        // Calendar feed of tasks
+        .route("/tasks.ics", get(tasks_calendar))
+        // Task board grouped by status
+        .route("/tasks/board", get(tasks_board))
+        .route("/tasks/:id/status", post(update_task_status))
+        // Nested tags with counts for browsing
+        .route("/tags/tree", get(tags_tree))
+        // Rename or merge a tag across every note
+        .route("/tags/rename", post(rename_tag_handler))
+        .route("/config/schema", get(config_schema))
+        // Changes made to notes through the API
+        .route("/audit", get(audit))
+        .route("/time/report", get(time_report_handler))
+        .route("/notes/:id/refile", post(refile_note))
+        // Static server of assets in ./web-ui
+        .nest_service("/", serve_dir.clone())
+        .layer(TraceLayer::new_for_http())
+        .layer(cors)
+        .with_state(Arc::clone(&shared_state))
+}
+
+// Tokens are a comma separated list of `device:token` pairs in
+// `INDEXER_QUICK_ADD_TOKENS` so each device can be revoked separately
+fn quick_add_tokens_from_env() -> BTreeMap<String, String> {
+    env::var("INDEXER_QUICK_ADD_TOKENS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|i| {
+            let (device, token) = i.split_once(':')?;
+            let token = token.trim();
+            if token.is_empty() {
+                return None;
+            }
+            Some((device.trim().to_string(), token.to_string()))
+        })
+        .collect()
+}
+
+// Origins are a comma separated list in `INDEXER_CORS_ORIGINS` e.g.
+// `chrome-extension://abcdef,moz-extension://123456`
+fn cors_origins_from_env() -> Vec<String> {
+    env::var("INDEXER_CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|i| i.trim().to_string())
+        .filter(|i| !i.is_empty())
+        .collect()
+}
+
+// Server-sent event streams send a keep-alive comment when idle so
+// proxies don't close the connection. Heartbeat events are optional
+// and have a sequence number so clients can tell if the connection
+// is stale or missed events.
+#[derive(Clone, Debug)]
+pub struct SseConfig {
+    pub keep_alive: Duration,
+    pub keep_alive_text: String,
+    // Disabled if not set
+    pub heartbeat: Option<Duration>,
+}
+
+impl Default for SseConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: Duration::from_secs(15),
+            keep_alive_text: String::new(),
+            heartbeat: None,
+        }
+    }
+}
+
+impl SseConfig {
+    // Read the config from `INDEXER_SSE_KEEP_ALIVE_SECS`,
+    // `INDEXER_SSE_KEEP_ALIVE_TEXT`, and `INDEXER_SSE_HEARTBEAT_SECS`
+    fn from_env() -> Self {
+        let secs = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+        };
+        let defaults = Self::default();
+        Self {
+            keep_alive: secs("INDEXER_SSE_KEEP_ALIVE_SECS").unwrap_or(defaults.keep_alive),
+            keep_alive_text: env::var("INDEXER_SSE_KEEP_ALIVE_TEXT")
+                .unwrap_or(defaults.keep_alive_text),
+            heartbeat: secs("INDEXER_SSE_HEARTBEAT_SECS"),
+        }
+    }
+
+    // Wrap the stream with heartbeats and keep-alives
+    fn sse<S>(&self, stream: S) -> Sse<impl Stream<Item = Result<Event, Infallible>>>
+    where
+        S: Stream<Item = Result<Event, Infallible>> + Send + 'static,
+    {
+        let heartbeats: Pin<Box<dyn Stream<Item = Result<Event, Infallible>> + Send>> =
+            match self.heartbeat {
+                Some(period) => {
+                    let mut seq: u64 = 0;
+                    let interval = tokio::time::interval_at(Instant::now() + period, period);
+                    Box::pin(IntervalStream::new(interval).map(move |_| {
+                        seq += 1;
+                        Ok(Event::default()
+                            .event("heartbeat")
+                            .json_data(json!({ "event": "heartbeat", "seq": seq }))
+                            .expect("Failed to serialize event"))
+                    }))
+                }
+                None => Box::pin(tokio_stream::pending()),
+            };
+        let keep_alive = KeepAlive::new()
+            .interval(self.keep_alive)
+            .text(self.keep_alive_text.as_str());
+        Sse::new(stream.merge(heartbeats)).keep_alive(keep_alive)
+    }
+}
+
+// HTTPS is enabled when both `INDEXER_TLS_CERT_PATH` and
+// `INDEXER_TLS_KEY_PATH` are set
+fn tls_config_from_env() -> Option<TlsConfig> {
+    Some(TlsConfig {
+        cert_path: env::var("INDEXER_TLS_CERT_PATH").ok()?,
+        key_path: env::var("INDEXER_TLS_KEY_PATH").ok()?,
+    })
+}
+
+// Run the server
+pub async fn serve(
+    host: String,
+    port: String,
+    notes_path: String,
+    index_path: String,
+    vec_db_path: String,
+    // A migration removed the embeddings
+    migrated_embeddings: bool,
+) {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                // axum logs rejections from built-in extractors with the `axum::rejection`
+                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+                format!(
+                    "{}=debug,tower_http=debug,axum::rejection=trace",
+                    env!("CARGO_CRATE_NAME")
+                )
+                    .into()
+            }),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let db = vector_db(&vec_db_path).expect("Failed to connect to db");
+    let reembed = set_vector_format(&db, VectorFormat::from_env())
+        .expect("Failed to set vector format")
+        || migrated_embeddings;
+    let index_config = IndexConfig::from_env();
+    let app_config = AppConfig {
+        notes_path,
+        index_path,
+        search_exclusions: SearchExclusions::from_env(),
+        board_columns: BoardColumn::from_env(&index_config.keywords),
+        index_config,
+        ical_token: env::var("INDEXER_ICAL_TOKEN").ok(),
+        share_secret: env::var("INDEXER_SHARE_SECRET").unwrap_or_else(|_| {
+            tracing::warn!("INDEXER_SHARE_SECRET is not set, share links will stop working when the server restarts");
+            format!("{:032x}", rand::random::<u128>())
+        }),
+        quick_add_tokens: quick_add_tokens_from_env(),
+        cors_origins: cors_origins_from_env(),
+        ann: AnnConfig::from_env(),
+        tls: tls_config_from_env(),
+        sse: SseConfig::from_env(),
+    };
+    let tls = app_config.tls.clone();
+    let shared_state = SharedState::new(RwLock::new(AppState::new(db, app_config)));
+
+    // Embeddings were dropped because the vector format changed or
+    // the embeddings tables were migrated
+    if reembed {
+        tokio::spawn(reembed_notes(Arc::clone(&shared_state)));
+    }
+
+    // Load the model once on start so a missing model is logged and
+    // reported by `/readyz` right away
+    tokio::task::spawn_blocking(embeddings_model);
+
+    // Only poll feeds if there are any configured
+    let feed_config = FeedConfig::from_env();
+    if !feed_config.urls.is_empty() {
+        tokio::spawn(poll_feeds(Arc::clone(&shared_state), feed_config));
+    }
+
+    let app = app_with_state(shared_state);
+
+    let listener = tokio::net::TcpListener::bind(format!("{}:{}", host, port))
+        .await
+        .unwrap();
+
+    tracing::debug!(
+        "Server started. Listening on {}",
+        listener.local_addr().unwrap()
+    );
+
+    if let Some(tls) = tls {
+        // More than one crypto provider is enabled by dependencies so
+        // rustls can't pick one on its own
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        // Clients that support HTTP/2 negotiate it during the TLS
+        // handshake, otherwise HTTP/1.1 is used
+        let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+            .await
+            .expect("Failed to load TLS certificate and key");
+        axum_server::from_tcp_rustls(listener.into_std().unwrap(), rustls_config)
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    } else {
+        axum::serve(listener, app).await.unwrap();
+    }
 }
@@ -0,0 +1,145 @@
+/// Public links for sharing a single note without access to the rest
+/// of the API. Tokens are signed so they can't be forged or changed to
+/// point at another note and they expire. Each link is stored so it
+/// can be revoked before it expires.
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use rusqlite::{Connection, OptionalExtension, Result};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A link to a note that can be viewed by anyone with the token
+#[derive(Debug)]
+pub struct ShareLink {
+    pub note_id: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn mac(secret: &str, share_id: &str, note_id: &str, expires_at: i64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(format!("{}.{}.{}", share_id, note_id, expires_at).as_bytes());
+    mac
+}
+
+/// Returns a token for sharing the note in the form
+/// `<share id>.<expires at>.<signature>`. The expiration is a unix
+/// timestamp.
+///
+/// ```rust
+/// use indexer::share::{is_valid_token, share_token};
+///
+/// let token = share_token("secret", "abc", "note-1", 1735689600);
+/// assert!(token.starts_with("abc.1735689600."));
+/// assert!(is_valid_token("secret", &token, "note-1"));
+/// assert!(!is_valid_token("secret", &token, "note-2"));
+/// assert!(!is_valid_token("other", &token, "note-1"));
+/// assert!(!is_valid_token("secret", &token.replace(".1735689600.", ".1735776000."), "note-1"));
+/// ```
+pub fn share_token(secret: &str, share_id: &str, note_id: &str, expires_at: i64) -> String {
+    let signature: String = mac(secret, share_id, note_id, expires_at)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}.{}.{}", share_id, expires_at, signature)
+}
+
+/// Returns true if the token was signed with the secret for the note.
+/// This doesn't check if the token expired or was revoked.
+pub fn is_valid_token(secret: &str, token: &str, note_id: &str) -> bool {
+    let Some((share_id, expires_at, signature)) = parse_token(token) else {
+        return false;
+    };
+    mac(secret, share_id, note_id, expires_at)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn parse_token(token: &str) -> Option<(&str, i64, Vec<u8>)> {
+    let mut parts = token.split('.');
+    let share_id = parts.next()?;
+    let expires_at = parts.next()?.parse().ok()?;
+    let signature = parts.next()?;
+    if parts.next().is_some() || signature.len() % 2 != 0 {
+        return None;
+    }
+    let signature = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((share_id, expires_at, signature))
+}
+
+/// Create a link for sharing the note that expires after `ttl`.
+/// Returns `None` if the note doesn't exist. Encrypted notes can't be
+/// shared since only their title and tags are stored. Only notes can
+/// be shared since a task or heading would show its whole file.
+pub fn create_share_link(
+    db: &Connection,
+    secret: &str,
+    note_id: &str,
+    ttl: Duration,
+) -> Result<Option<ShareLink>> {
+    let exists: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM note_meta WHERE id = ?1 AND type = 'note' AND encrypted = 0",
+        [note_id],
+        |r| r.get(0),
+    )?;
+    if !exists {
+        return Ok(None);
+    }
+
+    let share_id = format!("{:016x}", rand::random::<u64>());
+    let expires_at = Utc::now() + ttl;
+    db.execute(
+        "INSERT INTO note_share(id, note_meta_id, expires_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![share_id, note_id, expires_at.timestamp()],
+    )?;
+
+    Ok(Some(ShareLink {
+        note_id: note_id.to_string(),
+        token: share_token(secret, &share_id, note_id, expires_at.timestamp()),
+        expires_at,
+    }))
+}
+
+/// Returns the ID and file of the shared note if the token is valid,
+/// hasn't expired, and hasn't been revoked
+pub fn shared_note(db: &Connection, secret: &str, token: &str) -> Result<Option<(String, String)>> {
+    let Some((share_id, expires_at, _)) = parse_token(token) else {
+        return Ok(None);
+    };
+    let shared: Option<(String, String, i64)> = db
+        .query_row(
+            r"
+          SELECT
+            note_share.note_meta_id,
+            note_meta.file_name,
+            note_share.expires_at
+          FROM note_share
+          JOIN note_meta ON note_meta.id = note_share.note_meta_id
+          WHERE note_share.id = ?1
+          AND note_meta.type = 'note'
+        ",
+            [share_id],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()?;
+
+    Ok(shared.and_then(|(note_id, file_name, stored_expires_at)| {
+        let is_valid = stored_expires_at == expires_at
+            && expires_at > Utc::now().timestamp()
+            && is_valid_token(secret, token, &note_id);
+        is_valid.then_some((note_id, file_name))
+    }))
+}
+
+/// Revoke all share links for the note. Returns the number of links
+/// that were revoked.
+pub fn revoke_share_links(db: &Connection, note_id: &str) -> Result<usize> {
+    db.execute("DELETE FROM note_share WHERE note_meta_id = ?1", [note_id])
+}
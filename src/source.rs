@@ -1,39 +1,318 @@
 /// Utilities for getting source documents for indexing
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
-/// Get first level files in the directory, does not follow sub
-/// directories.
-pub fn notes(path: &str) -> Vec<PathBuf> {
-    let Ok(entries) = fs::read_dir(path) else {
-        return vec![];
+use super::capture::CAPTURE_FILE_NAME;
+use super::git::{diff_last_commit_files, GitAuth};
+use super::markdown::{is_markdown, markdown_to_org};
+
+/// A document from a source that can be indexed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    /// Location of the document which is stored as the file name
+    pub path: PathBuf,
+    /// Encrypted documents only have their title and tags stored
+    pub encrypted: bool,
+}
+
+impl Document {
+    fn from_path(path: PathBuf) -> Self {
+        let encrypted = is_encrypted(&path);
+        Self { path, encrypted }
+    }
+}
+
+/// Where documents to index come from. Implement this to index
+/// documents from somewhere other than the notes directory without
+/// changing `index_all`. Documents are read from more than one
+/// thread at a time.
+pub trait SourceProvider: Sync {
+    /// Documents that should be indexed
+    fn documents(&self) -> Vec<Document>;
+
+    /// Read the contents of the document as org-mode
+    fn read(&self, doc: &Document) -> io::Result<String>;
+
+    /// File names from `indexed` that came from this source but no
+    /// longer exist so they can be removed from the index. Nothing is
+    /// removed by default.
+    fn deleted(&self, _indexed: &[String]) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Which notes in sub directories of the notes directory are
+/// included. By default every sub directory is searched for notes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NoteWalk {
+    /// How many directories deep to look for notes. Notes directly in
+    /// the notes directory are at depth 0. Unlimited if not set.
+    pub max_depth: Option<usize>,
+    /// Names of files or directories to skip where `*` matches any
+    /// characters e.g. `archive` or `*.draft.org`
+    pub ignore: Vec<String>,
+    /// Include Markdown notes (`.md`) as well as org notes
+    pub markdown: bool,
+}
+
+impl NoteWalk {
+    /// Read the settings from `INDEXER_NOTES_MAX_DEPTH`,
+    /// `INDEXER_NOTES_IGNORE` which is a comma separated list of
+    /// patterns, and `INDEXER_MARKDOWN_NOTES=true`
+    pub fn from_env() -> Self {
+        Self {
+            max_depth: env::var("INDEXER_NOTES_MAX_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            ignore: env::var("INDEXER_NOTES_IGNORE")
+                .unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            markdown: env::var("INDEXER_MARKDOWN_NOTES").is_ok_and(|v| v == "true" || v == "1"),
+        }
+    }
+
+    /// Returns true if the file or directory name matches one of the
+    /// ignore patterns
+    ///
+    /// ```rust
+    /// use indexer::source::NoteWalk;
+    ///
+    /// let walk = NoteWalk {
+    ///     ignore: vec!["archive".to_string(), "*.draft.org".to_string()],
+    ///     ..Default::default()
+    /// };
+    /// assert!(walk.is_ignored("archive"));
+    /// assert!(walk.is_ignored("plan.draft.org"));
+    /// assert!(!walk.is_ignored("plan.org"));
+    /// assert!(!walk.is_ignored("archived"));
+    /// ```
+    pub fn is_ignored(&self, name: &str) -> bool {
+        self.ignore.iter().any(|p| matches_pattern(p, name))
+    }
+}
+
+// Match the name against a pattern where `*` matches any characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` so the whole name has to match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Org notes in the notes directory
+pub struct OrgDirectory {
+    path: String,
+    age_identity_path: Option<String>,
+    paths: Option<Vec<PathBuf>>,
+    walk: NoteWalk,
+}
+
+impl OrgDirectory {
+    pub fn new(path: &str, age_identity_path: Option<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            age_identity_path,
+            paths: None,
+            walk: NoteWalk::default(),
+        }
+    }
+
+    /// Only include the given files rather than every note
+    pub fn with_paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.paths = Some(paths);
+        self
+    }
+
+    /// Limit which sub directories notes are included from
+    pub fn with_walk(mut self, walk: NoteWalk) -> Self {
+        self.walk = walk;
+        self
+    }
+}
+
+impl SourceProvider for OrgDirectory {
+    fn documents(&self) -> Vec<Document> {
+        let paths = if let Some(paths) = &self.paths {
+            note_filter(&self.path, paths.clone(), &self.walk)
+        } else {
+            notes(&self.path, &self.walk)
+        };
+        paths.into_iter().map(Document::from_path).collect()
+    }
+
+    fn read(&self, doc: &Document) -> io::Result<String> {
+        read_note(&doc.path, self.age_identity_path.as_deref())
+    }
+
+    // Every file is checked, not just the given paths, so notes that
+    // were deleted while the server was down are removed too
+    fn deleted(&self, indexed: &[String]) -> Vec<String> {
+        indexed
+            .iter()
+            .filter(|f| {
+                let path = Path::new(f);
+                path.starts_with(&self.path) && !path.is_file()
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Org notes in the notes directory that changed in the last commit
+/// of the notes repo. Run `maybe_pull_and_reset_repo` before hand to
+/// get the notes that changed on origin.
+pub struct GitChanges {
+    dir: OrgDirectory,
+    auth: GitAuth,
+}
+
+impl GitChanges {
+    pub fn new(dir: OrgDirectory, auth: &GitAuth) -> Self {
+        Self {
+            dir,
+            auth: auth.clone(),
+        }
+    }
+}
+
+impl SourceProvider for GitChanges {
+    fn documents(&self) -> Vec<Document> {
+        // NOTE: This assumes the notes path is the root of the repo
+        // since changed files are relative to it
+        let paths = diff_last_commit_files(&self.auth, &self.dir.path)
+            .iter()
+            .map(|f| Path::new(&self.dir.path).join(f))
+            .collect();
+        note_filter(&self.dir.path, paths, &self.dir.walk)
+            .into_iter()
+            .map(Document::from_path)
+            .collect()
+    }
+
+    fn read(&self, doc: &Document) -> io::Result<String> {
+        self.dir.read(doc)
+    }
+
+    fn deleted(&self, indexed: &[String]) -> Vec<String> {
+        self.dir.deleted(indexed)
+    }
+}
+
+/// Returns true if the note is encrypted with gpg or age e.g.
+/// `secrets.org.gpg`
+pub fn is_encrypted(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.ends_with(".org.gpg") || name.ends_with(".org.age")
+}
+
+/// Returns true if the path is an org file, encrypted or not
+fn is_org_file(path: &Path) -> bool {
+    path.extension().unwrap_or_default() == "org" || is_encrypted(path)
+}
+
+/// Read the contents of the note as org-mode, decrypting it first if
+/// needed. Decryption shells out to `gpg` or `age` so they must be
+/// installed for encrypted notes to be indexed. Markdown notes are
+/// converted to org-mode.
+pub fn read_note(path: &Path, age_identity_path: Option<&str>) -> io::Result<String> {
+    if is_markdown(path) {
+        return fs::read_to_string(path).map(|content| markdown_to_org(&content, path));
+    }
+    if !is_encrypted(path) {
+        return fs::read_to_string(path);
+    }
+
+    let output = if path.extension().unwrap_or_default() == "age" {
+        let Some(identity) = age_identity_path else {
+            return Err(io::Error::other("Missing age identity to decrypt note"));
+        };
+        Command::new("age")
+            .arg("--decrypt")
+            .arg("--identity")
+            .arg(identity)
+            .arg(path)
+            .output()?
+    } else {
+        Command::new("gpg")
+            .arg("--batch")
+            .arg("--quiet")
+            .arg("--decrypt")
+            .arg(path)
+            .output()?
     };
 
-    // TODO: make this recursive if there is more than one directory of notes
-    entries
-        .flatten()
-        .flat_map(|entry| {
-            let Ok(meta) = entry.metadata() else {
-                return vec![];
-            };
-            // Skip directories and non org files
-            let path = entry.path();
-            let ext = path.extension().unwrap_or_default();
-            let name = path.file_name().unwrap_or_default();
-            if meta.is_file() && ext == "org" && name != "config.org" && name != "capture.org" {
-                return vec![entry.path()];
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("Decryption failed: {}", stderr)));
+    }
+
+    String::from_utf8(output.stdout).map_err(io::Error::other)
+}
+
+/// Notes in the directory and its sub directories. Hidden files and
+/// directories e.g. `.git` are skipped. Symlinked directories aren't
+/// followed so there are no cycles.
+pub fn notes(path: &str, walk: &NoteWalk) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    walk_notes(Path::new(path), 0, walk, &mut found);
+    found
+}
+
+fn walk_notes(dir: &Path, depth: usize, walk: &NoteWalk, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || walk.is_ignored(&name) {
+            continue;
+        }
+        if meta.is_dir() {
+            if walk.max_depth.is_none_or(|max| depth < max) {
+                walk_notes(&path, depth + 1, walk, found);
             }
-            vec![]
-        })
-        .collect()
+            continue;
+        }
+        // The config and capture files at the top of the notes
+        // directory aren't notes
+        let is_special = depth == 0 && (name == "config.org" || name == CAPTURE_FILE_NAME);
+        let is_note = is_org_file(&path) || (walk.markdown && is_markdown(&path));
+        if meta.is_file() && is_note && !is_special {
+            found.push(path);
+        }
+    }
 }
 
 /// Return a list of notes filtered by file names
-pub fn note_filter(path: &str, file_paths: Vec<PathBuf>) -> Vec<PathBuf> {
+pub fn note_filter(path: &str, file_paths: Vec<PathBuf>, walk: &NoteWalk) -> Vec<PathBuf> {
     // By using the notes source function we also inherit all the
     // extra filtering and rules for which files are eligible so they
     // don't need to be repeated in multiple places.
-    notes(path)
+    notes(path, walk)
         .into_iter()
         .filter(|p| file_paths.contains(p))
         .collect()
@@ -0,0 +1,176 @@
+/// Support for clients that queue requests while offline and replay
+/// them later. Responses are stored by idempotency key so replaying
+/// a request that already succeeded doesn't apply it twice.
+use chrono::{DateTime, Local, NaiveDateTime};
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+
+/// Header clients set to make a request safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// A response that was saved for an idempotency key
+pub struct SavedResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+/// Returns the saved response for the key if the request was already
+/// handled
+pub fn saved_response(db: &Connection, key: &str) -> Result<Option<SavedResponse>> {
+    expire_keys(db)?;
+    db.query_row(
+        "SELECT status, content_type, body FROM idempotency_key WHERE key = ?1 AND status IS NOT NULL",
+        [key],
+        |r| {
+            Ok(SavedResponse {
+                status: r.get(0)?,
+                content_type: r.get(1)?,
+                body: r.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Save the response for the key so it's returned for any retries
+pub fn save_response(db: &Connection, key: &str, response: &SavedResponse) -> Result<()> {
+    db.execute(
+        r"
+          INSERT INTO idempotency_key(key, status, content_type, body)
+          VALUES (?1, ?2, ?3, ?4)
+          ON CONFLICT(key) DO UPDATE SET
+            status = excluded.status,
+            content_type = excluded.content_type,
+            body = excluded.body
+        ",
+        rusqlite::params![key, response.status, response.content_type, response.body],
+    )?;
+    Ok(())
+}
+
+/// What to do with a request that has an idempotency key
+pub enum KeyReservation {
+    /// The key is new and is held by this request until its response
+    /// is saved or the key is released
+    Reserved,
+    /// Another request with the key is still being handled
+    InFlight,
+    /// The key was already used for a request with a different body
+    Mismatch,
+    /// The request was already handled
+    Saved(SavedResponse),
+}
+
+/// Hold the key for a request with the hash of its body so that
+/// concurrent retries don't both run
+pub fn reserve_key(db: &Connection, key: &str, request_hash: &str) -> Result<KeyReservation> {
+    expire_keys(db)?;
+    let inserted = db.execute(
+        "INSERT OR IGNORE INTO idempotency_key(key, request_hash) VALUES (?1, ?2)",
+        [key, request_hash],
+    )?;
+    if inserted == 1 {
+        return Ok(KeyReservation::Reserved);
+    }
+
+    let (hash, status, content_type, body): (
+        Option<String>,
+        Option<u16>,
+        Option<String>,
+        Option<String>,
+    ) = db.query_row(
+        "SELECT request_hash, status, content_type, body FROM idempotency_key WHERE key = ?1",
+        [key],
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+    )?;
+    if hash.as_deref() != Some(request_hash) {
+        return Ok(KeyReservation::Mismatch);
+    }
+    Ok(match status {
+        Some(status) => KeyReservation::Saved(SavedResponse {
+            status,
+            content_type,
+            body: body.unwrap_or_default(),
+        }),
+        None => KeyReservation::InFlight,
+    })
+}
+
+/// Release a key held by a request that failed so it can be retried
+pub fn release_key(db: &Connection, key: &str) -> Result<()> {
+    db.execute(
+        "DELETE FROM idempotency_key WHERE key = ?1 AND status IS NULL",
+        [key],
+    )?;
+    Ok(())
+}
+
+// Saved responses are kept long enough for offline clients to replay
+// their queue. Keys held by a request that never finished, e.g. the
+// server restarted, are released sooner.
+fn expire_keys(db: &Connection) -> Result<()> {
+    db.execute(
+        r"
+          DELETE FROM idempotency_key
+          WHERE created_at < datetime('now', '-7 days')
+          OR (status IS NULL AND created_at < datetime('now', '-10 minutes'))
+        ",
+        [],
+    )?;
+    Ok(())
+}
+
+/// An operation that was queued by the client
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SyncOperation {
+    /// Append an entry to the capture file
+    Capture {
+        title: String,
+        body: Option<String>,
+        url: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    /// Change the status of a task
+    TaskStatus { id: String, status: String },
+    /// Mark a feed item as read
+    FeedItemRead { id: String },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncRequest {
+    pub idempotency_key: String,
+    /// When the operation happened on the client as an RFC 3339
+    /// timestamp
+    pub client_timestamp: Option<String>,
+    #[serde(flatten)]
+    pub operation: SyncOperation,
+}
+
+impl SyncRequest {
+    /// The client timestamp in local time if it's set and valid
+    pub fn created_at(&self) -> Option<NaiveDateTime> {
+        let ts = self.client_timestamp.as_ref()?;
+        DateTime::parse_from_rfc3339(ts)
+            .ok()
+            .map(|d| d.with_timezone(&Local).naive_local())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncBatchRequest {
+    pub operations: Vec<SyncRequest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncResult {
+    pub idempotency_key: String,
+    pub success: bool,
+    /// True if the operation was already applied by an earlier
+    /// request
+    #[serde(default)]
+    pub replayed: bool,
+    pub error: Option<String>,
+}
@@ -0,0 +1,246 @@
+/// Tags across notes: renaming them e.g. to merge `wip` into
+/// `in-progress` after they drifted apart, and nesting them with `/`
+/// e.g. `project/acme` which can be browsed as a tree and searched by
+/// prefix e.g. `tags:project/*`
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use rusqlite::{Connection, Result};
+use serde::Serialize;
+use tantivy::schema::Field;
+use tantivy::TantivyDocument;
+
+use super::markdown::is_markdown;
+use super::source::{is_encrypted, notes, NoteWalk};
+
+/// Returns true if the tag can be written to a note. Tags are
+/// separated by spaces and colons so they can't contain either.
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.contains(|c: char| c == ':' || c.is_whitespace())
+}
+
+/// Rename the tag in the note's `#+FILETAGS:` and headline tags. If
+/// something is already tagged with `to` the tags are merged.
+/// Returns `None` if the note doesn't have the tag.
+///
+/// ```rust
+/// use indexer::tags::rename_tag_in_org;
+///
+/// let org = "#+TITLE: Plans\n#+FILETAGS: wip rust\n\n* Draft   :wip:blog:\n* Review :wip:in-progress:\n";
+/// assert_eq!(
+///     rename_tag_in_org(org, "wip", "in-progress").unwrap(),
+///     "#+TITLE: Plans\n#+FILETAGS: in-progress rust\n\n* Draft   :in-progress:blog:\n* Review :in-progress:\n"
+/// );
+/// assert_eq!(rename_tag_in_org(org, "draft", "wip"), None);
+/// ```
+pub fn rename_tag_in_org(content: &str, from: &str, to: &str) -> Option<String> {
+    let mut changed = false;
+    let renamed: String = content
+        .split_inclusive('\n')
+        .map(|line| match rename_in_line(line, from, to) {
+            Some(renamed) => {
+                changed = true;
+                renamed
+            }
+            None => line.to_string(),
+        })
+        .collect();
+    changed.then_some(renamed)
+}
+
+fn rename_in_line(line: &str, from: &str, to: &str) -> Option<String> {
+    let content = line.trim_end_matches(['\n', '\r']);
+    let ending = &line[content.len()..];
+
+    let is_filetags = content
+        .get(..11)
+        .is_some_and(|k| k.eq_ignore_ascii_case("#+FILETAGS:"));
+    if is_filetags {
+        let value = content[11..].trim();
+        let tags = rename_tags(value.split([' ', ':']), from, to)?;
+        // Keep the style of the keyword e.g. `:a:b:` or `a b`
+        let value = if value.starts_with(':') {
+            format!(":{}:", tags.join(":"))
+        } else {
+            tags.join(" ")
+        };
+        return Some(format!("{} {}{}", &content[..11], value, ending));
+    }
+
+    // Headline tags are the last word of the headline e.g. `:a:b:`
+    let is_headline = content.starts_with('*') && content.trim_start_matches('*').starts_with(' ');
+    if is_headline {
+        let trimmed = content.trim_end();
+        let start = trimmed.rfind(char::is_whitespace)? + 1;
+        let word = &trimmed[start..];
+        if word.len() < 3 || !word.starts_with(':') || !word.ends_with(':') {
+            return None;
+        }
+        let tags = rename_tags(word.split(':'), from, to)?;
+        return Some(format!(
+            "{}:{}:{}{}",
+            &trimmed[..start],
+            tags.join(":"),
+            &content[trimmed.len()..],
+            ending
+        ));
+    }
+    None
+}
+
+// The tags with `from` replaced, or `None` if it isn't one of them
+fn rename_tags<'a>(
+    tags: impl Iterator<Item = &'a str>,
+    from: &str,
+    to: &str,
+) -> Option<Vec<String>> {
+    let tags: Vec<&str> = tags.filter(|t| !t.is_empty()).collect();
+    if !tags.contains(&from) {
+        return None;
+    }
+    let mut renamed: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = if tag == from { to } else { tag };
+        if !renamed.iter().any(|t| t == tag) {
+            renamed.push(tag.to_string());
+        }
+    }
+    Some(renamed)
+}
+
+/// Rename the tag in every org note in the directory. Returns the
+/// notes that changed. Encrypted notes can't be changed and Markdown
+/// notes keep their tags in the frontmatter so both are skipped.
+pub fn rename_tag(
+    notes_path: &str,
+    walk: &NoteWalk,
+    from: &str,
+    to: &str,
+) -> io::Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for path in notes(notes_path, walk) {
+        if is_encrypted(&path) || is_markdown(&path) {
+            continue;
+        }
+        let content = fs::read_to_string(&path)?;
+        if let Some(renamed) = rename_tag_in_org(&content, from, to) {
+            fs::write(&path, renamed)?;
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+/// Returns the parents of each nested tag in the comma separated
+/// list with a trailing `/*` so `tags:project/*` matches every tag
+/// under `project`
+///
+/// ```rust
+/// use indexer::tags::tag_parents;
+///
+/// assert_eq!(
+///     tag_parents("project/acme,area/health/diet,rust,project/beta"),
+///     vec!["area/*", "area/health/*", "project/*"]
+/// );
+/// ```
+pub fn tag_parents(tags: &str) -> Vec<String> {
+    let parents: BTreeSet<String> = tags
+        .split(',')
+        .flat_map(|tag| {
+            tag.trim()
+                .match_indices('/')
+                .map(|(i, _)| &tag.trim()[..i])
+                .filter(|parent| !parent.is_empty())
+                .map(|parent| format!("{}/*", parent))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    parents.into_iter().collect()
+}
+
+/// Add the comma separated tags to the document along with the
+/// parents of nested tags so they can be searched by prefix
+pub fn add_tags(doc: &mut TantivyDocument, field: Field, tags: &str) {
+    doc.add_text(field, tags);
+    for parent in tag_parents(tags) {
+        doc.add_text(field, parent);
+    }
+}
+
+/// A tag in the tree of nested tags
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TagNode {
+    /// Last part of the tag e.g. `acme` for `project/acme`
+    pub name: String,
+    pub tag: String,
+    /// Number of documents with exactly this tag
+    pub count: usize,
+    /// Number of documents with this tag or any tag under it
+    pub total: usize,
+    pub children: Vec<TagNode>,
+}
+
+/// Build the tree of tags from the comma separated tags of each
+/// document. Parents that aren't used on their own are included with
+/// a `count` of 0.
+///
+/// ```rust
+/// use indexer::tags::tag_tree;
+///
+/// let tree = tag_tree(["project/acme,rust", "project/acme,project/beta", "project"]);
+/// assert_eq!(tree.len(), 2);
+/// assert_eq!((tree[0].tag.as_str(), tree[0].count, tree[0].total), ("project", 1, 3));
+/// assert_eq!((tree[0].children[0].name.as_str(), tree[0].children[0].total), ("acme", 2));
+/// assert_eq!(tree[0].children[1].tag, "project/beta");
+/// assert_eq!((tree[1].tag.as_str(), tree[1].count), ("rust", 1));
+/// ```
+pub fn tag_tree<'a>(docs: impl IntoIterator<Item = &'a str>) -> Vec<TagNode> {
+    // Count and total of each tag
+    let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for tags in docs {
+        let tags: BTreeSet<&str> = tags
+            .split(',')
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .collect();
+        // A document is counted once for each parent even if it has
+        // more than one tag under it
+        let mut with_parents: BTreeSet<&str> = BTreeSet::new();
+        for tag in tags.iter() {
+            counts.entry(tag.to_string()).or_default().0 += 1;
+            with_parents.insert(tag);
+            for (i, _) in tag.match_indices('/') {
+                with_parents.insert(&tag[..i]);
+            }
+        }
+        for tag in with_parents {
+            counts.entry(tag.to_string()).or_default().1 += 1;
+        }
+    }
+    tag_children(&counts, None)
+}
+
+fn tag_children(counts: &BTreeMap<String, (usize, usize)>, parent: Option<&str>) -> Vec<TagNode> {
+    counts
+        .iter()
+        .filter(|(tag, _)| tag.rsplit_once('/').map(|(p, _)| p) == parent)
+        .map(|(tag, (count, total))| TagNode {
+            name: tag.rsplit('/').next().unwrap_or(tag).to_string(),
+            tag: tag.clone(),
+            count: *count,
+            total: *total,
+            children: tag_children(counts, Some(tag)),
+        })
+        .collect()
+}
+
+/// Tree of the tags used by notes and tasks
+pub fn note_tag_tree(db: &Connection) -> Result<Vec<TagNode>> {
+    let tags: Vec<String> = db
+        .prepare("SELECT tags FROM note_meta WHERE type IN ('note', 'task') AND tags IS NOT NULL")?
+        .query_map([], |r| r.get(0))?
+        .collect::<Result<_>>()?;
+    Ok(tag_tree(tags.iter().map(|t| t.as_str())))
+}
@@ -0,0 +1,189 @@
+/// Kanban style board of tasks grouped into columns by status and
+/// moving tasks between columns by editing the org file
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use orgize::ast::Headline;
+use orgize::rowan::ast::AstNode;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use super::indexing::{parse_org, task_id, TaskKeywords};
+
+/// A column on the board and the task statuses it contains
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardColumn {
+    pub name: String,
+    pub statuses: Vec<String>,
+}
+
+impl BoardColumn {
+    /// Read the columns from `INDEXER_BOARD_COLUMNS` which is a comma
+    /// separated list of columns where each column is a name and the
+    /// statuses it contains e.g. `Todo:TODO|NEXT,Done:DONE|CANCELED`.
+    /// Defaults to a column for each task keyword.
+    pub fn from_env(keywords: &TaskKeywords) -> Vec<Self> {
+        env::var("INDEXER_BOARD_COLUMNS")
+            .map(|s| Self::parse(&s))
+            .unwrap_or_else(|_| Self::defaults(keywords))
+    }
+
+    /// One column for each task keyword in the order they are
+    /// configured
+    pub fn defaults(keywords: &TaskKeywords) -> Vec<Self> {
+        keywords
+            .todo
+            .iter()
+            .chain(keywords.done.iter())
+            .map(|k| Self {
+                name: k.to_string(),
+                statuses: vec![k.to_string()],
+            })
+            .collect()
+    }
+
+    fn parse(s: &str) -> Vec<Self> {
+        s.split(',')
+            .filter_map(|column| {
+                let (name, statuses) = column.split_once(':')?;
+                let statuses: Vec<String> = statuses
+                    .split('|')
+                    .map(|i| i.trim().to_uppercase())
+                    .filter(|i| !i.is_empty())
+                    .collect();
+                if name.trim().is_empty() || statuses.is_empty() {
+                    return None;
+                }
+                Some(Self {
+                    name: name.trim().to_string(),
+                    statuses,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct BoardCard {
+    id: String,
+    title: String,
+    status: String,
+    tags: Option<String>,
+    file_name: String,
+    scheduled: Option<String>,
+    deadline: Option<String>,
+    // Position of the card within the column
+    position: usize,
+}
+
+#[derive(Serialize)]
+pub struct BoardColumnResult {
+    name: String,
+    statuses: Vec<String>,
+    // Position of the column on the board
+    order: usize,
+    count: usize,
+    tasks: Vec<BoardCard>,
+}
+
+/// Returns the tasks grouped into the board columns. Within a column,
+/// tasks with the soonest deadline or scheduled date come first
+/// followed by tasks without dates ordered by title.
+pub fn task_board(db: &Connection, columns: &[BoardColumn]) -> Result<Vec<BoardColumnResult>> {
+    let mut stmt = db.prepare(
+        r"
+          SELECT
+            id,
+            title,
+            status,
+            tags,
+            file_name,
+            scheduled,
+            deadline
+          FROM note_meta
+          WHERE type = 'task'
+          AND status IN (SELECT value FROM json_each(?1))
+          ORDER BY
+            COALESCE(deadline, scheduled) IS NULL,
+            COALESCE(deadline, scheduled),
+            title
+        ",
+    )?;
+
+    let mut board = Vec::new();
+    for (order, column) in columns.iter().enumerate() {
+        let statuses = serde_json::json!(column.statuses).to_string();
+        let tasks = stmt
+            .query_map([statuses], |r| {
+                Ok(BoardCard {
+                    id: r.get(0)?,
+                    title: r.get(1)?,
+                    status: r.get(2)?,
+                    tags: r.get(3)?,
+                    file_name: r.get(4)?,
+                    scheduled: r.get(5)?,
+                    deadline: r.get(6)?,
+                    position: 0,
+                })
+            })?
+            .enumerate()
+            .map(|(position, card)| card.map(|c| BoardCard { position, ..c }))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        board.push(BoardColumnResult {
+            name: column.name.clone(),
+            statuses: column.statuses.clone(),
+            order,
+            count: tasks.len(),
+            tasks,
+        });
+    }
+    Ok(board)
+}
+
+/// Change the status keyword of the task by editing the org file it's
+/// in. Returns the path of the file that was changed so it can be
+/// re-indexed.
+pub fn set_task_status(
+    db: &Connection,
+    keywords: &TaskKeywords,
+    id: &str,
+    status: &str,
+) -> Result<PathBuf> {
+    let status = status.trim().to_uppercase();
+    if !keywords.contains(&status) {
+        return Err(anyhow!("Unknown task status {}", status));
+    }
+
+    let file_name: String = db
+        .query_row(
+            "SELECT file_name FROM note_meta WHERE id = ?1 AND type = 'task'",
+            [id],
+            |r| r.get(0),
+        )
+        .map_err(|_| anyhow!("Task {} not found", id))?;
+    let path = PathBuf::from(&file_name);
+    let mut content = fs::read_to_string(&path)?;
+
+    // Tasks can be nested under other headlines so look at all of
+    // them rather than just the top level
+    let org = parse_org(&content, keywords);
+    let keyword_range = org
+        .document()
+        .syntax()
+        .descendants()
+        .filter_map(Headline::cast)
+        .filter(|h| task_id(h) == id)
+        .find_map(|h| h.todo_keyword())
+        .map(|k| k.text_range())
+        .ok_or_else(|| anyhow!("Task {} not found in {}", id, file_name))?;
+
+    content.replace_range(
+        usize::from(keyword_range.start())..usize::from(keyword_range.end()),
+        &status,
+    );
+    fs::write(&path, content)?;
+
+    Ok(path)
+}
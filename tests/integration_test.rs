@@ -1,16 +1,32 @@
 #[cfg(test)]
 mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
     use std::env;
     use std::fs;
-    use std::time::SystemTime;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
 
-    use indexer::server::{app, AppState, AppConfig};
-    use indexer::db::vector_db;
+    use indexer::server::{app, AppState, AppConfig, SseConfig};
+    use indexer::ann::{load_vectors, AnnConfig, AnnIndex};
+    use indexer::db::{migrate_db, set_vector_format, vector_db, VectorFormat};
+    use indexer::indexing::{index_all, index_all_with_progress, index_writer, Collection, IndexConfig, IndexEvent, NoteType, TaskKeywords};
+    use indexer::search::{fulltext_search, search_notes_with_vector, search_similar_vector, similar_notes, SearchExclusions, SearchFilters, SearchIndex};
+    use indexer::tasks::BoardColumn;
+    use indexer::Indexer;
+    use indexer::source::{Document, GitChanges, NoteWalk, OrgDirectory, SourceProvider};
+    use indexer::git::{commit_and_push_repo, git_command, GitAuth};
+    use indexer::feeds::{index_feed_items, parse_feed};
+    use indexer::hash::sha256_hex;
     use axum::{
         Router,
         body::Body,
         http::{Request, StatusCode},
     };
+    use rusqlite::Connection;
+    use serde_json::Value;
+    use tokio_stream::StreamExt;
     use tower::util::ServiceExt; // for `call`, `oneshot`, and `ready`
 
     async fn body_to_string(body: Body) -> String {
@@ -19,12 +35,26 @@ mod tests {
     }
 
     fn test_app() -> Router {
+        test_app_with(|_, _| {})
+    }
+
+    // Same as `test_app` but allows seeding the db and notes directory
+    // before the app is created
+    fn test_app_with<F: FnOnce(&mut Connection, &AppConfig)>(setup: F) -> Router {
+        test_app_configured(|_| {}, setup)
+    }
+
+    // Same as `test_app_with` but allows changing the config first
+    fn test_app_configured<C: FnOnce(&mut AppConfig), F: FnOnce(&mut Connection, &AppConfig)>(
+        configure: C,
+        setup: F,
+    ) -> Router {
         // Create a unique directory for the test with a randomly
         // generated name using a timestamp to avoid collisions and
         // vulnerabilities
         let temp_dir = env::temp_dir();
-        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs().to_string();
-        let dir = temp_dir.join(ts);
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = temp_dir.join(format!("{}-{}", ts, rand::random::<u32>()));
         fs::create_dir_all(&dir).expect("Failed to create base directory");
 
         // Create the directory from each path
@@ -35,11 +65,27 @@ mod tests {
         fs::create_dir_all(&index_path).expect("Failed to create index directory");
         fs::create_dir_all(&vec_db_path).expect("Failed to create db directory");
 
-        let db = vector_db(dir.join(&vec_db_path).to_str().unwrap()).expect("Failed to connect to db");
-        let app_config = AppConfig {
+        let mut db = vector_db(dir.join(&vec_db_path).to_str().unwrap()).expect("Failed to connect to db");
+        migrate_db(&db).expect("DB migration failed");
+        let mut app_config = AppConfig {
             notes_path: notes_path.display().to_string(),
             index_path: index_path.display().to_string(),
+            search_exclusions: SearchExclusions::default(),
+            index_config: IndexConfig::default(),
+            ical_token: None,
+            share_secret: "secret".to_string(),
+            board_columns: BoardColumn::defaults(&TaskKeywords::default()),
+            quick_add_tokens: BTreeMap::from([("laptop".to_string(), "secret".to_string())]),
+            cors_origins: vec!["chrome-extension://indexer".to_string()],
+            ann: AnnConfig::default(),
+            tls: None,
+            sse: SseConfig {
+                heartbeat: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
         };
+        configure(&mut app_config);
+        setup(&mut db, &app_config);
         let app_state = AppState::new(db, app_config);
         app(app_state)
     }
@@ -58,4 +104,2927 @@ mod tests {
         let body = body_to_string(response.into_body()).await;
         assert!(body.contains("input id=\"search\""));
     }
+
+    #[tokio::test]
+    async fn it_reports_readiness() {
+        let app = test_app();
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/readyz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["checks"]["db"], true);
+        assert!(body["checks"]["embeddings_model"].is_boolean());
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=test").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert!(body["similarity_available"].is_boolean());
+    }
+
+    #[tokio::test]
+    async fn it_returns_related_notes() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, tags, body)
+                VALUES
+                  ('a', 'note', 'a.org', 'Note A', NULL, 'A'),
+                  ('b', 'note', 'b.org', 'Note B', 'foo', 'B'),
+                  ('c', 'note', 'c.org', 'Note C', NULL, 'C');
+                INSERT INTO note_related(note_meta_id, related_id, distance)
+                VALUES ('a', 'c', 0.5), ('a', 'b', 0.1);
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/a/related").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let ids: Vec<&str> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["b", "c"]);
+        assert_eq!(body["results"][0]["distance"], 0.1);
+    }
+
+    #[tokio::test]
+    async fn it_serves_tasks_calendar() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body, status, scheduled, deadline, repeat)
+                VALUES
+                  ('t1', 'task', 'a.org', 'Water plants', '', 'TODO', '2025-01-01', NULL, '+1w'),
+                  ('t2', 'task', 'a.org', 'Ship it', '', 'TODO', NULL, '2025-02-03', NULL),
+                  ('t3', 'task', 'a.org', 'Already done', '', 'DONE', '2025-01-01', NULL, NULL);
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/tasks.ics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body_to_string(response.into_body()).await;
+        assert!(body.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(body.contains("SUMMARY:Scheduled: Water plants\r\nRRULE:FREQ=WEEKLY;INTERVAL=1"));
+        assert!(body.contains("DTSTART;VALUE=DATE:20250203\r\nSUMMARY:Deadline: Ship it"));
+        assert!(!body.contains("Already done"));
+    }
+
+    #[tokio::test]
+    async fn it_returns_tasks_board() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body, status, scheduled, deadline)
+                VALUES
+                  ('t1', 'task', 'a.org', 'Later', '', 'TODO', NULL, NULL),
+                  ('t2', 'task', 'a.org', 'Soon', '', 'TODO', NULL, '2025-02-03'),
+                  ('t3', 'task', 'a.org', 'Finished', '', 'DONE', NULL, NULL);
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/tasks/board").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let todo = &body["columns"][0];
+        assert_eq!(todo["name"], "TODO");
+        assert_eq!(todo["count"], 2);
+        assert_eq!(todo["tasks"][0]["id"], "t2");
+        assert_eq!(todo["tasks"][1]["position"], 1);
+        assert_eq!(body["columns"][2]["name"], "DONE");
+        assert_eq!(body["columns"][2]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn it_updates_task_status() {
+        let app = test_app_with(|db, config| {
+            let file_name = Path::new(&config.notes_path).join("tasks.org");
+            fs::write(
+                &file_name,
+                ":PROPERTIES:\n:ID: note-1\n:END:\n#+TITLE: Tasks\n\n* TODO Write tests\n:PROPERTIES:\n:ID: task-1\n:END:\n",
+            )
+            .unwrap();
+            db.execute(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body, status)
+                VALUES ('task-1', 'task', ?1, 'Write tests', '', 'TODO')
+                ",
+                [file_name.display().to_string()],
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/tasks/task-1/status")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"status": "done"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri("/tasks/board").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["columns"][0]["count"], 0);
+        assert_eq!(body["columns"][2]["tasks"][0]["id"], "task-1");
+    }
+
+    #[tokio::test]
+    async fn it_renames_tags_across_notes() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(
+                notes.join("draft.org"),
+                ":PROPERTIES:\n:ID: draft\n:END:\n#+TITLE: Draft\n#+FILETAGS: wip writing\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("garden.org"),
+                ":PROPERTIES:\n:ID: garden\n:END:\n#+TITLE: Garden\n\n* Beds :wip:doing:\n:PROPERTIES:\n:ID: beds\n:END:\nBuild them.\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            notes_path = config.notes_path.clone();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/tags/rename")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"from": "wip", "to": "doing"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["files"].as_array().unwrap().len(), 2);
+
+        let notes = Path::new(&notes_path);
+        assert!(fs::read_to_string(notes.join("draft.org")).unwrap().contains("#+FILETAGS: doing writing\n"));
+        // Tags are merged when the headline already has the new tag
+        assert!(fs::read_to_string(notes.join("garden.org")).unwrap().contains("* Beds :doing:\n"));
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/search?query=tags:wip").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert!(body["results"].as_array().unwrap().is_empty());
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=tags:doing").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let mut ids: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["beds", "draft"]);
+    }
+
+    #[tokio::test]
+    async fn it_links_to_notes_in_view() {
+        let app = test_app_with(|db, config| {
+            let file_name = Path::new(&config.notes_path).join("a.org");
+            fs::write(
+                &file_name,
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nSee [[id:b][the other note]] and [[note c]] but not [[Missing]].\n",
+            )
+            .unwrap();
+            db.execute(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body)
+                VALUES ('a', 'note', ?1, 'Note A', ''), ('b', 'note', 'b.org', 'Note B', ''), ('c', 'note', 'c.org', 'Note C', '')
+                ",
+                [file_name.display().to_string()],
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/a/view").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = body_to_string(response.into_body()).await;
+        assert!(body.contains(r#"<a href="/notes/b/view">the other note</a>"#));
+        assert!(body.contains(r#"<a href="/notes/c/view">note c</a>"#));
+        assert!(body.contains(r#"<a href="Missing">Missing</a>"#));
+    }
+
+    #[tokio::test]
+    async fn it_returns_note_stats() {
+        let app = test_app_with(|db, config| {
+            fs::write(
+                Path::new(&config.notes_path).join("a.org"),
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\n* First\nOne two three.\n** Second\nFour five.\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/a/stats").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["word_count"], 7);
+        assert_eq!(body["heading_count"], 2);
+        assert_eq!(body["reading_time_minutes"], 1);
+        assert!(body["modified_at"].as_str().is_some_and(|m| m.ends_with('Z')));
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/missing/stats").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_diffs_a_note_against_the_index() {
+        let app = test_app_with(|db, config| {
+            let git = |args: &[&str]| {
+                let output = std::process::Command::new("git").current_dir(&config.notes_path).args(args).output().unwrap();
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            };
+            git(&["init", "-q"]);
+            git(&["config", "user.name", "Test"]);
+            git(&["config", "user.email", "test@example.com"]);
+            fs::write(
+                Path::new(&config.notes_path).join("a.org"),
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nOne two three.\n",
+            )
+            .unwrap();
+            git(&["add", "-A"]);
+            git(&["commit", "-qm", "First"]);
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            fs::write(
+                Path::new(&config.notes_path).join("a.org"),
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nOne two four.\n",
+            )
+            .unwrap();
+            git(&["commit", "-qam", "Second"]);
+        });
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, body) = get("/notes/a/diff").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["against"], "disk");
+        assert_eq!(body["stale"], true);
+        assert!(body["diff"].as_str().unwrap().ends_with("-One two three.\n+One two four.\n"));
+
+        // The index matches the previous commit
+        let (_, body) = get("/notes/a/diff?against=HEAD~1").await;
+        assert_eq!(body["stale"], false);
+        assert_eq!(body["diff"], "");
+
+        let (status, _) = get("/notes/missing/diff").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_finds_notes_by_dates_mentioned() {
+        let app = test_app_with(|db, config| {
+            fs::write(
+                Path::new(&config.notes_path).join("trip.org"),
+                ":PROPERTIES:\n:ID: trip\n:END:\n#+TITLE: Trip\n\n* Flights\nLeaving <2025-06-11 Wed>--<2025-06-12 Thu>\n:LOGBOOK:\nCLOCK: [2025-06-20 Fri 10:00]--[2025-06-20 Fri 11:00] =>  1:00\n:END:\n",
+            )
+            .unwrap();
+            fs::write(
+                Path::new(&config.notes_path).join("journal.org"),
+                ":PROPERTIES:\n:ID: journal\n:END:\n#+TITLE: Journal\n\nBooked the hotel for [2025-06-12 Thu]\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/dates/2025-06-12").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let ids: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["trip", "journal"]);
+        assert_eq!(body["results"][0]["headline"], "Flights");
+
+        // Clocked time isn't a mention of the date
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/dates/2025-06-20").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert!(body["results"].as_array().unwrap().is_empty());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/search?query=mentions_date:2025-06-11").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let ids: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["trip"]);
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/dates/june").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_summarizes_changes_to_notes() {
+        let app = test_app_with(|db, config| {
+            let file_name = Path::new(&config.notes_path).join("a.org");
+            let source = OrgDirectory::new(&config.notes_path, None);
+            fs::write(&file_name, ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\n* Ideas\nOne two\n").unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            // Notes that didn't change aren't recorded
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            fs::write(&file_name, ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\n* Questions\nOne two three\n").unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, body) = get("/notes/changes?since=2025-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["note_id"], "a");
+        assert_eq!(results[0]["title"], "Note A");
+        assert_eq!(results[0]["words_added"], 2);
+        assert_eq!(results[0]["words_removed"], 1);
+        assert_eq!(results[0]["headings_added"], serde_json::json!(["Questions"]));
+        assert_eq!(results[0]["headings_removed"], serde_json::json!(["Ideas"]));
+        // The first time the note is indexed everything is added
+        assert_eq!(results[1]["words_removed"], 0);
+        assert_eq!(results[1]["headings_added"], serde_json::json!(["Ideas"]));
+
+        let (_, body) = get("/notes/changes?since=2999-01-01").await;
+        assert!(body["results"].as_array().unwrap().is_empty());
+
+        let (status, _) = get("/notes/changes?since=last-week").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_lints_notes() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            fs::write(notes_path.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nSome words.\n").unwrap();
+            fs::write(notes_path.join("c.org"), ":PROPERTIES:\n:ID: c\n:END:\n#+TITLE: Note C\n\nSome words.\n").unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            fs::write(notes_path.join("b.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note B\n\nOther words.\n").unwrap();
+            fs::write(notes_path.join("d.org"), ":PROPERTIES:\n:ID: d\n\nNo title or end.\n").unwrap();
+        });
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, body) = get("/notes/lint").await;
+        assert_eq!(status, StatusCode::OK);
+        let notes = body["notes"].as_array().unwrap();
+        let summary: Vec<(String, Vec<String>)> = notes
+            .iter()
+            .map(|n| {
+                let file_name = n["file_name"].as_str().unwrap();
+                let file_name = Path::new(file_name).file_name().unwrap().to_string_lossy().to_string();
+                let kinds = n["issues"].as_array().unwrap().iter().map(|i| i["kind"].as_str().unwrap().to_string()).collect();
+                (file_name, kinds)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.org".to_string(), vec!["duplicate_id".to_string()]),
+                ("b.org".to_string(), vec!["duplicate_id".to_string()]),
+                ("d.org".to_string(), vec!["malformed_property_drawer".to_string(), "missing_title".to_string()]),
+            ]
+        );
+        assert_eq!(notes[2]["issues"][0]["line"], 1);
+
+        let (status, body) = get("/notes/a/lint").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "a");
+        assert!(body["issues"][0]["message"].as_str().unwrap().ends_with("b.org"));
+
+        let (_, body) = get("/notes/c/lint").await;
+        assert!(body["issues"].as_array().unwrap().is_empty());
+
+        let (status, _) = get("/notes/missing/lint").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_returns_a_random_note() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, tags, body)
+                VALUES
+                  ('a', 'note', 'a.org', 'Note A', 'rust', ''),
+                  ('b', 'note', 'b.org', 'Note B', NULL, ''),
+                  ('c', 'task', 'b.org', 'Task C', 'rust', '');
+                ",
+            )
+            .unwrap();
+        });
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                (status, body)
+            }
+        };
+
+        // Tasks are only picked when asked for
+        let (status, body) = get("/notes/random?tags=rust").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "a");
+        let (_, body) = get("/notes/random?tags=rust&type=task").await;
+        assert_eq!(body["id"], "c");
+
+        // The note of the day doesn't change
+        let (_, first) = get("/notes/random?daily=true").await;
+        let (_, second) = get("/notes/random?daily=true").await;
+        assert_eq!(first["id"], second["id"]);
+
+        let (status, _) = get("/notes/random?tags=missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_searches_highlights() {
+        let app = test_app_with(|db, config| {
+            let file_name = Path::new(&config.notes_path).join("book.org");
+            let source = OrgDirectory::new(&config.notes_path, None);
+            fs::write(
+                &file_name,
+                ":PROPERTIES:\n:ID: book\n:END:\n#+TITLE: Reading notes\n#+SOURCE: Walden\n\n#+begin_quote\nI went to the woods.\n#+end_quote\n\n#+begin_quote\nAn old quote about woods.\n#+end_quote\n",
+            )
+            .unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            // Removed quotes are removed from the index
+            fs::write(
+                &file_name,
+                ":PROPERTIES:\n:ID: book\n:END:\n#+TITLE: Reading notes\n#+SOURCE: Walden\n\n#+begin_quote\nI went to the woods.\n#+end_quote\n\nMy own thoughts on the woods.\n",
+            )
+            .unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/highlights/search?query=woods").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["text"], "I went to the woods.");
+        assert_eq!(results[0]["source"], "Walden");
+        assert_eq!(results[0]["note_id"], "book");
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/highlights/search?query=woods&source=other").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert!(body["results"].as_array().unwrap().is_empty());
+
+        // Highlights are also a type of search result
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=woods").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let mut types: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["type"].as_str().unwrap()).collect();
+        types.sort();
+        assert_eq!(types, vec!["highlight", "note"]);
+    }
+
+    #[tokio::test]
+    async fn it_lists_favorite_notes() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body)
+                VALUES ('a', 'note', 'a.org', 'Note A', ''), ('b', 'note', 'b.org', 'Note B', '');
+                ",
+            )
+            .unwrap();
+        });
+
+        let favorite = |id: &str, favorite: bool| {
+            let app = app.clone();
+            let uri = format!("/notes/{}/favorite", id);
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(uri)
+                        .header("content-type", "application/json")
+                        .body(Body::from(format!(r#"{{"favorite": {}}}"#, favorite)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            }
+        };
+        assert_eq!(favorite("a", true).await, StatusCode::OK);
+        assert_eq!(favorite("b", true).await, StatusCode::OK);
+        // Adding a favorite again is a no-op
+        assert_eq!(favorite("a", true).await, StatusCode::OK);
+        assert_eq!(favorite("missing", true).await, StatusCode::NOT_FOUND);
+
+        let favorites = || {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri("/notes/favorites").body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                body["results"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(favorites().await, vec!["b", "a"]);
+
+        assert_eq!(favorite("b", false).await, StatusCode::OK);
+        assert_eq!(favorites().await, vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn it_tracks_a_reading_queue() {
+        let app = test_app_with(|db, _| {
+            db.execute(
+                "INSERT INTO note_meta(id, type, file_name, title, body) VALUES ('a', 'note', 'a.org', 'Note A', '')",
+                [],
+            )
+            .unwrap();
+        });
+
+        let post = |uri: &str, body: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            let body = body.to_string();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method("POST")
+                            .uri(uri)
+                            .header("content-type", "application/json")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                (status, body)
+            }
+        };
+        let list = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                body["results"].as_array().unwrap().clone()
+            }
+        };
+
+        let (status, note) = post("/reading", r#"{"note_id": "a"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(note["title"], "Note A");
+        assert_eq!(note["status"], "queued");
+        let (_, page) = post("/reading", r#"{"url": "https://example.com/article"}"#).await;
+        // Adding the same thing again returns the existing item
+        let (_, again) = post("/reading", r#"{"url": "https://example.com/article"}"#).await;
+        assert_eq!(again["id"], page["id"]);
+        assert_eq!(post("/reading", r#"{"note_id": "missing"}"#).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(post("/reading", "{}").await.0, StatusCode::BAD_REQUEST);
+
+        let (status, item) = post(&format!("/reading/{}/progress", page["id"]), r#"{"progress": 40}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(item["progress"], 40);
+        assert_eq!(item["status"], "reading");
+        let (_, item) = post(&format!("/reading/{}/progress", note["id"]), r#"{"progress": 100}"#).await;
+        assert_eq!(item["status"], "finished");
+        assert_eq!(post("/reading/999/progress", r#"{"progress": 10}"#).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            post(&format!("/reading/{}/progress", page["id"]), r#"{"progress": 101}"#).await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        assert_eq!(list("/reading").await.len(), 2);
+        let reading = list("/reading?status=reading").await;
+        assert_eq!(reading.len(), 1);
+        assert_eq!(reading[0]["url"], "https://example.com/article");
+    }
+
+    #[tokio::test]
+    async fn it_views_markdown_notes_and_missing_files() {
+        let mut notes_path = Path::new("").to_path_buf();
+        let app = test_app_with(|db, config| {
+            notes_path = Path::new(&config.notes_path).to_path_buf();
+            let md_path = notes_path.join("vault.md");
+            fs::write(&md_path, "---\nid: md\ntitle: Gardening in markdown\n---\n# Tomatoes\nWater them daily.\n").unwrap();
+            db.execute(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body)
+                VALUES ('md', 'note', ?1, 'Gardening in markdown', ''), ('gone', 'note', ?2, 'Gone', '')
+                ",
+                [md_path.display().to_string(), notes_path.join("gone.org").display().to_string()],
+            )
+            .unwrap();
+        });
+
+        let get = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(get("/notes/md/view".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // Markdown is converted to org before it's rendered
+        let body = body_to_string(response.into_body()).await;
+        assert!(body.contains("Tomatoes"));
+        assert!(!body.contains("title: Gardening"));
+
+        // Notes deleted since they were indexed aren't found
+        let response = app.clone().oneshot(get("/notes/gone/view".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.clone().oneshot(get("/notes/missing/view".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().method("POST").uri("/notes/md/share").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let url = body["url"].as_str().unwrap().to_string();
+        let response = app.clone().oneshot(get(url.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_to_string(response.into_body()).await.contains("Water them daily."));
+
+        fs::remove_file(notes_path.join("vault.md")).unwrap();
+        let response = app.oneshot(get(url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_bookmarks_with_invalid_tags() {
+        let app = test_app();
+
+        // Rejected before the page is fetched
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/bookmarks")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"url": "http://localhost:1/page", "tags": ["reading\n#+TITLE: Oops"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_shares_notes_with_a_public_link() {
+        let app = test_app_with(|db, config| {
+            let note_path = Path::new(&config.notes_path).join("shared.org");
+            fs::write(&note_path, "#+TITLE: Shared\n\nHello from a shared note\n").unwrap();
+            db.execute(
+                "INSERT INTO note_meta(id, type, file_name, title, body) VALUES ('shared', 'note', ?1, 'Shared', '')",
+                [note_path.display().to_string()],
+            )
+            .unwrap();
+            // Tasks can't be shared since that would show the whole file
+            db.execute(
+                "INSERT INTO note_meta(id, type, file_name, title, body) VALUES ('shared-task', 'task', ?1, 'Task', '')",
+                [note_path.display().to_string()],
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().method("POST").uri("/notes/shared/share?expires_in_hours=1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let url = body["url"].as_str().unwrap().to_string();
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_to_string(response.into_body()).await;
+        assert!(body.contains("Hello from a shared note"));
+
+        // Changing the token invalidates the signature
+        let last = if url.ends_with('0') { "1" } else { "0" };
+        let tampered = format!("{}{}", &url[..url.len() - 1], last);
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(&tampered).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().method("POST").uri("/notes/missing/share").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().method("POST").uri("/notes/shared-task/share").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().method("DELETE").uri("/notes/shared/share").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri(&url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn it_indexes_and_searches_as_a_library() {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = env::temp_dir().join(format!("{}-{}", ts, rand::random::<u32>()));
+        let notes_path = dir.join("notes");
+        fs::create_dir_all(&notes_path).unwrap();
+        fs::write(
+            notes_path.join("rust.org"),
+            ":PROPERTIES:\n:ID: library-note\n:END:\n#+TITLE: Embedding the indexer\n\nNo server required\n",
+        )
+        .unwrap();
+
+        let mut indexer = Indexer::open(
+            notes_path.to_str().unwrap(),
+            dir.join("index").to_str().unwrap(),
+            dir.join("db").to_str().unwrap(),
+        )
+        .unwrap();
+        indexer.index(None, true, false).unwrap();
+
+        let results = serde_json::to_value(indexer.search("embedding", false)).unwrap();
+        assert_eq!(results[0]["id"], "library-note");
+        assert_eq!(results[0]["title"], "Embedding the indexer");
+    }
+
+    #[tokio::test]
+    async fn it_returns_broken_links() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body)
+                VALUES ('a', 'note', 'a.org', 'Note A', ''), ('b', 'note', 'b.org', 'Note B', '');
+                INSERT INTO note_link(note_meta_id, kind, target)
+                VALUES ('a', 'id', 'b'), ('a', 'id', 'gone'), ('b', 'title', 'note a'), ('b', 'title', 'Nowhere');
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/links/broken").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let targets: Vec<&str> = body["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["target"].as_str().unwrap())
+            .collect();
+        assert_eq!(targets, vec!["gone", "Nowhere"]);
+    }
+
+    #[tokio::test]
+    async fn it_reports_orphan_notes() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body)
+                VALUES
+                  ('a', 'note', 'a.org', 'Note A', ''),
+                  ('b', 'note', 'b.org', 'Note B', ''),
+                  ('c', 'note', 'c.org', 'Note C', ''),
+                  ('d', 'note', 'd.org', 'Note D', '');
+                INSERT INTO note_link(note_meta_id, kind, target)
+                VALUES ('a', 'id', 'b'), ('c', 'id', 'gone');
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/report").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["broken_links"][0]["target"], "gone");
+        let orphans: Vec<&str> = body["orphans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(orphans, vec!["d"]);
+    }
+
+    #[tokio::test]
+    async fn it_marks_feed_items_read() {
+        let mut first_id = String::new();
+        let app = test_app_with(|db, config| {
+            let rss = r#"<?xml version="1.0"?>
+                <rss version="2.0"><channel><title>Blog</title>
+                <item><guid>1</guid><title>First post</title><link>https://example.com/1</link><description>Hello</description></item>
+                <item><guid>2</guid><title>Second post</title><link>https://example.com/2</link><description>World</description></item>
+                </channel></rss>"#;
+            let items = parse_feed("https://example.com/feed", rss.as_bytes()).unwrap();
+            assert_eq!(items.len(), 2);
+            first_id = items[0].id.clone();
+            let count = index_feed_items(db, &mut index_writer(&config.index_path), &items, None).unwrap();
+            assert_eq!(count, 2);
+            // Items that were already ingested are skipped
+            let count = index_feed_items(db, &mut index_writer(&config.index_path), &items, None).unwrap();
+            assert_eq!(count, 0);
+            // The link is kept in the props since items aren't files
+            let (file_name, url): (Option<String>, String) = db
+                .query_row(
+                    "SELECT file_name, json_extract(props, '$.url') FROM note_meta WHERE id = ?1",
+                    [&first_id],
+                    |r| Ok((r.get(0)?, r.get(1)?)),
+                )
+                .unwrap();
+            assert_eq!(file_name, None);
+            assert_eq!(url, "https://example.com/1");
+        });
+
+        let unread = |app: Router| async move {
+            let response = app
+                .oneshot(Request::builder().uri("/notes/search?query=type:feed_item%20AND%20read:false").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+            body["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["title"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+        let mut titles = unread(app.clone()).await;
+        titles.sort();
+        assert_eq!(titles, vec!["First post", "Second post"]);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/feeds/items/{}/read", first_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(unread(app.clone()).await, vec!["Second post"]);
+
+        // There is no file to view
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/notes/{}/view", first_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_quick_adds_to_capture_file() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|_, config| {
+            notes_path = config.notes_path.clone();
+        });
+        let request = |token: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/quick-add")
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(
+                    r#"{"url": "https://example.com", "title": "Example", "selection": "Some text", "tags": ["reading"]}"#,
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(request("secret")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let capture = fs::read_to_string(Path::new(&notes_path).join("capture.org")).unwrap();
+        assert!(capture.starts_with("* [[https://example.com][Example]] :reading:\n"));
+        assert!(capture.ends_with("#+begin_quote\nSome text\n#+end_quote\n"));
+    }
+
+    #[tokio::test]
+    async fn it_only_allows_configured_cors_origins() {
+        let app = test_app();
+        let preflight = |origin: &str| {
+            Request::builder()
+                .method("OPTIONS")
+                .uri("/quick-add")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "authorization,content-type")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(preflight("chrome-extension://indexer")).await.unwrap();
+        assert_eq!(response.headers()["access-control-allow-origin"], "chrome-extension://indexer");
+
+        let response = app.oneshot(preflight("https://evil.example.com")).await.unwrap();
+        assert!(response.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[tokio::test]
+    async fn it_records_changes_in_the_audit_log() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|_, config| {
+            notes_path = config.notes_path.clone();
+        });
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/quick-add")
+                    .header("content-type", "application/json")
+                    .header("authorization", "Bearer secret")
+                    .body(Body::from(r#"{"title": "Example", "selection": "Some text"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri("/audit").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let entry = &body["entries"][0];
+        assert_eq!(entry["actor"], "laptop");
+        assert_eq!(entry["action"], "capture");
+        assert_eq!(entry["summary"], "Capture Example");
+
+        // Entries can't be changed once they are recorded
+        let db_path = Path::new(&notes_path).parent().unwrap().join("db");
+        let db = vector_db(db_path.to_str().unwrap()).unwrap();
+        assert!(db.execute("DELETE FROM audit_log", []).is_err());
+        assert!(db.execute("UPDATE audit_log SET actor = NULL", []).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_replays_requests_with_idempotency_key() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|_, config| {
+            notes_path = config.notes_path.clone();
+        });
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/notes/capture")
+                .header("content-type", "application/json")
+                .header("idempotency-key", "abc")
+                .body(Body::from(r#"{"title": "Call mom"}"#))
+                .unwrap()
+        };
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(request()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/json");
+            let body = body_to_string(response.into_body()).await;
+            assert_eq!(body, r#"{"success":true}"#);
+        }
+
+        let capture = fs::read_to_string(Path::new(&notes_path).join("capture.org")).unwrap();
+        assert_eq!(capture.matches("* Call mom").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_scopes_idempotency_keys_to_the_request() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|db, config| {
+            notes_path = config.notes_path.clone();
+            // A request that is still being handled
+            db.execute(
+                "INSERT INTO idempotency_key(key, request_hash) VALUES (?1, ?2)",
+                ["POST /notes/capture:pending", &sha256_hex(br#"{"title": "Pending"}"#)],
+            )
+            .unwrap();
+        });
+        let request = |uri: &str, key: &str, body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("authorization", "Bearer secret")
+                .header("idempotency-key", key)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("/notes/capture", "abc", r#"{"title": "Call mom"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Reusing the key for a different body is rejected
+        let response = app
+            .clone()
+            .oneshot(request("/notes/capture", "abc", r#"{"title": "Call dad"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // The same key on another route is a different request
+        let response = app
+            .clone()
+            .oneshot(request("/quick-add", "abc", r#"{"title": "Example", "selection": "Some text"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(request("/notes/capture", "pending", r#"{"title": "Pending"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let capture = fs::read_to_string(Path::new(&notes_path).join("capture.org")).unwrap();
+        assert_eq!(capture.matches("* Call mom").count(), 1);
+        assert_eq!(capture.matches("Some text").count(), 1);
+        assert!(!capture.contains("Call dad"));
+        assert!(!capture.contains("Pending"));
+
+        // Quick adds are authorized before a saved response is replayed
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/quick-add")
+                    .header("content-type", "application/json")
+                    .header("idempotency-key", "abc")
+                    .body(Body::from(r#"{"title": "Example", "selection": "Some text"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn it_syncs_a_batch_of_operations() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|_, config| {
+            notes_path = config.notes_path.clone();
+        });
+        let request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/sync/batch")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"operations": [
+                        {"idempotency_key": "1", "client_timestamp": "2025-01-06T10:00:00Z", "op": "capture", "title": "Offline thought"},
+                        {"idempotency_key": "2", "op": "task_status", "id": "missing", "status": "DONE"}
+                    ]}"#,
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["results"][0]["success"], true);
+        assert_eq!(body["results"][0]["replayed"], false);
+        assert_eq!(body["results"][1]["success"], false);
+        assert!(body["results"][1]["error"].as_str().unwrap().contains("not found"));
+
+        // Retrying the batch doesn't apply the capture twice
+        let response = app.oneshot(request()).await.unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["results"][0]["replayed"], true);
+        assert_eq!(body["results"][1]["replayed"], false);
+
+        let capture = fs::read_to_string(Path::new(&notes_path).join("capture.org")).unwrap();
+        assert_eq!(capture.matches("* Offline thought").count(), 1);
+        assert!(capture.contains(":CREATED: [2025-01-0"));
+    }
+
+    #[tokio::test]
+    async fn it_suggests_notes_by_title_prefix() {
+        let app = test_app_with(|db, config| {
+            fs::write(
+                Path::new(&config.notes_path).join("go.org"),
+                ":PROPERTIES:\n:ID: go\n:ROAM_ALIASES: Golang\n:END:\n#+TITLE: Go programming\n\nNotes on Go.\n",
+            )
+            .unwrap();
+            fs::write(
+                Path::new(&config.notes_path).join("google.org"),
+                ":PROPERTIES:\n:ID: google\n:END:\n#+TITLE: Google\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let suggest = |app: Router, q: &str| {
+            let uri = format!("/notes/suggest?q={}", q);
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                let mut ids: Vec<String> = body["results"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| r["id"].as_str().unwrap().to_string())
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        assert_eq!(suggest(app.clone(), "go").await, vec!["go", "google"]);
+        assert_eq!(suggest(app.clone(), "go%20prog").await, vec!["go"]);
+        assert_eq!(suggest(app.clone(), "golan").await, vec!["go"]);
+        assert_eq!(suggest(app, "").await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn it_returns_bulk_note_metadata() {
+        let app = test_app_with(|db, _| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, tags, body)
+                VALUES
+                  ('a', 'note', 'a.org', 'Note A', 'foo', ':PROPERTIES:
+:ID: a
+:END:
+#+TITLE: Note A
+
+First line.
+Second line.'),
+                  ('b', 'note', 'b.org', 'Note B', NULL, 'B');
+                ",
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/notes/bulk")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"ids": ["b", "missing", "a"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["id"], "b");
+        assert_eq!(results[1]["title"], "Note A");
+        assert_eq!(results[1]["tags"], "foo");
+        assert_eq!(results[1]["snippet"], "First line. Second line.");
+    }
+
+    #[tokio::test]
+    async fn it_refiles_from_capture() {
+        let mut notes_path = String::new();
+        let app = test_app_with(|_, config| {
+            notes_path = config.notes_path.clone();
+            let notes = Path::new(&config.notes_path);
+            fs::write(
+                notes.join("capture.org"),
+                "* Read article :reading:\n:PROPERTIES:\n:CREATED: [2025-01-06 Mon 10:00]\n:END:\nSome text\n** Notes\n* Keep me\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("projects.org"),
+                ":PROPERTIES:\n:ID: projects\n:END:\n#+TITLE: Projects\n\n* Inbox\n* Other\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("a.org"),
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nSee [[file:capture.org::*Read article][the article]].\n",
+            )
+            .unwrap();
+        });
+
+        // Headlines without an org-id use a hash of the title
+        let mut hasher = DefaultHasher::new();
+        "Read article".hash(&mut hasher);
+        let id = hasher.finish().to_string();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/notes/{}/refile", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"file_name": "projects.org", "parent": "Inbox"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let new_id = body["id"].as_str().unwrap();
+        assert_ne!(new_id, id);
+
+        let notes = Path::new(&notes_path);
+        assert_eq!(fs::read_to_string(notes.join("capture.org")).unwrap(), "* Keep me\n");
+        assert_eq!(
+            fs::read_to_string(notes.join("projects.org")).unwrap(),
+            format!(
+                ":PROPERTIES:\n:ID: projects\n:END:\n#+TITLE: Projects\n\n* Inbox\n** Read article :reading:\n:PROPERTIES:\n:ID: {}\n:CREATED: [2025-01-06 Mon 10:00]\n:END:\nSome text\n*** Notes\n* Other\n",
+                new_id
+            )
+        );
+        assert!(fs::read_to_string(notes.join("a.org"))
+            .unwrap()
+            .contains(&format!("[[id:{}][the article]]", new_id)));
+    }
+
+    #[tokio::test]
+    async fn it_searches_notes_by_property() {
+        let app = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(
+                notes.join("apollo.org"),
+                ":PROPERTIES:\n:ID: apollo\n:CLIENT: Acme\n:END:\n#+TITLE: Apollo kickoff\n#+PROJECT: launch\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("gemini.org"),
+                ":PROPERTIES:\n:ID: gemini\n:CLIENT: Initech\n:END:\n#+TITLE: Gemini kickoff\n#+PROJECT: launch\n",
+            )
+            .unwrap();
+            let index_config = IndexConfig {
+                indexed_properties: vec!["client".to_string(), "project".to_string()],
+                ..Default::default()
+            };
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+        });
+
+        let search = |app: Router, query: &str| {
+            let uri = format!("/notes/search?query={}", query.replace(' ', "%20"));
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                let mut ids: Vec<String> = body["results"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| r["id"].as_str().unwrap().to_string())
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        // Properties from the drawer and keywords are both searchable
+        assert_eq!(search(app.clone(), "prop.client:acme").await, vec!["apollo"]);
+        assert_eq!(search(app.clone(), "prop.project:launch").await, vec!["apollo", "gemini"]);
+        assert_eq!(search(app, "prop.client:initech AND prop.project:launch").await, vec!["gemini"]);
+    }
+
+    #[tokio::test]
+    async fn it_uses_configured_keywords_and_note_types() {
+        let mut status = String::new();
+        let mut props = String::new();
+        let app = test_app_with(|db, config| {
+            fs::write(
+                Path::new(&config.notes_path).join("journal-2025-01-06.org"),
+                ":PROPERTIES:\n:ID: journal\n:END:\n#+TITLE: 2025-01-06\n\n* NEXT Plan the day\n",
+            )
+            .unwrap();
+            let index_config = IndexConfig {
+                keywords: TaskKeywords {
+                    todo: vec!["NEXT".to_string()],
+                    done: vec!["DONE".to_string()],
+                },
+                note_types: vec![NoteType {
+                    name: "journal".to_string(),
+                    file_prefixes: vec!["journal-".to_string()],
+                    tags: vec![],
+                }],
+                ..Default::default()
+            };
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+            status = db
+                .query_row("SELECT status FROM note_meta WHERE type = 'task'", [], |r| r.get(0))
+                .unwrap();
+            props = db
+                .query_row("SELECT props FROM note_meta WHERE id = 'journal'", [], |r| r.get(0))
+                .unwrap();
+        });
+
+        assert_eq!(status, "NEXT");
+        let props: Value = serde_json::from_str(&props).unwrap();
+        assert_eq!(props["type"], "journal");
+
+        let response = app
+            .oneshot(Request::builder().uri("/config/schema").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["todo_keywords"][0], "TODO");
+        assert_eq!(body["done_keywords"][0], "DONE");
+    }
+
+    #[tokio::test]
+    async fn it_reports_clocked_time() {
+        let app = test_app_with(|db, config| {
+            fs::write(
+                Path::new(&config.notes_path).join("work.org"),
+                r":PROPERTIES:
+:ID: work
+:END:
+#+TITLE: Work
+#+FILETAGS: acme
+
+* TODO Write docs :docs:
+:LOGBOOK:
+CLOCK: [2025-01-06 Mon 09:00]--[2025-01-06 Mon 10:30] =>  1:30
+CLOCK: [2025-01-07 Tue 09:00]--[2025-01-07 Tue 10:00] =>  1:00
+:END:
+* TODO Review
+:LOGBOOK:
+CLOCK: [2025-01-06 Mon 11:00]--[2025-01-06 Mon 11:15] =>  0:15
+:END:
+",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/time/report?from=2025-01-06&to=2025-01-06&group_by=tag")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(
+            body["results"],
+            serde_json::json!([
+                {"key": "acme", "minutes": 105},
+                {"key": "docs", "minutes": 90},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn it_infers_links_from_mentions() {
+        let app = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(
+                notes.join("rust.org"),
+                ":PROPERTIES:\n:ID: rust\n:ROAM_ALIASES: \"Rust lang\"\n:END:\n#+TITLE: Rust\n\nTrusty notes.\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("a.org"),
+                ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n\nWriting the indexer in Rust lang.\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("b.org"),
+                ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n\nSee [[id:rust][Rust]].\n",
+            )
+            .unwrap();
+            let index_config = IndexConfig {
+                infer_links: true,
+                ..Default::default()
+            };
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/rust/backlinks").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["links"].as_array().unwrap().len(), 2);
+        assert_eq!(body["links"][0]["id"], "a");
+        assert_eq!(body["links"][0]["type"], "inferred");
+        assert_eq!(body["links"][1]["id"], "b");
+        assert_eq!(body["links"][1]["type"], "explicit");
+    }
+
+    #[test]
+    fn it_matches_tags_exactly() {
+        let mut ids = Vec::new();
+        let _ = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(
+                notes.join("org.org"),
+                ":PROPERTIES:\n:ID: org\n:END:\n#+TITLE: Org\n#+FILETAGS: org emacs\n",
+            )
+            .unwrap();
+            fs::write(
+                notes.join("org-mode.org"),
+                ":PROPERTIES:\n:ID: org-mode\n:END:\n#+TITLE: Org mode\n#+FILETAGS: org-mode\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            ids = fulltext_search(&SearchIndex::open(&config.index_path), "tags:org", 10, &config.search_exclusions, &SearchFilters::default())
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect();
+        });
+
+        assert_eq!(ids, vec!["org"]);
+    }
+
+    #[test]
+    fn it_only_stores_the_title_and_tags_of_encrypted_notes() {
+        // Reads the plain text as if it was decrypted
+        struct Decrypted(Document);
+        impl SourceProvider for Decrypted {
+            fn documents(&self) -> Vec<Document> {
+                vec![self.0.clone()]
+            }
+            fn read(&self, doc: &Document) -> std::io::Result<String> {
+                fs::read_to_string(&doc.path)
+            }
+        }
+
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = env::temp_dir().join(format!("{}-{}", ts, rand::random::<u32>()));
+        fs::create_dir_all(dir.join("index")).unwrap();
+        fs::create_dir_all(dir.join("db")).unwrap();
+        let path = dir.join("secret.org.gpg");
+        fs::write(
+            &path,
+            r":PROPERTIES:
+:ID: secret
+:CLIENT: acme
+:END:
+#+TITLE: Secret plans
+#+FILETAGS: private
+
+Top secret
+* Meeting
+:LOGBOOK:
+CLOCK: [2025-01-06 Mon 09:00]--[2025-01-06 Mon 10:00] =>  1:00
+:END:
+* TODO Call the lawyer
+:PROPERTIES:
+:CLIENT: acme
+:END:
+",
+        )
+        .unwrap();
+
+        let mut db = vector_db(dir.join("db").to_str().unwrap()).unwrap();
+        migrate_db(&db).unwrap();
+        let index_path = dir.join("index").display().to_string();
+        let config = IndexConfig {
+            indexed_properties: vec!["client".to_string()],
+            ..Default::default()
+        };
+        let source = Decrypted(Document { path, encrypted: true });
+        index_all(&mut db, &mut index_writer(&index_path), &source, true, false, &config).unwrap();
+
+        let (title, tags, body, client, word_count, heading_count): (String, String, String, Option<String>, i64, i64) = db
+            .query_row(
+                "SELECT title, tags, body, json_extract(props, '$.client'), word_count, heading_count FROM note_meta WHERE id = 'secret'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?, r.get(5)?)),
+            )
+            .unwrap();
+        assert_eq!((title.as_str(), tags.as_str(), body.as_str()), ("Secret plans", "private", ""));
+        assert_eq!((client, word_count, heading_count), (None, 0, 0));
+        let count = |sql: &str| -> i64 { db.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM note_meta WHERE id != 'secret'"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM clock_entry"), 0);
+
+        let search_index = SearchIndex::open(&index_path);
+        let search = |query: &str| -> Vec<String> {
+            fulltext_search(&search_index, query, 10, &SearchExclusions::default(), &SearchFilters::default())
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect()
+        };
+        assert_eq!(search("title:secret"), vec!["secret"]);
+        assert!(search("prop.client:acme").is_empty());
+        assert!(search("lawyer").is_empty());
+    }
+
+    #[test]
+    fn it_matches_nested_tags_by_prefix() {
+        let mut full_text_ids = Vec::new();
+        let mut excluded_ids = Vec::new();
+        let mut sql_ids = Vec::new();
+        let mut filtered_ids = Vec::new();
+        let mut similar_filtered_ids = Vec::new();
+        let mut similar_excluded_ids = Vec::new();
+        let _ = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            let files = [
+                ("acme", "project/acme"),
+                ("beta", "project/beta/launch"),
+                ("health", "area/health"),
+                ("projects", "projects"),
+            ];
+            for (id, tags) in files {
+                fs::write(
+                    notes.join(format!("{}.org", id)),
+                    format!(":PROPERTIES:\n:ID: {}\n:END:\n#+TITLE: {}\n#+FILETAGS: {}\n\nA note.\n", id, id, tags),
+                )
+                .unwrap();
+            }
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            let search_index = SearchIndex::open(&config.index_path);
+            let ids = |query: &str, exclusions: &SearchExclusions| {
+                let mut ids: Vec<String> = fulltext_search(&search_index, query, 10, exclusions, &SearchFilters::default())
+                    .into_iter()
+                    .map(|hit| hit.id)
+                    .collect();
+                ids.sort();
+                ids
+            };
+            full_text_ids = ids("tags:project/*", &SearchExclusions::default());
+            let exclusions = SearchExclusions {
+                tags: vec!["project/*".to_string()],
+                types: vec![],
+                titles: vec![],
+            };
+            excluded_ids = ids("note", &exclusions);
+            let all_ids: Vec<String> = files.iter().map(|(id, _)| id.to_string()).collect();
+            sql_ids = exclusions.filter_ids(db, &all_ids).unwrap();
+
+            let filters = SearchFilters {
+                tags: vec!["project/*".to_string()],
+                ..Default::default()
+            };
+            filtered_ids = fulltext_search(&search_index, "note", 10, &SearchExclusions::default(), &filters)
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect();
+            filtered_ids.sort();
+
+            // Similarity search applies the same rules in SQL
+            for (i, id) in all_ids.iter().enumerate() {
+                let mut v = vec![0.0f32; 384];
+                v[0] = i as f32;
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, ?2)",
+                    [id.to_string(), serde_json::json!(v).to_string()],
+                )
+                .unwrap();
+            }
+            let query = vec![0.0f32; 384];
+            let similar = |exclusions: &SearchExclusions, filters: &SearchFilters| {
+                let mut ids: Vec<String> = search_similar_vector(db, &query, 10, None, None, exclusions, filters, None)
+                    .unwrap()
+                    .into_iter()
+                    .map(|hit| hit.id)
+                    .collect();
+                ids.sort();
+                ids
+            };
+            similar_filtered_ids = similar(&SearchExclusions::default(), &filters);
+            similar_excluded_ids = similar(&exclusions, &SearchFilters::default());
+        });
+
+        assert_eq!(full_text_ids, vec!["acme", "beta"]);
+        assert_eq!(excluded_ids, vec!["health", "projects"]);
+        assert_eq!(sql_ids, excluded_ids);
+        assert_eq!(filtered_ids, full_text_ids);
+        assert_eq!(similar_filtered_ids, full_text_ids);
+        assert_eq!(similar_excluded_ids, excluded_ids);
+    }
+
+    #[tokio::test]
+    async fn it_returns_the_tag_tree() {
+        let app = test_app_with(|db, _| {
+            db.execute(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, body, tags)
+                VALUES
+                  ('n1', 'note', 'a.org', 'A', '', 'project/acme,rust'),
+                  ('n2', 'note', 'b.org', 'B', '', 'project/acme,project/beta'),
+                  ('t1', 'task', 'b.org', 'Ship', '', 'project/beta'),
+                  ('h1', 'heading', 'b.org', 'Heading', '', 'project/beta')
+                ",
+                [],
+            )
+            .unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/tags/tree").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let tags = body["tags"].as_array().unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0]["tag"], "project");
+        assert_eq!(tags[0]["count"], 0);
+        assert_eq!(tags[0]["total"], 3);
+        assert_eq!(tags[0]["children"][0]["name"], "acme");
+        assert_eq!(tags[0]["children"][0]["count"], 2);
+        assert_eq!(tags[0]["children"][1]["name"], "beta");
+        assert_eq!(tags[0]["children"][1]["count"], 2);
+        assert_eq!(tags[1]["tag"], "rust");
+    }
+
+    #[test]
+    fn it_applies_the_same_exclusions_to_full_text_and_sql() {
+        let mut full_text_ids = Vec::new();
+        let mut sql_ids = Vec::new();
+        let _ = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            let files = [
+                ("a", "Journal 2025", "daily"),
+                ("b", "Secrets", "Private"),
+                ("c", "Reading list", "read-later"),
+                ("d", "Networking", "networking"),
+                ("e", "Other", "work private"),
+            ];
+            for (id, title, tags) in files {
+                fs::write(
+                    notes.join(format!("{}.org", id)),
+                    format!(":PROPERTIES:\n:ID: {}\n:END:\n#+TITLE: {}\n#+FILETAGS: {}\n\nA note.\n", id, title, tags),
+                )
+                .unwrap();
+            }
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            let exclusions = SearchExclusions {
+                tags: vec!["private".to_string(), "read_later".to_string(), "work".to_string()],
+                types: vec![],
+                titles: vec!["JOURNAL".to_string()],
+            };
+            full_text_ids = fulltext_search(&SearchIndex::open(&config.index_path), "note", 10, &exclusions, &SearchFilters::default())
+                .into_iter()
+                .map(|hit| hit.id)
+                .collect();
+            full_text_ids.sort();
+            let all_ids: Vec<String> = files.iter().map(|(id, _, _)| id.to_string()).collect();
+            sql_ids = exclusions.filter_ids(db, &all_ids).unwrap();
+        });
+
+        assert_eq!(full_text_ids, vec!["c", "d"]);
+        assert_eq!(sql_ids, full_text_ids);
+    }
+
+    #[tokio::test]
+    async fn it_explains_search_queries() {
+        let app = test_app();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/notes/search/explain?query=tags:org%20AND%20rust")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["query"], "tags:org AND rust");
+        let terms = body["terms"].as_array().unwrap();
+        assert!(terms.contains(&serde_json::json!({"field": "tags", "text": "org"})));
+        assert!(terms.contains(&serde_json::json!({"field": "title", "text": "rust"})));
+        assert!(terms.contains(&serde_json::json!({"field": "body", "text": "rust"})));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/notes/search/explain?query=missing:field")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_status_of_index_runs() {
+        let app = test_app_with(|_, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(notes.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n").unwrap();
+            fs::write(notes.join("b.org"), ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n").unwrap();
+        });
+
+        let status = |app: Router| async move {
+            let response = app
+                .oneshot(Request::builder().uri("/notes/index/status").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+            body
+        };
+
+        // Nothing has been indexed through the API yet
+        assert!(status(app.clone()).await["run"].is_null());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/notes/index")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"full": true, "embeddings": false, "pull": false}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let id = body["run"]["id"].clone();
+
+        let run = status(app).await["run"].clone();
+        assert_eq!(run["id"], id);
+        assert_eq!(run["trigger"], "api");
+        assert_eq!(run["status"], "completed");
+        assert_eq!(run["indexed"], 2);
+        assert_eq!(run["failed"], 0);
+        assert!(run["finished_at"].is_string());
+        assert!(run["duration_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn it_indexes_selected_paths_without_pulling() {
+        let app = test_app_with(|_, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(notes.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n").unwrap();
+            fs::write(notes.join("b.org"), ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n").unwrap();
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/notes/index")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"paths": ["a.org"], "embeddings": false, "pull": false}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/notes/bulk")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"ids": ["a", "b"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["id"], "a");
+    }
+
+    #[tokio::test]
+    async fn it_streams_indexing_progress() {
+        let app = test_app_with(|_, config| {
+            let notes = Path::new(&config.notes_path);
+            fs::write(notes.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n").unwrap();
+        });
+
+        // Subscribe before indexing starts
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/index/stream").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut stream = response.into_body().into_data_stream();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/notes/index")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"full": true, "embeddings": false, "pull": false}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut events: Vec<Value> = Vec::new();
+        let has_event = |events: &[Value], name: &str| events.iter().any(|e| e["event"] == name);
+        while !has_event(&events, "finished") || !has_event(&events, "heartbeat") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .expect("Timed out waiting for progress")
+                .unwrap()
+                .unwrap();
+            let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+            events.extend(chunk.lines().filter_map(|l| l.strip_prefix("data: ")).map(|d| serde_json::from_str::<Value>(d).unwrap()));
+        }
+
+        // Heartbeats are sent in between progress events
+        let (heartbeats, events): (Vec<Value>, Vec<Value>) = events.into_iter().partition(|e| e["event"] == "heartbeat");
+        for (i, heartbeat) in heartbeats.iter().enumerate() {
+            assert_eq!(heartbeat["seq"], i + 1);
+        }
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["event"], "started");
+        assert_eq!(events[0]["total"], 1);
+        assert_eq!(events[1]["event"], "indexed");
+        assert!(events[1]["path"].as_str().unwrap().ends_with("a.org"));
+        assert_eq!(events[2]["indexed"], 1);
+        assert_eq!(events[2]["failed"], 0);
+    }
+
+    #[tokio::test]
+    async fn it_quarantines_and_retries_notes_that_fail_to_index() {
+        let mut note_path = Path::new("").to_path_buf();
+        let app = test_app_with(|db, config| {
+            note_path = Path::new(&config.notes_path).join("bad.org");
+            // Invalid UTF-8 can't be read as a note
+            fs::write(&note_path, [0xff, 0xfe, 0x00]).unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            for _ in 0..4 {
+                index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            }
+        });
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/notes/index/errors").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        // Quarantined notes are skipped so the attempts stop increasing
+        assert_eq!(errors[0]["attempts"], 3);
+        assert_eq!(errors[0]["quarantined"], true);
+        let id = errors[0]["id"].as_i64().unwrap();
+
+        fs::write(&note_path, ":PROPERTIES:\n:ID: fixed\n:END:\n#+TITLE: Fixed\n").unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/notes/index/errors/{}/retry", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"embeddings": false}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/index/errors").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert!(body["errors"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_records_notes_without_an_id_instead_of_panicking() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            fs::write(notes_path.join("no-drawer.org"), "#+TITLE: No drawer\n").unwrap();
+            fs::write(notes_path.join("no-id.org"), ":PROPERTIES:\n:CUSTOM: x\n:END:\n#+TITLE: No id\n").unwrap();
+            fs::write(notes_path.join("good.org"), ":PROPERTIES:\n:ID: good\n:END:\n#+TITLE: Good\n").unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            // The rest of the notes are still indexed
+            let title: String = db.query_row("SELECT title FROM note_meta WHERE id = 'good'", [], |r| r.get(0)).unwrap();
+            assert_eq!(title, "Good");
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/index/errors").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        let error_for = |file_name: &str| {
+            errors
+                .iter()
+                .find(|e| e["file_name"].as_str().unwrap().ends_with(file_name))
+                .map(|e| e["error"].as_str().unwrap().to_string())
+                .unwrap()
+        };
+        assert!(error_for("no-drawer.org").contains("Missing property drawer"));
+        assert!(error_for("no-id.org").contains("Missing org-id"));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_first_note_with_a_duplicate_id() {
+        let mut notes_path = Path::new("").to_path_buf();
+        let app = test_app_with(|db, config| {
+            notes_path = Path::new(&config.notes_path).to_path_buf();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            fs::write(notes_path.join("b.org"), ":PROPERTIES:\n:ID: dup\n:END:\n#+TITLE: Note B\n").unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            // The file that sorts first keeps the ID even though it's
+            // indexed after the other one
+            fs::write(notes_path.join("a.org"), ":PROPERTIES:\n:ID: dup\n:END:\n#+TITLE: Note A\n").unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            let title: String = db.query_row("SELECT title FROM note_meta WHERE id = 'dup'", [], |r| r.get(0)).unwrap();
+            assert_eq!(title, "Note A");
+        });
+
+        let get = |uri: &str| {
+            let app = app.clone();
+            let uri = uri.to_string();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                body
+            }
+        };
+
+        let body = get("/notes/index/conflicts").await;
+        let conflicts = body["conflicts"].as_array().unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0]["id"], "dup");
+        assert!(conflicts[0]["file_name"].as_str().unwrap().ends_with("b.org"));
+        assert!(conflicts[0]["kept_file_name"].as_str().unwrap().ends_with("a.org"));
+
+        let body = get("/notes/index/errors").await;
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]["error"].as_str().unwrap().starts_with("Duplicate ID dup"));
+        let id = errors[0]["id"].as_i64().unwrap();
+
+        fs::write(notes_path.join("b.org"), ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n").unwrap();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/notes/index/errors/{}/retry", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"embeddings": false}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = get("/notes/index/conflicts").await;
+        assert!(body["conflicts"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_indexes_headings_with_an_id() {
+        let app = test_app_with(|db, config| {
+            let path = Path::new(&config.notes_path).join("garden.org");
+            let source = OrgDirectory::new(&config.notes_path, None);
+            fs::write(
+                &path,
+                ":PROPERTIES:\n:ID: garden\n:END:\n#+TITLE: Garden\n#+FILETAGS: home\n\n* Tomatoes :summer:\n:PROPERTIES:\n:ID: tomatoes\n:END:\nWater daily.\n* Peppers\n:PROPERTIES:\n:ID: peppers\n:END:\nLots of sun.\n",
+            )
+            .unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            // Headings that are removed from the note are removed from
+            // the index
+            fs::write(&path, ":PROPERTIES:\n:ID: garden\n:END:\n#+TITLE: Garden\n#+FILETAGS: home\n\n* Tomatoes :summer:\n:PROPERTIES:\n:ID: tomatoes\n:END:\nWater daily.\n* Peppers\nLots of sun.\n").unwrap();
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+        });
+
+        let search = |query: &str| {
+            let app = app.clone();
+            let uri = format!("/notes/search?query={}", query);
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+                body["results"].as_array().unwrap().clone()
+            }
+        };
+
+        let results = search("water%20AND%20type:heading").await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["id"], "tomatoes");
+        assert_eq!(results[0]["title"], "Tomatoes");
+        assert_eq!(results[0]["tags"], "home,summer");
+
+        let results = search("sun%20AND%20type:heading").await;
+        assert!(results.is_empty());
+    }
+
+    // A one page PDF with the text on it
+    fn pdf_with_text(text: &str) -> Vec<u8> {
+        let content = format!("BT /F1 12 Tf 72 720 Td ({}) Tj ET", text);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>".to_string(),
+            format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+        }
+        let xref = pdf.len();
+        pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            pdf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.push_str(&format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref));
+        pdf.into_bytes()
+    }
+
+    #[tokio::test]
+    async fn it_indexes_pdf_attachments() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            fs::create_dir_all(notes_path.join("attachments")).unwrap();
+            fs::write(notes_path.join("attachments/paper.pdf"), pdf_with_text("Photosynthesis in tomatoes")).unwrap();
+            fs::write(
+                notes_path.join("reading.org"),
+                ":PROPERTIES:\n:ID: reading\n:END:\n#+TITLE: Reading\n#+FILETAGS: papers\n\nRead [[file:attachments/paper.pdf][the paper]] and [[file:attachments/missing.pdf]].\n",
+            )
+            .unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            let index_config = IndexConfig { pdf_attachments: true, ..config.index_config.clone() };
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+
+            let note_id: String = db.query_row("SELECT note_meta_id FROM attachment", [], |r| r.get(0)).unwrap();
+            assert_eq!(note_id, "reading");
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=photosynthesis%20AND%20type:attachment").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["title"], "paper.pdf");
+        assert_eq!(results[0]["tags"], "papers");
+        assert!(results[0]["file_name"].as_str().unwrap().ends_with("reading.org"));
+    }
+
+    #[tokio::test]
+    async fn it_indexes_notes_in_batches() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            // More notes than are parsed in parallel at once
+            for i in 0..150 {
+                fs::write(notes_path.join(format!("note-{:03}.org", i)), format!(":PROPERTIES:\n:ID: note-{:03}\n:END:\n#+TITLE: Note {}\n", i, i)).unwrap();
+            }
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            let count: i64 = db.query_row("SELECT COUNT(*) FROM note_meta WHERE id LIKE 'note-%'", [], |r| r.get(0)).unwrap();
+            assert_eq!(count, 150);
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=note-149").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["results"][0]["title"], "Note 149");
+    }
+
+    #[test]
+    fn it_skips_notes_that_havent_changed() {
+        let _ = test_app_with(|db, config| {
+            // Events for each note, leaving out when indexing started
+            // and finished
+            let index = |db: &mut Connection, index_config: &IndexConfig| {
+                let source = OrgDirectory::new(&config.notes_path, None);
+                let events = RefCell::new(Vec::new());
+                let progress = |event: IndexEvent| {
+                    if let IndexEvent::Indexed { .. } | IndexEvent::Unchanged { .. } = event {
+                        events.borrow_mut().push(serde_json::to_value(&event).unwrap()["event"].as_str().unwrap().to_string());
+                    }
+                };
+                index_all_with_progress(db, &mut index_writer(&config.index_path), &source, true, false, index_config, &progress).unwrap();
+                events.into_inner()
+            };
+            let path = Path::new(&config.notes_path).join("garden.org");
+            let content = ":PROPERTIES:\n:ID: garden\n:END:\n#+TITLE: Garden\n";
+            fs::write(&path, content).unwrap();
+            assert_eq!(index(db, &config.index_config), vec!["indexed"]);
+            assert_eq!(index(db, &config.index_config), vec!["unchanged"]);
+            // The stored hash is the same on every platform and release
+            let hash: String = db.query_row("SELECT content_hash FROM note_meta WHERE id = 'garden'", [], |r| r.get(0)).unwrap();
+            assert_eq!(hash, sha256_hex(content.as_bytes()));
+
+            // Saving the file without changing it is still unchanged
+            fs::write(&path, content).unwrap();
+            assert_eq!(index(db, &config.index_config), vec!["unchanged"]);
+
+            let forced = IndexConfig { force: true, ..config.index_config.clone() };
+            assert_eq!(index(db, &forced), vec!["indexed"]);
+
+            fs::write(&path, ":PROPERTIES:\n:ID: garden\n:END:\n#+TITLE: Vegetable garden\n").unwrap();
+            assert_eq!(index(db, &config.index_config), vec!["indexed"]);
+            let title: String = db.query_row("SELECT title FROM note_meta WHERE id = 'garden'", [], |r| r.get(0)).unwrap();
+            assert_eq!(title, "Vegetable garden");
+        });
+    }
+
+    #[tokio::test]
+    async fn it_removes_deleted_notes() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            let deleted = notes_path.join("gardening.org");
+            // Org-ids are usually UUIDs which have to be removed from
+            // the full text index as a whole
+            fs::write(
+                &deleted,
+                ":PROPERTIES:\n:ID: 5f0c6a3e-8d2b-4b1e-9a7c-2e4d6f8a1b3c\n:END:\n#+TITLE: Gardening tomatoes\n\n* TODO Water the tomatoes\n:PROPERTIES:\n:ID: 9b1d2c4e-6f8a-4c3b-8e5d-7a9b0c1d2e3f\n:END:\n",
+            )
+            .unwrap();
+            fs::write(notes_path.join("cooking.org"), ":PROPERTIES:\n:ID: cooking\n:END:\n#+TITLE: Cooking tomatoes\n").unwrap();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            fs::remove_file(&deleted).unwrap();
+            // Only the changed files are given so the deleted note is
+            // found by checking everything that was indexed
+            let source = OrgDirectory::new(&config.notes_path, None).with_paths(vec![]);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+
+            let count: i64 = db
+                .query_row("SELECT COUNT(*) FROM note_meta WHERE file_name = ?1", [deleted.to_str().unwrap()], |r| r.get(0))
+                .unwrap();
+            assert_eq!(count, 0);
+
+            // The deleted note and task aren't left in the full text
+            // index either
+            let hits = fulltext_search(&SearchIndex::open(&config.index_path), "tomatoes", 10, &SearchExclusions::default(), &SearchFilters::default());
+            let ids: Vec<String> = hits.into_iter().map(|h| h.id).collect();
+            assert_eq!(ids, vec!["cooking"]);
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=tomatoes").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let ids: Vec<&str> = body["results"].as_array().unwrap().iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["cooking"]);
+    }
+
+    #[tokio::test]
+    async fn it_searches_within_a_collection() {
+        let app = test_app_with(|db, config| {
+            let work_path = Path::new(&config.notes_path).parent().unwrap().join("work");
+            fs::create_dir_all(&work_path).unwrap();
+            fs::write(
+                Path::new(&config.notes_path).join("personal.org"),
+                ":PROPERTIES:\n:ID: personal\n:END:\n#+TITLE: Planning\n",
+            )
+            .unwrap();
+            fs::write(
+                work_path.join("work.org"),
+                ":PROPERTIES:\n:ID: work\n:END:\n#+TITLE: Planning\n",
+            )
+            .unwrap();
+            let index_config = IndexConfig {
+                collections: vec![Collection {
+                    name: "work".to_string(),
+                    path: work_path.display().to_string(),
+                    remote: None,
+                }],
+                ..Default::default()
+            };
+            for path in [config.notes_path.clone(), work_path.display().to_string()] {
+                let source = OrgDirectory::new(&path, None);
+                index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+            }
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/notes/search?query=planning%20AND%20collection:work")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["id"], "work");
+        assert_eq!(results[0]["collection"], "work");
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=planning").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["results"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_updates_and_refiles_tasks_in_a_collection() {
+        let mut work_path = Path::new("").to_path_buf();
+        let app = test_app_configured(
+            |config| {
+                work_path = Path::new(&config.notes_path).parent().unwrap().join("work");
+                config.index_config.collections = vec![Collection {
+                    name: "work".to_string(),
+                    path: work_path.display().to_string(),
+                    remote: None,
+                }];
+            },
+            |db, config| {
+                let work_path = Path::new(&config.index_config.collections[0].path);
+                fs::create_dir_all(work_path).unwrap();
+                fs::write(
+                    work_path.join("tasks.org"),
+                    ":PROPERTIES:\n:ID: work-tasks\n:END:\n#+TITLE: Work tasks\n\n* TODO Ship it\n:PROPERTIES:\n:ID: ship-it\n:END:\n",
+                )
+                .unwrap();
+                fs::write(work_path.join("projects.org"), ":PROPERTIES:\n:ID: work-projects\n:END:\n#+TITLE: Projects\n").unwrap();
+                let source = OrgDirectory::new(work_path.to_str().unwrap(), None);
+                index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            },
+        );
+
+        let post = |uri: &str, body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(post("/tasks/ship-it/status", r#"{"status": "done"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The task is re-indexed from the collection's directory
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/tasks/board").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        assert_eq!(body["columns"][2]["tasks"][0]["id"], "ship-it");
+
+        // File names are relative to the collection the task is in
+        let response = app.oneshot(post("/notes/ship-it/refile", r#"{"file_name": "projects.org"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(fs::read_to_string(work_path.join("projects.org")).unwrap().contains("* DONE Ship it"));
+        assert!(!fs::read_to_string(work_path.join("tasks.org")).unwrap().contains("Ship it"));
+    }
+
+    #[tokio::test]
+    async fn it_indexes_markdown_notes() {
+        let app = test_app_with(|db, config| {
+            let notes_path = Path::new(&config.notes_path);
+            fs::write(notes_path.join("org.org"), ":PROPERTIES:\n:ID: org\n:END:\n#+TITLE: Gardening\n").unwrap();
+            fs::write(
+                notes_path.join("vault.md"),
+                "---\nid: markdown-note\ntitle: Gardening in markdown\ntags:\n  - garden\n  - outdoors\n---\n# Tomatoes\nWater them daily.\n",
+            )
+            .unwrap();
+            let mut index_config = IndexConfig::default();
+            let source = OrgDirectory::new(&config.notes_path, None);
+            // Markdown notes are only included when enabled
+            assert_eq!(source.documents().len(), 1);
+
+            index_config.walk = NoteWalk { markdown: true, ..Default::default() };
+            let source = source.with_walk(index_config.walk.clone());
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &index_config).unwrap();
+        });
+
+        let response = app
+            .oneshot(Request::builder().uri("/notes/search?query=tomatoes").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&body_to_string(response.into_body()).await).unwrap();
+        let results = body["results"].as_array().unwrap();
+        let note = results.iter().find(|r| r["type"] == "note").unwrap();
+        assert_eq!(note["id"], "markdown-note");
+        assert_eq!(note["title"], "Gardening in markdown");
+        assert_eq!(note["tags"], "garden,outdoors");
+    }
+
+    #[tokio::test]
+    async fn it_filters_similarity_search_before_finding_neighbors() {
+        let mut hits = Vec::new();
+        let mut unfiltered = Vec::new();
+        let _ = test_app_with(|db, config| {
+            db.execute_batch(
+                r"
+                INSERT INTO note_meta(id, type, file_name, title, tags, body)
+                VALUES
+                  ('near', 'note', 'near.org', 'Near', NULL, ''),
+                  ('rust', 'note', 'rust.org', 'Rust', 'rust', ''),
+                  ('far', 'note', 'far.org', 'Far', 'rust', '');
+                ",
+            )
+            .unwrap();
+            let vector = |x: f32| -> String {
+                let mut v = vec![0.0f32; 384];
+                v[0] = x;
+                serde_json::json!(v).to_string()
+            };
+            for (id, x) in [("near", 1.0), ("rust", 2.0), ("far", 5.0)] {
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, ?2)",
+                    [id.to_string(), vector(x)],
+                )
+                .unwrap();
+            }
+
+            let mut query = vec![0.0f32; 384];
+            query[0] = 1.0;
+            let filters = SearchFilters {
+                tags: vec!["rust".to_string()],
+                ..Default::default()
+            };
+            hits = search_similar_vector(db, &query, 10, Some(1), None, &config.search_exclusions, &filters, None)
+                .unwrap()
+                .into_iter()
+                .map(|h| h.id)
+                .collect();
+            unfiltered = search_similar_vector(db, &query, 10, Some(1), None, &config.search_exclusions, &SearchFilters::default(), None)
+                .unwrap()
+                .into_iter()
+                .map(|h| h.id)
+                .collect();
+        });
+
+        // The nearest note overall doesn't have the tag but the
+        // nearest one that does is still found
+        assert_eq!(hits, vec!["rust"]);
+        assert_eq!(unfiltered, vec!["near"]);
+    }
+
+    #[tokio::test]
+    async fn it_finds_approximate_nearest_neighbors() {
+        let mut exact = Vec::new();
+        let mut approximate = Vec::new();
+        let _ = test_app_with(|db, config| {
+            for i in 0..50 {
+                let id = format!("n{}", i);
+                db.execute(
+                    "INSERT INTO note_meta(id, type, file_name, title, body) VALUES (?1, 'note', ?1, ?1, '')",
+                    [&id],
+                )
+                .unwrap();
+                let mut v = vec![0.0f32; 384];
+                v[0] = i as f32;
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, ?2)",
+                    [id, serde_json::json!(v).to_string()],
+                )
+                .unwrap();
+            }
+
+            let mut query = vec![0.0f32; 384];
+            query[0] = 20.2;
+            let ids = |hits: Vec<indexer::search::SearchHit>| -> Vec<String> { hits.into_iter().map(|h| h.id).collect() };
+            exact = ids(search_similar_vector(db, &query, 3, None, None, &config.search_exclusions, &SearchFilters::default(), None).unwrap());
+            // Searching every cluster finds the same neighbors as the
+            // exact search
+            let ann = AnnIndex::build(load_vectors(db).unwrap(), 50);
+            assert_eq!(ann.len(), 50);
+            approximate = ids(search_similar_vector(db, &query, 3, None, None, &config.search_exclusions, &SearchFilters::default(), Some(&ann)).unwrap());
+        });
+
+        assert_eq!(exact, vec!["n20", "n21", "n19"]);
+        assert_eq!(approximate, exact);
+    }
+
+    #[tokio::test]
+    async fn it_returns_notes_similar_to_an_embedding() {
+        let mut results = Vec::new();
+        let _ = test_app_with(|db, config| {
+            for (id, x) in [("far", 5.0f32), ("near", 1.5)] {
+                db.execute(
+                    "INSERT INTO note_meta(id, type, file_name, title, body) VALUES (?1, 'note', ?1, ?1, '')",
+                    [id],
+                )
+                .unwrap();
+                let mut v = vec![0.0f32; 384];
+                v[0] = x;
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, ?2)",
+                    [id.to_string(), serde_json::json!(v).to_string()],
+                )
+                .unwrap();
+            }
+            // Another chunk of the far note that is closer than its
+            // first one
+            let mut v = vec![0.0f32; 384];
+            v[0] = 4.0;
+            db.execute(
+                "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES ('far:2', 'far', ?1)",
+                [serde_json::json!(v).to_string()],
+            )
+            .unwrap();
+
+            let mut query = vec![0.0f32; 384];
+            query[0] = 1.0;
+            let search_index = SearchIndex::open(&config.index_path);
+            results = similar_notes(&search_index, db, &query, 10, None, None, &config.search_exclusions, &SearchFilters::default())
+                .unwrap()
+                .into_iter()
+                .map(|r| serde_json::to_value(r).unwrap())
+                .collect();
+        });
+
+        // Nearest first with the distance to each note
+        let ids: Vec<&str> = results.iter().map(|r| r["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, vec!["near", "far"]);
+        assert!((results[0]["distance"].as_f64().unwrap() - 0.5).abs() < 0.001);
+        assert!((results[1]["distance"].as_f64().unwrap() - 3.0).abs() < 0.001);
+    }
+
+    #[tokio::test]
+    async fn it_includes_the_distance_of_similar_search_results() {
+        let mut results = Vec::new();
+        let _ = test_app_with(|db, config| {
+            let notes = Path::new(&config.notes_path);
+            for (id, title) in [("alpha", "Rust ownership"), ("beta", "Memory safety"), ("gamma", "Gardening"), ("delta", "Rust macros")] {
+                fs::write(
+                    notes.join(format!("{}.org", id)),
+                    format!(":PROPERTIES:\n:ID: {}\n:END:\n#+TITLE: {}\n", id, title),
+                )
+                .unwrap();
+            }
+            let source = OrgDirectory::new(&config.notes_path, None);
+            index_all(db, &mut index_writer(&config.index_path), &source, true, false, &config.index_config).unwrap();
+            for (id, x) in [("alpha", 1.0f32), ("beta", 1.5), ("gamma", 5.0)] {
+                let mut v = vec![0.0f32; 384];
+                v[0] = x;
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, ?2)",
+                    [id.to_string(), serde_json::json!(v).to_string()],
+                )
+                .unwrap();
+            }
+
+            let mut query = vec![0.0f32; 384];
+            query[0] = 1.0;
+            let search_index = SearchIndex::open(&config.index_path);
+            results = search_notes_with_vector(
+                &search_index,
+                db,
+                "rust",
+                Some(&query),
+                10,
+                None,
+                Some(1.0),
+                &config.search_exclusions,
+                &SearchFilters::default(),
+            )
+            .into_iter()
+            .map(|r| serde_json::to_value(r).unwrap())
+            .collect();
+        });
+
+        let distances: BTreeMap<&str, Option<f64>> = results
+            .iter()
+            .map(|r| (r["id"].as_str().unwrap(), r["distance"].as_f64()))
+            .collect();
+        // Gamma is further than the threshold and delta only matches
+        // the full text search
+        assert_eq!(distances.keys().copied().collect::<Vec<_>>(), vec!["alpha", "beta", "delta"]);
+        // Alpha is found by both searches and keeps its distance
+        assert!(distances["alpha"].unwrap().abs() < 0.001);
+        assert!((distances["beta"].unwrap() - 0.5).abs() < 0.001);
+        assert_eq!(distances["delta"], None);
+    }
+
+    #[tokio::test]
+    async fn it_reports_when_migrations_clear_embeddings() {
+        let _ = test_app_with(|db, _| {
+            // New databases already have the current tables
+            assert!(!migrate_db(db).unwrap());
+
+            // Embeddings from before each chunk was stored are removed
+            db.execute_batch(
+                r"
+                DROP TABLE vec_items;
+                CREATE VIRTUAL TABLE vec_items USING vec0(embedding float[384]);
+                ",
+            )
+            .unwrap();
+            assert!(migrate_db(db).unwrap());
+            assert!(!migrate_db(db).unwrap());
+        });
+    }
+
+    #[tokio::test]
+    async fn it_searches_quantized_embeddings() {
+        let mut hits = Vec::new();
+        let _ = test_app_with(|db, config| {
+            // Binary embeddings are searched by hamming distance and
+            // rescored with the int8 copy
+            assert!(set_vector_format(db, VectorFormat::Binary).unwrap());
+            assert!(!set_vector_format(db, VectorFormat::Binary).unwrap());
+            let vector = |x: f32, y: f32| -> String {
+                let mut v = vec![0.0f32; 384];
+                v[0] = x;
+                v[1] = y;
+                serde_json::json!(v).to_string()
+            };
+            for (id, x, y) in [("near", 0.99, 0.14), ("mid", 0.71, 0.71), ("far", -1.0, 0.0)] {
+                db.execute(
+                    "INSERT INTO note_meta(id, type, file_name, title, body) VALUES (?1, 'note', ?1, ?1, '')",
+                    [id],
+                )
+                .unwrap();
+                db.execute(
+                    "INSERT INTO vec_items(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, vec_quantize_binary(?2))",
+                    [id.to_string(), vector(x, y)],
+                )
+                .unwrap();
+                db.execute(
+                    "INSERT INTO vec_items_int8(chunk_id, note_meta_id, embedding) VALUES (?1, ?1, vec_quantize_int8(?2, 'unit'))",
+                    [id.to_string(), vector(x, y)],
+                )
+                .unwrap();
+            }
+
+            let mut query = vec![0.0f32; 384];
+            query[0] = 1.0;
+            hits = search_similar_vector(db, &query, 3, None, None, &config.search_exclusions, &SearchFilters::default(), None)
+                .unwrap()
+                .into_iter()
+                .map(|h| (h.id, h.score))
+                .collect();
+        });
+
+        let ids: Vec<&str> = hits.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["near", "mid", "far"]);
+        // Distances are close to the distances between the floats
+        assert!((hits[0].1 - 0.14).abs() < 0.05);
+        assert!((hits[2].1 - 2.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn it_finds_notes_in_sub_directories() {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let notes_path = env::temp_dir().join(format!("{}-nested", ts));
+        for dir in ["projects/deep", "archive", ".git"] {
+            fs::create_dir_all(notes_path.join(dir)).unwrap();
+        }
+        for file in ["top.org", "projects/p.org", "projects/p.draft.org", "projects/deep/d.org", "archive/a.org", ".git/g.org"] {
+            fs::write(notes_path.join(file), "#+TITLE: Note\n").unwrap();
+        }
+        let notes = notes_path.to_str().unwrap();
+        let found = |source: OrgDirectory| {
+            let mut found: Vec<String> = source
+                .documents()
+                .iter()
+                .map(|d| d.path.strip_prefix(&notes_path).unwrap().display().to_string())
+                .collect();
+            found.sort();
+            found
+        };
+
+        // Hidden directories are always skipped
+        assert_eq!(
+            found(OrgDirectory::new(notes, None)),
+            vec!["archive/a.org", "projects/deep/d.org", "projects/p.draft.org", "projects/p.org", "top.org"]
+        );
+
+        let walk = NoteWalk {
+            max_depth: Some(1),
+            ignore: vec!["archive".to_string(), "*.draft.org".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(found(OrgDirectory::new(notes, None).with_walk(walk.clone())), vec!["projects/p.org", "top.org"]);
+
+        // Changed files are filtered with the same rules
+        let source = OrgDirectory::new(notes, None)
+            .with_walk(walk)
+            .with_paths(vec![notes_path.join("projects/p.org"), notes_path.join("archive/a.org")]);
+        assert_eq!(found(source), vec!["projects/p.org"]);
+    }
+
+    #[tokio::test]
+    async fn it_handles_spaces_and_unicode_in_paths() {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = env::temp_dir().join(format!("{} my nötes ✓", ts));
+        let notes_path = dir.join("org notes");
+        let index_path = dir.join("search index");
+        fs::create_dir_all(&notes_path).unwrap();
+        fs::create_dir_all(&index_path).unwrap();
+        let notes = notes_path.to_str().unwrap();
+
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git").current_dir(&notes_path).args(args).output().unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&["init", "-q"]);
+        git(&["config", "user.name", "Test"]);
+        git(&["config", "user.email", "test@example.com"]);
+        fs::write(notes_path.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n").unwrap();
+        git(&["add", "-A"]);
+        git(&["commit", "-qm", "First"]);
+        fs::write(notes_path.join("café notes.org"), ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n").unwrap();
+        // Pushing fails without a remote but the commit is still made
+        let auth = GitAuth {
+            deploy_key_path: Some("/no such/key".to_string()),
+            ..Default::default()
+        };
+        assert!(commit_and_push_repo(&auth, notes, "Don't lose 'quotes'").is_err());
+        assert_eq!(git(&["log", "-1", "--format=%s"]), "Don't lose 'quotes'");
+
+        // Only the note in the last commit changed
+        let changes = GitChanges::new(OrgDirectory::new(notes, None), &auth);
+        let paths: Vec<_> = changes.documents().into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, vec![notes_path.join("café notes.org")]);
+
+        let mut db = vector_db(dir.to_str().unwrap()).unwrap();
+        migrate_db(&db).unwrap();
+        let source = OrgDirectory::new(notes, None);
+        index_all(&mut db, &mut index_writer(index_path.to_str().unwrap()), &source, true, false, &IndexConfig::default()).unwrap();
+        let count: i64 = db.query_row("SELECT COUNT(*) FROM note_meta", [], |r| r.get(0)).unwrap();
+        assert_eq!(count, 2);
+        assert!(dir.join("vector.db").is_file());
+    }
+
+    #[test]
+    fn it_rebases_onto_origin_before_pushing() {
+        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_nanos();
+        let dir = env::temp_dir().join(format!("{}_push", ts));
+        fs::create_dir_all(&dir).unwrap();
+
+        let git = |cwd: &Path, args: &[&str]| {
+            let output = std::process::Command::new("git").current_dir(cwd).args(args).output().unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&dir, &["init", "-q", "--bare", "-b", "main", "origin.git"]);
+        for name in ["server", "laptop"] {
+            git(&dir, &["clone", "-q", "origin.git", name]);
+            let clone = dir.join(name);
+            git(&clone, &["config", "user.name", "Test"]);
+            git(&clone, &["config", "user.email", "test@example.com"]);
+            git(&clone, &["checkout", "-q", "-b", "main"]);
+        }
+        let server = dir.join("server");
+        let laptop = dir.join("laptop");
+        fs::write(server.join("a.org"), ":PROPERTIES:\n:ID: a\n:END:\n#+TITLE: Note A\n").unwrap();
+        git(&server, &["add", "-A"]);
+        git(&server, &["commit", "-qm", "First"]);
+        git(&server, &["push", "-q", "origin", "HEAD:main"]);
+
+        // The laptop pushes a note the server hasn't pulled yet
+        git(&laptop, &["pull", "-q", "origin", "main"]);
+        fs::write(laptop.join("b.org"), ":PROPERTIES:\n:ID: b\n:END:\n#+TITLE: Note B\n").unwrap();
+        git(&laptop, &["add", "-A"]);
+        git(&laptop, &["commit", "-qm", "From laptop"]);
+        git(&laptop, &["push", "-q", "origin", "HEAD:main"]);
+
+        fs::write(server.join("c.org"), ":PROPERTIES:\n:ID: c\n:END:\n#+TITLE: Note C\n").unwrap();
+        commit_and_push_repo(&GitAuth::default(), server.to_str().unwrap(), "From server").unwrap();
+
+        let log = git(&dir.join("origin.git"), &["log", "--format=%s", "main"]);
+        assert_eq!(log, "From server\nFrom laptop\nFirst");
+    }
+
+    #[test]
+    fn it_answers_git_credential_requests_with_a_token() {
+        use std::io::Write;
+        use std::process::Stdio;
+
+        let auth = GitAuth {
+            token: Some("secret-token".to_string()),
+            ..Default::default()
+        };
+        let mut child = git_command(&auth, &env::temp_dir())
+            .args(["credential", "fill"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"protocol=https\nhost=github.com\n\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+
+        assert!(output.status.success());
+        assert!(stdout.contains("username=x-access-token\n"));
+        assert!(stdout.contains("password=secret-token\n"));
+    }
 }
@@ -5,8 +5,7 @@
 
   const handleSearch = async (includeSimilarity, viewSelected, val) => {
     try {
-      // Auto hide results from journal entries
-      const query = `-title:journal ${encodeURIComponent(val.trim())}`
+      const query = encodeURIComponent(val.trim());
       const headers = new Headers();
       headers.append("Content-Type", "application/json");
 